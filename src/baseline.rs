@@ -0,0 +1,210 @@
+use crate::report::{CommandReport, Report};
+use crate::stats;
+use std::path::{Path, PathBuf};
+
+/// p-value thresholds for the significance markers, most significant first.
+const SIGNIFICANCE_LEVELS: [(f64, &str); 3] = [(0.001, "***"), (0.01, "**"), (0.05, "*")];
+
+/// Named result sets stored under the results directory, one JSON report each.
+pub struct BaselineStore {
+    dir: PathBuf,
+}
+
+impl BaselineStore {
+    pub fn new(results_dir: &Path) -> Self {
+        Self {
+            dir: results_dir.join("baselines"),
+        }
+    }
+
+    pub fn save(&self, name: &str, report: &Report) -> Result<PathBuf, Box<dyn std::error::Error>> {
+        let path = self.path(name)?;
+        std::fs::create_dir_all(&self.dir)?;
+        std::fs::write(&path, report.to_json()?)?;
+        Ok(path)
+    }
+
+    pub fn load(&self, name: &str) -> Result<Report, Box<dyn std::error::Error>> {
+        let path = self.path(name)?;
+        let json = std::fs::read_to_string(&path).map_err(|e| {
+            format!(
+                "Failed to read baseline '{}' at {}: {}",
+                name,
+                path.display(),
+                e
+            )
+        })?;
+        Ok(serde_json::from_str(&json)?)
+    }
+
+    fn path(&self, name: &str) -> Result<PathBuf, Box<dyn std::error::Error>> {
+        let valid = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+            && !name.starts_with('.');
+        if !valid {
+            return Err(format!(
+                "Invalid baseline name '{}': use letters, digits, '-', '_' or '.'",
+                name
+            )
+            .into());
+        }
+        Ok(self.dir.join(format!("{}.json", name)))
+    }
+}
+
+/// One metric of a command measured against the same metric in a baseline.
+#[derive(Debug)]
+pub struct Comparison {
+    pub metric: &'static str,
+    pub unit: &'static str,
+    pub baseline: f64,
+    pub current: f64,
+    /// Welch's t-test over the per-iteration values, when both sides have enough runs.
+    pub p_value: Option<f64>,
+}
+
+impl Comparison {
+    /// Relative change from the baseline, e.g. 0.05 for 5% higher.
+    pub fn change(&self) -> f64 {
+        if self.baseline == 0.0 {
+            return 0.0;
+        }
+        (self.current - self.baseline) / self.baseline
+    }
+
+    pub fn marker(&self) -> &'static str {
+        let Some(p_value) = self.p_value else {
+            return "";
+        };
+        SIGNIFICANCE_LEVELS
+            .iter()
+            .find(|(level, _)| p_value < *level)
+            .map(|(_, marker)| *marker)
+            .unwrap_or("~")
+    }
+
+    pub fn significant(&self) -> bool {
+        self.p_value.is_some_and(|p| p < 0.05)
+    }
+}
+
+/// Compares the per-iteration metrics of a command against its baseline run.
+pub fn compare(baseline: &CommandReport, current: &CommandReport) -> Vec<Comparison> {
+    type Metric = fn(&crate::report::IterationReport) -> f64;
+    let metrics: [(&'static str, &'static str, Metric); 4] = [
+        ("Wall time", "s", |i| i.wall_time),
+        ("User time", "ticks", |i| i.user_ticks as f64),
+        ("System time", "ticks", |i| i.system_ticks as f64),
+        ("Peak RSS", "MB", |i| i.peak_rss_kb as f64 / 1024.0),
+    ];
+
+    metrics
+        .iter()
+        .map(|(metric, unit, value)| {
+            let before: Vec<f64> = baseline.iterations.iter().map(value).collect();
+            let after: Vec<f64> = current.iterations.iter().map(value).collect();
+            Comparison {
+                metric,
+                unit,
+                baseline: stats::mean(&before),
+                current: stats::mean(&after),
+                p_value: stats::welch_t_test(&before, &after),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::benchmark::Benchmark;
+    use crate::process::ProcessInfo;
+    use crate::system::SystemInfo;
+    use std::time::Duration;
+
+    fn create_report(command: &str, wall_times_ms: &[u64]) -> Report {
+        let processes: Vec<ProcessInfo> = wall_times_ms
+            .iter()
+            .map(|&ms| {
+                let mut process = ProcessInfo::new(String::from("ls"), vec![]);
+                process.duration = Some(Duration::from_millis(ms));
+                process
+            })
+            .collect();
+        let mut report = Report::new(SystemInfo::collect());
+        let stat = Benchmark::calculate(&processes[0]);
+        report.push(command.to_string(), command.to_string(), &processes, stat);
+        report
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("lens-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let dir = temp_dir("baseline");
+        let store = BaselineStore::new(&dir);
+        let report = create_report("ls", &[100, 110, 120]);
+
+        let path = store.save("main", &report).unwrap();
+        assert_eq!(path, dir.join("baselines").join("main.json"));
+
+        let loaded = store.load("main").unwrap();
+        assert_eq!(loaded.commands[0].command, "ls");
+        assert_eq!(loaded.commands[0].iterations.len(), 3);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_load_missing_baseline() {
+        let store = BaselineStore::new(&temp_dir("missing"));
+        let error = store.load("nope").unwrap_err();
+        assert!(error.to_string().contains("baseline 'nope'"));
+    }
+
+    #[test]
+    fn test_invalid_names() {
+        let store = BaselineStore::new(Path::new(".lens"));
+        assert!(store.path("../etc").is_err());
+        assert!(store.path("a/b").is_err());
+        assert!(store.path("").is_err());
+        assert!(store.path(".hidden").is_err());
+        assert!(store.path("feature-1.2_x").is_ok());
+    }
+
+    #[test]
+    fn test_compare_detects_regression() {
+        let before = create_report("ls", &[100, 101, 99, 100, 100]);
+        let after = create_report("ls", &[120, 121, 119, 120, 120]);
+        let comparisons = compare(&before.commands[0], &after.commands[0]);
+
+        let wall = &comparisons[0];
+        assert_eq!(wall.metric, "Wall time");
+        assert!((wall.change() - 0.2).abs() < 1e-9);
+        assert!(wall.significant());
+        assert_eq!(wall.marker(), "***");
+    }
+
+    #[test]
+    fn test_compare_noise_is_not_significant() {
+        let before = create_report("ls", &[100, 110, 90, 105, 95]);
+        let after = create_report("ls", &[101, 109, 91, 104, 96]);
+        let comparisons = compare(&before.commands[0], &after.commands[0]);
+        assert!(!comparisons[0].significant());
+        assert_eq!(comparisons[0].marker(), "~");
+    }
+
+    #[test]
+    fn test_marker_without_enough_runs() {
+        let before = create_report("ls", &[100]);
+        let after = create_report("ls", &[200]);
+        let comparisons = compare(&before.commands[0], &after.commands[0]);
+        assert_eq!(comparisons[0].p_value, None);
+        assert_eq!(comparisons[0].marker(), "");
+    }
+}
@@ -1,26 +1,70 @@
-use crate::process::ProcessInfo;
-pub(crate) struct Benchmark;
-
-#[derive(Debug)]
-pub(crate) struct BenchmarkStat {
-    pub(crate) min_ttime: f64,
-    pub(crate) max_ttime: f64,
-    pub(crate) average_ttime: f64,
-    pub(crate) average_duration: f64,
-    pub(crate) max_duration: f64,
-    pub(crate) min_duration: f64,
-    pub(crate) min_stime: f64,
-    pub(crate) max_stime: f64,
-    pub(crate) average_stime: f64,
-    pub(crate) min_utime: f64,
-    pub(crate) max_utime: f64,
-    pub(crate) average_utime: f64,
-    pub(crate) min_memory: f64,
-    pub(crate) max_memory: f64,
-    pub(crate) average_memory: f64,
-    pub(crate) utime_percentage: f64,
-    pub(crate) stime_percentage: f64,
-    pub(crate) ttime_percentage: f64,
+use crate::cache::CacheMode;
+use crate::executor;
+use crate::interrupt::Interrupt;
+use crate::limits::Limits;
+use crate::monitor::MonitorKind;
+use crate::output::OutputMode;
+use crate::plan::BenchmarkSpec;
+use crate::process::{ProcessInfo, Sample};
+use crate::report::{CommandReport, Report};
+use crate::scheduling::Scheduling;
+use crate::stats;
+use crate::system::SystemInfo;
+use crate::threads::{ThreadStat, WaitStat};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Benchmarks a single command and reports on it.
+///
+/// ```no_run
+/// # async fn example() -> Result<(), lens::executor::Error> {
+/// use lens::{Benchmark, MonitorKind};
+///
+/// let report = Benchmark::command("gzip -k -f data.txt")
+///     .warmup(3)
+///     .iterations(10)
+///     .monitor(MonitorKind::Cgroup)
+///     .run()
+///     .await?;
+/// println!("{:.3} s", report.commands[0].summary.average_duration);
+/// # Ok(())
+/// # }
+/// ```
+pub struct Benchmark {
+    spec: BenchmarkSpec,
+    interrupt: Option<Interrupt>,
+    exclude_outliers: bool,
+}
+
+/// Runs shorter than this are too brief for a memory growth trend to mean much.
+const LEAK_MIN_DURATION_SECS: f64 = 5.0;
+/// Fraction of the peak RSS the process must grow by over the run to be flagged.
+const LEAK_MIN_GROWTH_RATIO: f64 = 0.1;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BenchmarkStat {
+    pub min_ttime: f64,
+    pub max_ttime: f64,
+    pub average_ttime: f64,
+    pub average_duration: f64,
+    pub max_duration: f64,
+    pub min_duration: f64,
+    pub min_stime: f64,
+    pub max_stime: f64,
+    pub average_stime: f64,
+    pub min_utime: f64,
+    pub max_utime: f64,
+    pub average_utime: f64,
+    pub min_memory: f64,
+    pub max_memory: f64,
+    pub average_memory: f64,
+    pub final_memory: f64,
+    /// Linear regression slope of RSS over the run, in KB/s.
+    pub memory_slope: f64,
+    pub utime_percentage: f64,
+    pub stime_percentage: f64,
+    pub ttime_percentage: f64,
 }
 
 impl BenchmarkStat {
@@ -41,11 +85,112 @@ impl BenchmarkStat {
             min_memory: 0.0,
             max_memory: 0.0,
             average_memory: 0.0,
+            final_memory: 0.0,
+            memory_slope: 0.0,
             utime_percentage: 0.0,
             stime_percentage: 0.0,
             ttime_percentage: 0.0,
         }
     }
+
+    /// Whether RSS grew steadily enough over a long enough run to suggest a leak.
+    pub fn probable_leak(&self) -> bool {
+        let growth_kb = self.memory_slope * self.average_duration;
+        self.average_duration >= LEAK_MIN_DURATION_SECS
+            && self.memory_slope > 0.0
+            && growth_kb >= self.max_memory * LEAK_MIN_GROWTH_RATIO
+    }
+}
+
+/// How a command behaved with several instances running at once. Each
+/// iteration is one batch of `concurrency` instances started together.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ConcurrencyStat {
+    pub concurrency: u32,
+    pub batches: usize,
+    /// Instances completed per second of batch wall time.
+    pub throughput: f64,
+    /// Per-instance wall time percentiles, in seconds.
+    pub latency_min: f64,
+    pub latency_p50: f64,
+    pub latency_p90: f64,
+    pub latency_p99: f64,
+    pub latency_max: f64,
+    /// User + system ticks of all instances in a batch, averaged over batches.
+    pub combined_cpu_ticks: f64,
+    /// Sum of the instances' peak RSS in a batch, averaged over batches. An
+    /// upper bound, since the peaks need not coincide.
+    pub combined_peak_rss_kb: f64,
+}
+
+impl ConcurrencyStat {
+    pub fn calculate(processes: &[ProcessInfo], concurrency: u32) -> Self {
+        let batches = batches(processes);
+        let mut latencies: Vec<f64> = processes
+            .iter()
+            .filter_map(|p| p.duration)
+            .map(|d| d.as_secs_f64())
+            .collect();
+        latencies.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+        let batch_time: f64 = batches.iter().map(|batch| batch_wall_time(batch)).sum();
+        let throughput = if batch_time > 0.0 {
+            processes.len() as f64 / batch_time
+        } else {
+            0.0
+        };
+        let per_batch = |value: fn(&ProcessInfo) -> u64| {
+            let totals: Vec<f64> = batches
+                .iter()
+                .map(|batch| batch.iter().map(|p| value(p)).sum::<u64>() as f64)
+                .collect();
+            stats::mean(&totals)
+        };
+
+        ConcurrencyStat {
+            concurrency,
+            batches: batches.len(),
+            throughput,
+            latency_min: latencies.first().copied().unwrap_or(0.0),
+            latency_p50: stats::percentile(&latencies, 0.5),
+            latency_p90: stats::percentile(&latencies, 0.9),
+            latency_p99: stats::percentile(&latencies, 0.99),
+            latency_max: latencies.last().copied().unwrap_or(0.0),
+            combined_cpu_ticks: per_batch(|p| p.stat.total_time),
+            combined_peak_rss_kb: per_batch(|p| p.stat.peak_memory_kb()),
+        }
+    }
+}
+
+/// Groups runs into the batches they were started in: runs overlapping in time
+/// belong together, and batches run one after another.
+fn batches(processes: &[ProcessInfo]) -> Vec<Vec<&ProcessInfo>> {
+    let mut sorted: Vec<&ProcessInfo> = processes
+        .iter()
+        .filter(|p| p.start_time.is_some() && p.end_time.is_some())
+        .collect();
+    sorted.sort_by_key(|p| p.start_time);
+
+    let mut batches: Vec<Vec<&ProcessInfo>> = Vec::new();
+    for process in sorted {
+        match batches.last_mut() {
+            Some(batch) if batch.iter().any(|p| p.end_time >= process.start_time) => {
+                batch.push(process)
+            }
+            _ => batches.push(vec![process]),
+        }
+    }
+    batches
+}
+
+/// From the first instance starting to the last one finishing.
+fn batch_wall_time(batch: &[&ProcessInfo]) -> f64 {
+    let start = batch.iter().filter_map(|p| p.start_time).min();
+    let end = batch.iter().filter_map(|p| p.end_time).max();
+    match (start, end) {
+        (Some(start), Some(end)) => end.duration_since(start).as_secs_f64(),
+        _ => 0.0,
+    }
 }
 
 // pub(crate) read_bytes: Vec<u64>,
@@ -58,7 +203,230 @@ impl BenchmarkStat {
 // pub(crate) total_time: u64,
 // }
 impl Benchmark {
-    pub(crate) fn average_stat(benchmark_stats: &Vec<BenchmarkStat>) -> BenchmarkStat {
+    pub fn command(command: impl Into<String>) -> Self {
+        Self::spec(BenchmarkSpec::new(command.into()))
+    }
+
+    /// Benchmarks a spec as planned from the command line or a suite file.
+    pub fn spec(spec: BenchmarkSpec) -> Self {
+        Self {
+            spec,
+            interrupt: None,
+            exclude_outliers: false,
+        }
+    }
+
+    /// Name shown in the report instead of the command.
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.spec.name = name.into();
+        self
+    }
+
+    /// Unmeasured runs before the measured ones.
+    pub fn warmup(mut self, warmup: u32) -> Self {
+        self.spec.warmup = warmup;
+        self
+    }
+
+    pub fn iterations(mut self, iterations: u32) -> Self {
+        self.spec.iterations = iterations;
+        self
+    }
+
+    /// Runs at most this many iterations when sampling adaptively.
+    pub fn max_iterations(mut self, max: u32) -> Self {
+        self.spec.sampling.max_iterations = Some(max);
+        self
+    }
+
+    /// Keeps starting iterations past the minimum until this much time was
+    /// spent measuring.
+    pub fn time_budget(mut self, budget: Duration) -> Self {
+        self.spec.sampling.time_budget = Some(budget);
+        self
+    }
+
+    /// Keeps starting iterations past the minimum until the 95% confidence
+    /// interval of the mean wall time is within this fraction of it.
+    pub fn target_rel_ci(mut self, target: f64) -> Self {
+        self.spec.sampling.target_rel_ci = Some(target);
+        self
+    }
+
+    /// Leaves runs whose wall time is an outlier out of the summary. They are
+    /// still listed, flagged, with the iterations.
+    pub fn exclude_outliers(mut self, exclude: bool) -> Self {
+        self.exclude_outliers = exclude;
+        self
+    }
+
+    /// CPU affinity and priorities every run is started with.
+    pub fn scheduling(mut self, scheduling: Scheduling) -> Self {
+        self.spec.scheduling = scheduling;
+        self
+    }
+
+    /// Memory, CPU, process and rlimit caps every run is started under.
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.spec.limits = limits;
+        self
+    }
+
+    /// Drop or fill the page cache before every measured iteration.
+    pub fn cache(mut self, cache: Option<CacheMode>) -> Self {
+        self.spec.cache = cache;
+        self
+    }
+
+    /// Also profile syscalls, in an extra run under ptrace that is left out of
+    /// the summary.
+    pub fn syscalls(mut self, syscalls: bool) -> Self {
+        self.spec.syscalls = syscalls;
+        self
+    }
+
+    /// Also record every file the command tree opens, reads, writes or
+    /// executes, in the same traced run as `syscalls`.
+    pub fn files(mut self, files: bool) -> Self {
+        self.spec.files = files;
+        self
+    }
+
+    /// Also sample the command's stacks this many times a second, in an extra
+    /// run that is left out of the summary.
+    pub fn profile(mut self, frequency: Option<u32>) -> Self {
+        self.spec.profile = frequency;
+        self
+    }
+
+    /// Instances started together for each iteration.
+    pub fn concurrency(mut self, concurrency: u32) -> Self {
+        self.spec.concurrency = concurrency;
+        self
+    }
+
+    pub fn cwd(mut self, cwd: impl Into<PathBuf>) -> Self {
+        self.spec.cwd = Some(cwd.into());
+        self
+    }
+
+    pub fn env(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.spec.env.push((key.into(), value.into()));
+        self
+    }
+
+    pub fn output(mut self, output: OutputMode) -> Self {
+        self.spec.output = output;
+        self
+    }
+
+    /// Kill a run once it has taken this long.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.spec.timeout = Some(timeout);
+        self
+    }
+
+    pub fn monitor(mut self, monitor: MonitorKind) -> Self {
+        self.spec.monitor = monitor;
+        self
+    }
+
+    /// Stop early when interrupted, keeping the iterations completed so far.
+    pub fn interrupt(mut self, interrupt: Interrupt) -> Self {
+        self.interrupt = Some(interrupt);
+        self
+    }
+
+    /// Runs the warmups and iterations. Fails if a hook failed or no iteration
+    /// could be run at all.
+    pub async fn run(self) -> Result<Report, executor::Error> {
+        let measured = self.spec.run(self.interrupt.as_ref()).await;
+        if let Some(e) = measured.error {
+            return Err(e.into());
+        }
+        let (processes, first_error) = measured.completed();
+        if processes.is_empty() {
+            return Err(first_error.unwrap_or_else(|| "No iterations were run".into()));
+        }
+        let mut command = self.summarize(&processes);
+        self.add_trace(&mut command).await?;
+        self.add_profile(&mut command).await?;
+        let mut report = Report::new(SystemInfo::collect());
+        report.commands.push(command);
+        report.partial = self.interrupted();
+        Ok(report)
+    }
+
+    /// The report on a command's measured runs: the summary, which leaves out
+    /// outliers when asked to, and the thread and concurrency stats.
+    pub fn summarize(&self, processes: &[ProcessInfo]) -> CommandReport {
+        let stats: Vec<BenchmarkStat> = processes.iter().map(Self::calculate).collect();
+        let summary = if self.exclude_outliers {
+            Self::average_stat_excluding_outliers(&stats)
+        } else {
+            Self::average_stat(&stats)
+        };
+        let spec = &self.spec;
+        let mut command =
+            CommandReport::new(spec.name.clone(), spec.command.clone(), processes, summary);
+        command.cache = spec.cache;
+        command.threads = ThreadStat::aggregate(processes);
+        command.waits = WaitStat::aggregate(processes);
+        if spec.concurrency > 1 {
+            command.concurrency = Some(ConcurrencyStat::calculate(processes, spec.concurrency));
+        }
+        command
+    }
+
+    /// Adds the syscall profile and file report of an extra traced run, when
+    /// asked for either and not interrupted.
+    pub async fn add_trace(&self, command: &mut CommandReport) -> Result<(), String> {
+        let spec = &self.spec;
+        if !(spec.syscalls || spec.files) || self.interrupted() {
+            return Ok(());
+        }
+        let mut trace = spec.trace(self.interrupt.as_ref()).await?;
+        let average = command.summary.average_duration;
+        trace.syscalls.overhead = (average > 0.0).then(|| trace.syscalls.wall_time / average);
+        command.syscalls = spec.syscalls.then_some(trace.syscalls);
+        command.files = trace.files;
+        Ok(())
+    }
+
+    /// Adds the stacks sampled in an extra run, when asked for them and not
+    /// interrupted.
+    pub async fn add_profile(&self, command: &mut CommandReport) -> Result<(), String> {
+        if self.spec.profile.is_none() || self.interrupted() {
+            return Ok(());
+        }
+        command.profile = Some(self.spec.profile(self.interrupt.as_ref()).await?);
+        Ok(())
+    }
+
+    fn interrupted(&self) -> bool {
+        self.interrupt
+            .as_ref()
+            .is_some_and(Interrupt::is_interrupted)
+    }
+
+    pub fn average_stat(benchmark_stats: &[BenchmarkStat]) -> BenchmarkStat {
+        Self::combine(&benchmark_stats.iter().collect::<Vec<_>>())
+    }
+
+    /// Like [`Benchmark::average_stat`], but leaves out runs whose wall time
+    /// is an outlier.
+    pub fn average_stat_excluding_outliers(benchmark_stats: &[BenchmarkStat]) -> BenchmarkStat {
+        let durations: Vec<f64> = benchmark_stats.iter().map(|s| s.average_duration).collect();
+        let kept: Vec<&BenchmarkStat> = benchmark_stats
+            .iter()
+            .zip(stats::outliers(&durations))
+            .filter(|(_, outlier)| outlier.is_none())
+            .map(|(stat, _)| stat)
+            .collect();
+        Self::combine(&kept)
+    }
+
+    fn combine(benchmark_stats: &[&BenchmarkStat]) -> BenchmarkStat {
         let mut result = BenchmarkStat::new();
 
         result.min_ttime = benchmark_stats
@@ -131,6 +499,10 @@ impl Benchmark {
             .map(|s| s.average_memory)
             .sum::<f64>()
             / benchmark_stats.len() as f64;
+        result.final_memory = benchmark_stats.iter().map(|s| s.final_memory).sum::<f64>()
+            / benchmark_stats.len() as f64;
+        result.memory_slope = benchmark_stats.iter().map(|s| s.memory_slope).sum::<f64>()
+            / benchmark_stats.len() as f64;
 
         result.utime_percentage = benchmark_stats
             .iter()
@@ -151,17 +523,15 @@ impl Benchmark {
         result
     }
 
-    pub(crate) fn calculate(process: &ProcessInfo) -> BenchmarkStat {
+    pub fn calculate(process: &ProcessInfo) -> BenchmarkStat {
         let mut benchmark = BenchmarkStat::new();
 
-        let mut memory_values = Vec::new();
         let mut utimes = Vec::new();
         let mut stimes = Vec::new();
         let mut total_times = Vec::new();
         let mut durations = Vec::new();
 
         // for process in processes {
-        memory_values.extend(&process.stat.memory_kb);
         utimes.push(process.stat.utime);
         stimes.push(process.stat.stime);
         total_times.push(process.stat.total_time);
@@ -169,9 +539,15 @@ impl Benchmark {
             durations.push(duration.as_secs_f64());
         }
         // }
-        benchmark.average_memory = Self::average(memory_values.clone());
-        benchmark.max_memory = Self::max(memory_values.clone());
-        benchmark.min_memory = Self::min(memory_values);
+        let memory = &process.stat.memory_kb;
+        if !memory.is_empty() {
+            let values: Vec<u64> = memory.iter().map(|s| s.value).collect();
+            benchmark.min_memory = Self::min(values);
+            benchmark.average_memory = Self::time_weighted_average(memory, process.duration);
+            benchmark.final_memory = memory.last().map(|s| s.value as f64).unwrap_or(0.0);
+            benchmark.memory_slope = Self::slope(memory);
+        }
+        benchmark.max_memory = process.stat.peak_memory_kb() as f64;
 
         benchmark.average_utime = Self::average(utimes.clone());
         benchmark.max_utime = Self::max(utimes.clone());
@@ -210,6 +586,52 @@ impl Benchmark {
         nums.iter().sum::<u64>() as f64 / nums.len() as f64
     }
 
+    /// Integrates the samples over the run and divides by its length, so readings
+    /// that persisted longer weigh more. Each sample holds until the next one, and
+    /// the last one until `end` when the process duration is known.
+    fn time_weighted_average(samples: &[Sample], end: Option<Duration>) -> f64 {
+        let Some(last) = samples.last() else {
+            return 0.0;
+        };
+        let first = samples[0].elapsed;
+        let end = end
+            .filter(|end| *end > last.elapsed)
+            .unwrap_or(last.elapsed);
+        let span = (end - first).as_secs_f64();
+        if span <= 0.0 {
+            return Self::average(samples.iter().map(|s| s.value).collect());
+        }
+
+        let mut area = 0.0;
+        for (i, sample) in samples.iter().enumerate() {
+            let until = samples.get(i + 1).map(|next| next.elapsed).unwrap_or(end);
+            area += sample.value as f64 * (until - sample.elapsed).as_secs_f64();
+        }
+        area / span
+    }
+
+    /// Least squares slope of value over time, in units per second.
+    fn slope(samples: &[Sample]) -> f64 {
+        if samples.len() < 2 {
+            return 0.0;
+        }
+        let n = samples.len() as f64;
+        let mean_x = samples.iter().map(|s| s.elapsed.as_secs_f64()).sum::<f64>() / n;
+        let mean_y = samples.iter().map(|s| s.value as f64).sum::<f64>() / n;
+
+        let mut covariance = 0.0;
+        let mut variance = 0.0;
+        for sample in samples {
+            let dx = sample.elapsed.as_secs_f64() - mean_x;
+            covariance += dx * (sample.value as f64 - mean_y);
+            variance += dx * dx;
+        }
+        if variance == 0.0 {
+            return 0.0;
+        }
+        covariance / variance
+    }
+
     fn max(nums: Vec<u64>) -> f64 {
         *nums.iter().max().unwrap() as f64
     }
@@ -222,7 +644,7 @@ impl Benchmark {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::time::{Duration, Instant};
+    use std::time::Instant;
 
     fn create_test_process(
         utime: u64,
@@ -233,7 +655,13 @@ mod tests {
         let mut process = ProcessInfo::new(String::from("ls"), vec![]);
         process.stat.utime = utime;
         process.stat.stime = stime;
-        process.stat.memory_kb = memory;
+        // Spread the readings evenly across the run
+        let step = Duration::from_secs(duration_secs) / memory.len().max(1) as u32;
+        process.stat.memory_kb = memory
+            .into_iter()
+            .enumerate()
+            .map(|(i, value)| Sample::new(step * i as u32, value))
+            .collect();
         process.stat.total_time = utime + stime;
         process.duration = Some(Duration::from_secs(duration_secs));
         process.start_time = Some(Instant::now());
@@ -248,7 +676,7 @@ mod tests {
 
         assert_eq!(stats.average_utime, 100.0);
         assert_eq!(stats.average_stime, 50.0);
-        assert_eq!(stats.average_memory, 2000.0);
+        assert!((stats.average_memory - 2000.0).abs() < 1e-3);
         assert_eq!(stats.min_memory, 1000.0);
         assert_eq!(stats.max_memory, 3000.0);
         assert_eq!(stats.average_duration, 5.0);
@@ -283,4 +711,135 @@ mod tests {
         assert_eq!(Benchmark::max(nums.clone()), 5.0);
         assert_eq!(Benchmark::min(nums), 1.0);
     }
+
+    #[test]
+    fn test_memory_peak_and_final() {
+        let process = create_test_process(10, 10, vec![1000, 4000, 2500], 3);
+        let stats = Benchmark::calculate(&process);
+
+        assert_eq!(stats.max_memory, 4000.0);
+        assert_eq!(stats.final_memory, 2500.0);
+    }
+
+    #[test]
+    fn test_memory_peak_between_samples() {
+        let mut process = create_test_process(10, 10, vec![1000, 2500], 3);
+        process.stat.max_rss_kb = Some(6000);
+        assert_eq!(Benchmark::calculate(&process).max_memory, 6000.0);
+        // Too short a run to sample at all
+        let mut process = create_test_process(0, 0, vec![], 1);
+        process.stat.max_rss_kb = Some(1500);
+        assert_eq!(Benchmark::calculate(&process).max_memory, 1500.0);
+    }
+
+    #[test]
+    fn test_time_weighted_average_uneven_sampling() {
+        // 1000 KB held for 9s, then 10000 KB for the final second
+        let samples = vec![
+            Sample::new(Duration::from_secs(0), 1000),
+            Sample::new(Duration::from_secs(9), 10000),
+        ];
+        let average = Benchmark::time_weighted_average(&samples, Some(Duration::from_secs(10)));
+        assert!((average - 1900.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_time_weighted_average_single_sample() {
+        let samples = vec![Sample::new(Duration::from_millis(3), 512)];
+        assert_eq!(Benchmark::time_weighted_average(&samples, None), 512.0);
+        assert_eq!(Benchmark::time_weighted_average(&[], None), 0.0);
+    }
+
+    #[test]
+    fn test_memory_slope() {
+        let samples: Vec<Sample> = (0..10)
+            .map(|i| Sample::new(Duration::from_secs(i), 1000 + i * 250))
+            .collect();
+        assert!((Benchmark::slope(&samples) - 250.0).abs() < 1e-9);
+
+        let flat = vec![Sample::new(Duration::from_secs(1), 1000); 3];
+        assert_eq!(Benchmark::slope(&flat), 0.0);
+    }
+
+    #[test]
+    fn test_probable_leak() {
+        let growing = create_test_process(10, 10, (1..=10).map(|i| i * 1000).collect(), 10);
+        assert!(Benchmark::calculate(&growing).probable_leak());
+
+        let steady = create_test_process(10, 10, vec![5000; 10], 10);
+        assert!(!Benchmark::calculate(&steady).probable_leak());
+
+        let short = create_test_process(10, 10, (1..=10).map(|i| i * 1000).collect(), 1);
+        assert!(!Benchmark::calculate(&short).probable_leak());
+    }
+
+    #[test]
+    fn test_concurrency_stat() {
+        let start = Instant::now();
+        let run = |offset_ms: u64, duration_ms: u64, rss: u64| {
+            let mut process = create_test_process(10, 5, vec![rss], 0);
+            process.start_time = Some(start + Duration::from_millis(offset_ms));
+            process.end_time = Some(start + Duration::from_millis(offset_ms + duration_ms));
+            process.duration = Some(Duration::from_millis(duration_ms));
+            process
+        };
+        // Two batches of two instances: 0-100ms and 200-400ms
+        let processes = vec![
+            run(0, 100, 1000),
+            run(1, 99, 2000),
+            run(200, 150, 1000),
+            run(201, 199, 1000),
+        ];
+        let stat = ConcurrencyStat::calculate(&processes, 2);
+        assert_eq!(stat.batches, 2);
+        // Four runs in 0.1 + 0.2 seconds of batch time
+        assert!((stat.throughput - 4.0 / 0.3).abs() < 1e-6);
+        assert!((stat.latency_min - 0.099).abs() < 1e-9);
+        assert!((stat.latency_max - 0.199).abs() < 1e-9);
+        assert_eq!(stat.combined_cpu_ticks, 30.0);
+        assert_eq!(stat.combined_peak_rss_kb, 2500.0);
+    }
+
+    #[test]
+    fn test_average_stat_exclude_outliers() {
+        let stats: Vec<BenchmarkStat> = [1.0, 1.1, 1.0, 1.2, 1.1, 3.3]
+            .iter()
+            .map(|&seconds| {
+                let mut process = create_test_process(10, 5, vec![1000], 0);
+                process.duration = Some(Duration::from_secs_f64(seconds));
+                Benchmark::calculate(&process)
+            })
+            .collect();
+        let all = Benchmark::average_stat(&stats);
+        assert!((all.average_duration - 1.45).abs() < 1e-9);
+        assert_eq!(all.max_duration, 3.3);
+        let kept = Benchmark::average_stat_excluding_outliers(&stats);
+        assert!((kept.average_duration - 1.08).abs() < 1e-9);
+        assert_eq!(kept.max_duration, 1.2);
+    }
+
+    #[test]
+    fn test_summarize() {
+        let processes: Vec<ProcessInfo> = [1.0, 1.1, 1.0, 1.2, 1.1, 3.3]
+            .iter()
+            .map(|&seconds| {
+                let mut process = create_test_process(10, 5, vec![1000], 0);
+                process.duration = Some(Duration::from_secs_f64(seconds));
+                process
+            })
+            .collect();
+        let benchmark = Benchmark::command("ls")
+            .name("list")
+            .cache(Some(CacheMode::Warm))
+            .concurrency(2)
+            .exclude_outliers(true);
+        let command = benchmark.summarize(&processes);
+        assert_eq!(command.name, "list");
+        assert_eq!(command.command, "ls");
+        assert_eq!(command.iterations.len(), 6);
+        assert_eq!(command.summary.max_duration, 1.2);
+        assert_eq!(command.cache, Some(CacheMode::Warm));
+        assert_eq!(command.concurrency.unwrap().concurrency, 2);
+        assert!(command.syscalls.is_none() && command.profile.is_none());
+    }
 }
@@ -0,0 +1,141 @@
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io;
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+
+const DROP_CACHES: &str = "/proc/sys/vm/drop_caches";
+
+/// State of the page cache each measured iteration starts from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum CacheMode {
+    /// Nothing the command reads is cached, as on a first run after boot.
+    Cold,
+    /// Everything the command reads is already cached.
+    Warm,
+}
+
+impl std::fmt::Display for CacheMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CacheMode::Cold => write!(f, "cold"),
+            CacheMode::Warm => write!(f, "warm"),
+        }
+    }
+}
+
+/// Whether lens may drop the whole page cache, which takes root and a writable
+/// /proc/sys. Without it only the command's files are evicted.
+pub fn can_drop_page_cache() -> bool {
+    let path = std::ffi::CString::new(DROP_CACHES).unwrap();
+    // SAFETY: access only reads the NUL terminated path
+    unsafe { libc::access(path.as_ptr(), libc::W_OK) == 0 }
+}
+
+/// Brings the page cache into the state of `mode`: for cold, drops it whole
+/// when allowed or evicts `files` otherwise; for warm, reads `files` in.
+pub fn prepare(mode: CacheMode, files: &[PathBuf]) -> Result<(), String> {
+    match mode {
+        CacheMode::Cold if can_drop_page_cache() => {
+            drop_page_cache().map_err(|e| format!("Failed to drop the page cache: {}", e))
+        }
+        CacheMode::Cold => files.iter().try_for_each(|file| {
+            evict(file).map_err(|e| format!("Failed to evict {}: {}", file.display(), e))
+        }),
+        CacheMode::Warm => files.iter().try_for_each(|file| {
+            preload(file).map_err(|e| format!("Failed to read {}: {}", file.display(), e))
+        }),
+    }
+}
+
+/// Writes back dirty pages, then drops every clean one.
+fn drop_page_cache() -> io::Result<()> {
+    // SAFETY: sync takes no arguments and cannot fail
+    unsafe { libc::sync() };
+    std::fs::write(DROP_CACHES, "1")
+}
+
+/// Drops a file's pages from the page cache. Dirty pages are written back
+/// first, as the kernel only drops clean ones.
+fn evict(path: &Path) -> io::Result<()> {
+    let file = File::open(path)?;
+    file.sync_data()?;
+    // SAFETY: the descriptor stays open for the duration of the call
+    let error = unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED) };
+    match error {
+        0 => Ok(()),
+        error => Err(io::Error::from_raw_os_error(error)),
+    }
+}
+
+/// Reads a file through so that all of it is in the page cache.
+fn preload(path: &Path) -> io::Result<()> {
+    io::copy(&mut File::open(path)?, &mut io::sink()).map(|_| ())
+}
+
+/// Pages of a file currently in the page cache, from mincore.
+#[cfg(test)]
+fn resident_pages(path: &Path) -> io::Result<usize> {
+    let file = File::open(path)?;
+    let len = file.metadata()?.len() as usize;
+    if len == 0 {
+        return Ok(0);
+    }
+    // SAFETY: a private read-only mapping of an open file, unmapped below
+    let addr = unsafe {
+        libc::mmap(
+            std::ptr::null_mut(),
+            len,
+            libc::PROT_READ,
+            libc::MAP_PRIVATE,
+            file.as_raw_fd(),
+            0,
+        )
+    };
+    if addr == libc::MAP_FAILED {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: sysconf has no preconditions
+    let page = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+    let mut pages = vec![0u8; len.div_ceil(page)];
+    // SAFETY: the vector has one byte per page of the mapping
+    let result = unsafe { libc::mincore(addr, len, pages.as_mut_ptr()) };
+    // SAFETY: addr and len are the mapping made above
+    unsafe { libc::munmap(addr, len) };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(pages.iter().filter(|&&page| page & 1 == 1).count())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_file(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("lens-cache-{}-{}", std::process::id(), name));
+        std::fs::write(&path, vec![b'x'; 256 * 1024]).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_evict_and_preload() {
+        let path = temp_file("evict");
+        preload(&path).unwrap();
+        let cached = resident_pages(&path).unwrap();
+        evict(&path).unwrap();
+        let evicted = resident_pages(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        // tmpfs pages cannot be evicted, so only require no growth there
+        assert!(cached > 0);
+        assert!(evicted <= cached);
+    }
+
+    #[test]
+    fn test_prepare_missing_file() {
+        let missing = [PathBuf::from("/nonexistent/lens-cache")];
+        let error = prepare(CacheMode::Warm, &missing).unwrap_err();
+        assert!(error.contains("Failed to read /nonexistent/lens-cache"));
+    }
+}
@@ -0,0 +1,514 @@
+use crate::cache::CacheMode;
+use crate::limits::{self, Limits};
+use crate::output::OutputMode;
+use crate::plan::{BenchmarkSpec, Hooks, Sampling};
+use crate::profile;
+use crate::scheduling::{self, Scheduling};
+use crate::threshold::Thresholds;
+use crate::units;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Looked up in the current directory when no --config is given.
+pub const DEFAULT_CONFIG: &str = "lens.toml";
+
+/// A benchmark suite file.
+///
+/// ```toml
+/// [[benchmark]]
+/// name = "compress"
+/// command = "gzip -k -f -{level} data.txt"
+/// cwd = "fixtures"
+/// env = { LC_ALL = "C" }
+/// input = "fixtures/stdin.txt"
+/// output = "pipe"
+/// expected_output = "fixtures/expected.txt"
+/// warmup = 1
+/// iterations = 10
+/// max_runs = 100
+/// time_budget = "30s"
+/// target_rel_ci = "1%"
+/// concurrency = 4
+/// cpus = "2,3"
+/// nice = -10
+/// sched = "fifo:50"
+/// ionice = "best-effort:0"
+/// memory_limit = "512M"
+/// cpu_quota = 1.5
+/// pids_max = 64
+/// rlimits = ["nofile=1024"]
+/// cache = "cold"
+/// cache_files = ["fixtures/dictionary.txt"]
+/// syscalls = true
+/// files = true
+/// profile = true
+/// profile_frequency = 499
+/// timeout = "30s"
+/// parameters = { level = [1, 9] }
+/// hooks = { prepare = "rm -f data.txt.gz" }
+/// thresholds = { max-time = "2.5s", max-rss = "64MB" }
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default, rename = "benchmark")]
+    benchmarks: Vec<BenchmarkConfig>,
+    /// Directory relative paths in the file are resolved against.
+    #[serde(skip)]
+    root: PathBuf,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BenchmarkConfig {
+    name: String,
+    command: String,
+    cwd: Option<PathBuf>,
+    #[serde(default)]
+    env: BTreeMap<String, String>,
+    #[serde(default)]
+    env_clear: bool,
+    input: Option<PathBuf>,
+    output: Option<String>,
+    expected_output: Option<PathBuf>,
+    warmup: Option<u32>,
+    /// The minimum when sampling is adaptive.
+    iterations: Option<u32>,
+    max_runs: Option<u32>,
+    time_budget: Option<String>,
+    target_rel_ci: Option<String>,
+    concurrency: Option<u32>,
+    cpus: Option<String>,
+    nice: Option<i32>,
+    sched: Option<String>,
+    ionice: Option<String>,
+    memory_limit: Option<String>,
+    cpu_quota: Option<toml::Value>,
+    pids_max: Option<u64>,
+    #[serde(default)]
+    rlimits: Vec<String>,
+    cache: Option<CacheMode>,
+    #[serde(default)]
+    cache_files: Vec<PathBuf>,
+    #[serde(default)]
+    syscalls: bool,
+    #[serde(default)]
+    files: bool,
+    #[serde(default)]
+    profile: bool,
+    profile_frequency: Option<u32>,
+    timeout: Option<String>,
+    #[serde(default)]
+    hooks: HooksConfig,
+    #[serde(default)]
+    parameters: BTreeMap<String, Vec<toml::Value>>,
+    #[serde(default)]
+    thresholds: ThresholdsConfig,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct HooksConfig {
+    setup: Option<String>,
+    prepare: Option<String>,
+    conclude: Option<String>,
+    cleanup: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct ThresholdsConfig {
+    max_time: Option<String>,
+    max_rss: Option<String>,
+    max_regression: Option<String>,
+    max_cpu_time: Option<String>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Config, Box<dyn std::error::Error>> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let mut config = Self::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+        config.root = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        Ok(config)
+    }
+
+    fn parse(text: &str) -> Result<Config, Box<dyn std::error::Error>> {
+        let config: Config = toml::from_str(text)?;
+        let mut seen = Vec::new();
+        for benchmark in &config.benchmarks {
+            if seen.contains(&&benchmark.name) {
+                return Err(format!("benchmark '{}' is defined twice", benchmark.name).into());
+            }
+            seen.push(&benchmark.name);
+        }
+        Ok(config)
+    }
+
+    /// Specs for the named benchmarks, or for all of them when no names are given.
+    pub fn specs(&self, names: &[String]) -> Result<Vec<BenchmarkSpec>, String> {
+        for name in names {
+            if !self.benchmarks.iter().any(|b| &b.name == name) {
+                let known: Vec<&str> = self.benchmarks.iter().map(|b| b.name.as_str()).collect();
+                return Err(format!(
+                    "Unknown benchmark '{}', expected one of: {}",
+                    name,
+                    known.join(", ")
+                ));
+            }
+        }
+
+        let mut specs = Vec::new();
+        for benchmark in &self.benchmarks {
+            if names.is_empty() || names.contains(&benchmark.name) {
+                specs.extend(self.spec(benchmark)?);
+            }
+        }
+        Ok(specs)
+    }
+
+    fn spec(&self, benchmark: &BenchmarkConfig) -> Result<Vec<BenchmarkSpec>, String> {
+        let context = |e: String| format!("benchmark '{}': {}", benchmark.name, e);
+        let mut spec = BenchmarkSpec::new(benchmark.command.clone());
+        spec.name = benchmark.name.clone();
+        spec.cwd = Some(match &benchmark.cwd {
+            Some(cwd) => self.root.join(cwd),
+            None => self.root.clone(),
+        })
+        .filter(|cwd| !cwd.as_os_str().is_empty());
+        spec.env = benchmark
+            .env
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        spec.env_clear = benchmark.env_clear;
+        spec.input = benchmark.input.as_ref().map(|input| self.root.join(input));
+        spec.output = match benchmark.output.as_deref().map(str::parse).transpose() {
+            Ok(Some(OutputMode::File(path))) => OutputMode::File(self.root.join(path)),
+            Ok(output) => output.unwrap_or_default(),
+            Err(e) => return Err(context(e)),
+        };
+        spec.expected_output = benchmark
+            .expected_output
+            .as_ref()
+            .map(|expected| self.root.join(expected));
+        spec.warmup = benchmark.warmup.unwrap_or(spec.warmup);
+        spec.iterations = benchmark.iterations.unwrap_or(spec.iterations);
+        spec.sampling = Sampling {
+            max_iterations: benchmark.max_runs,
+            time_budget: benchmark
+                .time_budget
+                .as_deref()
+                .map(units::parse_duration)
+                .transpose()
+                .map_err(context)?,
+            target_rel_ci: benchmark
+                .target_rel_ci
+                .as_deref()
+                .map(units::parse_percent)
+                .transpose()
+                .map_err(context)?,
+        };
+        spec.concurrency = benchmark.concurrency.unwrap_or(spec.concurrency);
+        spec.scheduling = Scheduling {
+            cpus: benchmark
+                .cpus
+                .as_deref()
+                .map(scheduling::parse_cpu_list)
+                .transpose()
+                .map_err(context)?,
+            nice: benchmark.nice,
+            policy: benchmark
+                .sched
+                .as_deref()
+                .map(str::parse)
+                .transpose()
+                .map_err(context)?,
+            io_priority: benchmark
+                .ionice
+                .as_deref()
+                .map(str::parse)
+                .transpose()
+                .map_err(context)?,
+        };
+        spec.limits = Limits {
+            memory: benchmark
+                .memory_limit
+                .as_deref()
+                .map(units::parse_bytes)
+                .transpose()
+                .map_err(context)?,
+            cpu_quota: benchmark
+                .cpu_quota
+                .as_ref()
+                .map(|quota| limits::parse_cpu_quota(&quota.to_string()))
+                .transpose()
+                .map_err(context)?,
+            pids: benchmark.pids_max,
+            rlimits: benchmark
+                .rlimits
+                .iter()
+                .map(|rlimit| rlimit.parse())
+                .collect::<Result<_, _>>()
+                .map_err(context)?,
+        };
+        spec.cache = benchmark.cache;
+        spec.cache_files = benchmark
+            .cache_files
+            .iter()
+            .map(|file| self.root.join(file))
+            .collect();
+        spec.syscalls = benchmark.syscalls;
+        spec.files = benchmark.files;
+        spec.profile = benchmark.profile.then(|| {
+            benchmark
+                .profile_frequency
+                .unwrap_or(profile::DEFAULT_FREQUENCY)
+        });
+        spec.timeout = benchmark
+            .timeout
+            .as_deref()
+            .map(units::parse_duration)
+            .transpose()
+            .map_err(context)?;
+        spec.hooks = Hooks {
+            setup: benchmark.hooks.setup.clone(),
+            prepare: benchmark.hooks.prepare.clone(),
+            conclude: benchmark.hooks.conclude.clone(),
+            cleanup: benchmark.hooks.cleanup.clone(),
+        };
+        spec.thresholds = benchmark.thresholds.parse().map_err(context)?;
+
+        let parameters = benchmark
+            .parameters
+            .iter()
+            .map(|(name, values)| {
+                let values = values
+                    .iter()
+                    .map(|value| match value {
+                        toml::Value::String(text) => text.clone(),
+                        other => other.to_string(),
+                    })
+                    .collect();
+                (name.clone(), values)
+            })
+            .collect();
+        Ok(spec.expand(&parameters))
+    }
+}
+
+impl ThresholdsConfig {
+    fn parse(&self) -> Result<Thresholds, String> {
+        Ok(Thresholds {
+            max_time: self
+                .max_time
+                .as_deref()
+                .map(units::parse_duration)
+                .transpose()?,
+            max_rss: self
+                .max_rss
+                .as_deref()
+                .map(units::parse_bytes)
+                .transpose()?,
+            max_regression: self
+                .max_regression
+                .as_deref()
+                .map(units::parse_percent)
+                .transpose()?,
+            max_cpu_time: self
+                .max_cpu_time
+                .as_deref()
+                .map(units::parse_duration)
+                .transpose()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scheduling::IoPriority;
+    use std::time::Duration;
+
+    const SUITE: &str = r#"
+        [[benchmark]]
+        name = "compress"
+        command = "gzip -k -f -{level} data.txt"
+        cwd = "fixtures"
+        env = { LC_ALL = "C" }
+        env_clear = true
+        input = "stdin.txt"
+        output = "file:out.txt"
+        expected_output = "expected.txt"
+        warmup = 1
+        iterations = 10
+        time_budget = "1m"
+        target_rel_ci = "2%"
+        concurrency = 2
+        cpus = "0-1"
+        nice = -5
+        ionice = "idle"
+        memory_limit = "256M"
+        cpu_quota = 2
+        pids_max = 32
+        rlimits = ["nofile=1024"]
+        cache = "warm"
+        cache_files = ["words.txt"]
+        syscalls = true
+        files = true
+        profile = true
+        profile_frequency = 99
+        timeout = "30s"
+        parameters = { level = [1, 9] }
+        hooks = { prepare = "rm -f data.txt.gz" }
+        thresholds = { max-time = "2.5s", max-rss = "64MB" }
+
+        [[benchmark]]
+        name = "list"
+        command = "ls -l"
+    "#;
+
+    fn load(text: &str) -> Config {
+        let mut config = Config::parse(text).unwrap();
+        config.root = PathBuf::from("/project");
+        config
+    }
+
+    #[test]
+    fn test_specs_all() {
+        let specs = load(SUITE).specs(&[]).unwrap();
+        assert_eq!(specs.len(), 3);
+
+        let compress = &specs[0];
+        assert_eq!(compress.name, "compress (level=1)");
+        assert_eq!(compress.command, "gzip -k -f -1 data.txt");
+        assert_eq!(compress.cwd, Some(PathBuf::from("/project/fixtures")));
+        assert_eq!(
+            compress.env,
+            vec![(String::from("LC_ALL"), String::from("C"))]
+        );
+        assert!(compress.env_clear);
+        assert_eq!(compress.input, Some(PathBuf::from("/project/stdin.txt")));
+        assert_eq!(
+            compress.output,
+            OutputMode::File(PathBuf::from("/project/out.txt"))
+        );
+        assert_eq!(
+            compress.expected_output,
+            Some(PathBuf::from("/project/expected.txt"))
+        );
+        assert_eq!(compress.warmup, 1);
+        assert_eq!(compress.iterations, 10);
+        assert_eq!(compress.concurrency, 2);
+        assert_eq!(compress.scheduling.cpus, Some(vec![0, 1]));
+        assert_eq!(compress.scheduling.nice, Some(-5));
+        assert_eq!(compress.scheduling.policy, None);
+        assert_eq!(compress.scheduling.io_priority, Some(IoPriority::Idle));
+        assert_eq!(compress.limits.memory, Some(256 * 1024 * 1024));
+        assert_eq!(compress.limits.cpu_quota, Some(2.0));
+        assert_eq!(compress.limits.pids, Some(32));
+        assert_eq!(compress.limits.rlimits[0].value, Some(1024));
+        assert_eq!(compress.cache, Some(CacheMode::Warm));
+        assert!(compress.syscalls);
+        assert!(compress.files);
+        assert_eq!(compress.profile, Some(99));
+        assert_eq!(compress.cache_files, [PathBuf::from("/project/words.txt")]);
+        assert_eq!(compress.sampling.max_iterations, None);
+        assert_eq!(compress.sampling.time_budget, Some(Duration::from_secs(60)));
+        assert_eq!(compress.sampling.target_rel_ci, Some(0.02));
+        assert_eq!(compress.timeout, Some(Duration::from_secs(30)));
+        assert_eq!(compress.hooks.prepare.as_deref(), Some("rm -f data.txt.gz"));
+        assert_eq!(
+            compress.thresholds.max_time,
+            Some(Duration::from_millis(2500))
+        );
+        assert_eq!(compress.thresholds.max_rss, Some(64 << 20));
+        assert_eq!(specs[1].command, "gzip -k -f -9 data.txt");
+
+        let list = &specs[2];
+        assert_eq!(list.name, "list");
+        assert_eq!(list.cwd, Some(PathBuf::from("/project")));
+        assert_eq!(list.iterations, 5);
+        assert!(!list.env_clear);
+        assert_eq!(list.input, None);
+        assert_eq!(list.profile, None);
+        assert_eq!(list.output, OutputMode::Null);
+    }
+
+    #[test]
+    fn test_specs_subset() {
+        let specs = load(SUITE).specs(&[String::from("list")]).unwrap();
+        assert_eq!(specs.len(), 1);
+        assert_eq!(specs[0].name, "list");
+    }
+
+    #[test]
+    fn test_specs_unknown_name() {
+        let error = load(SUITE).specs(&[String::from("nope")]).unwrap_err();
+        assert_eq!(
+            error,
+            "Unknown benchmark 'nope', expected one of: compress, list"
+        );
+    }
+
+    #[test]
+    fn test_invalid_values() {
+        let config = load(
+            r#"
+            [[benchmark]]
+            name = "slow"
+            command = "sleep 1"
+            timeout = "soon"
+            "#,
+        );
+        assert!(
+            config
+                .specs(&[])
+                .unwrap_err()
+                .starts_with("benchmark 'slow'")
+        );
+    }
+
+    #[test]
+    fn test_unknown_fields_are_rejected() {
+        let error = Config::parse(
+            r#"
+            [[benchmark]]
+            name = "typo"
+            command = "ls"
+            iteration = 3
+            "#,
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("iteration"));
+    }
+
+    #[test]
+    fn test_duplicate_names_are_rejected() {
+        let error = Config::parse(
+            r#"
+            [[benchmark]]
+            name = "a"
+            command = "ls"
+            [[benchmark]]
+            name = "a"
+            command = "pwd"
+            "#,
+        )
+        .unwrap_err();
+        assert_eq!(error.to_string(), "benchmark 'a' is defined twice");
+    }
+
+    #[test]
+    fn test_load_resolves_relative_to_file() {
+        let dir = std::env::temp_dir().join(format!("lens-config-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(DEFAULT_CONFIG);
+        std::fs::write(&path, SUITE).unwrap();
+
+        let specs = Config::load(&path).unwrap().specs(&[]).unwrap();
+        assert_eq!(specs[0].cwd, Some(dir.join("fixtures")));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
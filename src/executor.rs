@@ -1,64 +1,457 @@
-use crate::linux::PollBased;
-use crate::monitor::Monitor;
-use crate::process::ProcessInfo;
-use std::process::Stdio;
-use tokio::process::{Child, Command};
+use crate::files::FileRecorder;
+use crate::interrupt::{self, Interrupt};
+use crate::limits::Limits;
+use crate::linux::{CgroupV2, PollBased, RunCgroup};
+use crate::monitor::{Monitor, MonitorKind};
+use crate::output::{OutputDigest, OutputMode};
+use crate::process::{Outcome, ProcessInfo};
+use crate::profile::{self, Profile};
+use crate::scheduling::{Scheduling, SchedulingState};
+use crate::syscalls::{self, SyscallProfile, Trace};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::PathBuf;
+use std::process::{Child, ExitStatus, Stdio};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+use tokio::process::ChildStdout;
+
+/// How often a running command is sampled.
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(5);
+
+/// Errors are `Send` so that an execution can be moved onto any runtime task.
+pub type Error = Box<dyn std::error::Error + Send + Sync>;
 
 pub struct Executor {
     command: String,
+    cwd: Option<PathBuf>,
+    env: Vec<(String, String)>,
+    env_clear: bool,
+    input: Option<PathBuf>,
+    output: OutputMode,
+    monitor: MonitorKind,
+    timeout: Option<Duration>,
+    interrupt: Option<Interrupt>,
+    scheduling: Scheduling,
+    limits: Limits,
 }
 
 impl Executor {
     pub fn new(command: String) -> Self {
-        Self { command }
+        Self {
+            command,
+            cwd: None,
+            env: Vec::new(),
+            env_clear: false,
+            input: None,
+            output: OutputMode::Null,
+            monitor: MonitorKind::Poll,
+            timeout: None,
+            interrupt: None,
+            scheduling: Scheduling::default(),
+            limits: Limits::default(),
+        }
+    }
+
+    /// Directory the command is started in, instead of the current one.
+    pub fn cwd(mut self, cwd: Option<PathBuf>) -> Self {
+        self.cwd = cwd;
+        self
+    }
+
+    /// Variables set on top of the inherited environment.
+    pub fn env(mut self, env: Vec<(String, String)>) -> Self {
+        self.env = env;
+        self
+    }
+
+    /// Start from an empty environment instead of inheriting lens's own.
+    pub fn env_clear(mut self, env_clear: bool) -> Self {
+        self.env_clear = env_clear;
+        self
+    }
+
+    /// File fed to the command's stdin. Without one stdin is empty.
+    pub fn input(mut self, input: Option<PathBuf>) -> Self {
+        self.input = input;
+        self
+    }
+
+    /// Where the command's stdout goes.
+    pub fn output(mut self, output: OutputMode) -> Self {
+        self.output = output;
+        self
+    }
+
+    /// How the running command is measured.
+    pub fn monitor(mut self, monitor: MonitorKind) -> Self {
+        self.monitor = monitor;
+        self
+    }
+
+    /// Kill the command once it has run for this long.
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Pass interrupts on to the command's process group.
+    pub fn interrupt(mut self, interrupt: Option<Interrupt>) -> Self {
+        self.interrupt = interrupt;
+        self
+    }
+
+    /// CPU affinity and priorities the command runs with.
+    pub fn scheduling(mut self, scheduling: Scheduling) -> Self {
+        self.scheduling = scheduling;
+        self
+    }
+
+    /// Memory, CPU, process and rlimit caps the command runs under.
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
     }
 
-    pub fn execute(&self) -> Result<ProcessInfo, Box<dyn std::error::Error>> {
+    /// Runs the command once, sampling it until it exits. Dropping the future
+    /// kills the command.
+    pub async fn execute(&self) -> Result<ProcessInfo, Error> {
+        let cgroup = if self.monitor == MonitorKind::Cgroup || self.limits.needs_cgroup() {
+            let cgroup =
+                RunCgroup::create().map_err(|e| format!("Failed to create a cgroup: {}", e))?;
+            cgroup
+                .limit(&self.limits)
+                .map_err(|e| format!("Failed to apply resource limits: {}", e))?;
+            Some(cgroup)
+        } else {
+            None
+        };
+        let (mut command, program, args) = self.command(cgroup.as_ref())?;
+        let mut child = command.spawn().map_err(|e| self.spawn_error(e))?;
+        let guard = KillOnDrop(child.id());
+
+        // Drained concurrently so a full pipe never stalls the command
+        let stdout = match child.stdout.take() {
+            Some(stdout) => Some(tokio::spawn(OutputDigest::read(ChildStdout::from_std(
+                stdout,
+            )?))),
+            None => None,
+        };
+
+        let mut process_info = ProcessInfo::new(program, args);
+        let pid = child.id();
+        match &cgroup {
+            Some(cgroup) if self.monitor == MonitorKind::Cgroup => {
+                let monitor = CgroupV2::at(pid, cgroup.path().to_path_buf());
+                self.run(child, &mut process_info, monitor).await?
+            }
+            _ => {
+                self.run(child, &mut process_info, PollBased::new(pid))
+                    .await?
+            }
+        }
+        std::mem::forget(guard);
+        if let Some(cgroup) = &cgroup
+            && self.limits.needs_cgroup()
+        {
+            let events = cgroup.limit_events();
+            if process_info.outcome == Outcome::Exited {
+                if events.oom_kills > 0 {
+                    process_info.outcome = Outcome::OomKilled;
+                } else if events.throttled_periods > 0 {
+                    process_info.outcome = Outcome::Throttled;
+                }
+            }
+            process_info.limit_events = Some(events);
+        }
+        if let Some(stdout) = stdout {
+            process_info.stdout = Some(
+                stdout
+                    .await?
+                    .map_err(|e| format!("Failed to read output: {}", e))?,
+            );
+        }
+        Ok(process_info)
+    }
+
+    /// Runs the command once under ptrace, counting and timing its syscalls and
+    /// those of everything it starts. Tracing slows the command down, so this
+    /// is a run of its own rather than part of `execute`. No cgroup is set up.
+    pub async fn trace_syscalls(&self) -> Result<SyscallProfile, Error> {
+        Ok(self.trace(false).await?.syscalls)
+    }
+
+    /// Like [`Executor::trace_syscalls`], and with `files` also recording the
+    /// files the command and everything it starts use.
+    pub async fn trace(&self, files: bool) -> Result<Trace, Error> {
+        let (mut command, _, _) = self.command(None)?;
+        if self.output == OutputMode::Pipe {
+            // Nothing would drain the pipe
+            command.stdout(Stdio::null());
+        }
+        let recorder = if files {
+            Some(FileRecorder::new(match &self.cwd {
+                Some(cwd) => cwd.clone(),
+                None => std::env::current_dir()?,
+            }))
+        } else {
+            None
+        };
+        self.supervise(move |started| syscalls::trace(command, started, recorder))
+            .await?
+            .map_err(|e| match e.raw_os_error() {
+                Some(libc::EPERM) => format!(
+                    "Failed to trace syscalls: {}, ptrace may be forbidden here",
+                    e
+                )
+                .into(),
+                _ => self.spawn_error(e).into(),
+            })
+    }
+
+    /// Runs the command once while sampling its stacks `frequency` times a
+    /// second. Like `trace`, a run of its own, without a cgroup.
+    pub async fn profile(&self, frequency: u32) -> Result<Profile, Error> {
+        let (mut command, _, _) = self.command(None)?;
+        if self.output == OutputMode::Pipe {
+            command.stdout(Stdio::null());
+        }
+        self.supervise(move |started| profile::record(command, frequency, started))
+            .await?
+            .map_err(|e| self.spawn_error(e).into())
+    }
+
+    /// Runs `run` on a blocking thread, killing the command it starts on
+    /// timeout or interrupt. `run` gets a callback to hand over the pid with.
+    async fn supervise<T: Send + 'static>(
+        &self,
+        run: impl FnOnce(Box<dyn FnOnce(u32) + Send>) -> T + Send + 'static,
+    ) -> Result<T, Error> {
+        let pid = Arc::new(AtomicU32::new(0));
+        let started = pid.clone();
+        let mut task = tokio::task::spawn_blocking(move || {
+            run(Box::new(move |pid| started.store(pid, Ordering::Relaxed)))
+        });
+
+        let deadline = async {
+            match self.timeout {
+                Some(timeout) => tokio::time::sleep(timeout).await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::pin!(deadline);
+        // Until the command has started there is no group to kill, and 0 would be lens's own
+        let kill = || match pid.load(Ordering::Relaxed) {
+            0 => {}
+            pid => interrupt::signal_group(pid, libc::SIGKILL),
+        };
+        let mut timed_out = false;
+        let mut interrupt = self.interrupt.clone();
+        loop {
+            tokio::select! {
+                result = &mut task => return Ok(result?),
+                _ = &mut deadline, if !timed_out => {
+                    kill();
+                    timed_out = true;
+                }
+                _ = next_interrupt(&mut interrupt) => kill(),
+            }
+        }
+    }
+
+    /// The command ready to spawn, with the program and arguments it runs.
+    fn command(
+        &self,
+        cgroup: Option<&RunCgroup>,
+    ) -> Result<(std::process::Command, String, Vec<String>), Error> {
         let parts = shell_words::split(&self.command)?;
-        if parts.is_empty() {
+        // Leading KEY=VAL words set variables for this command only, as in a shell
+        let assignments = parts.iter().take_while(|p| is_assignment(p)).count();
+        if parts.len() == assignments {
             return Err("Empty command".into());
         }
 
-        let program = parts[0].clone();
-        let args = parts[1..].to_vec();
+        let program = parts[assignments].clone();
+        let args = parts[assignments + 1..].to_vec();
+        let inline_env = parts[..assignments]
+            .iter()
+            .filter_map(|assignment| assignment.split_once('='));
 
-        let child = Command::new(program.clone())
+        let stdin = match &self.input {
+            Some(input) => std::fs::File::open(input)
+                .map_err(|e| format!("Failed to open input {}: {}", input.display(), e))?
+                .into(),
+            None => Stdio::null(),
+        };
+        let stdout = match &self.output {
+            OutputMode::Null => Stdio::null(),
+            OutputMode::Pipe => Stdio::piped(),
+            OutputMode::Inherit => Stdio::inherit(),
+            OutputMode::File(path) => std::fs::File::create(path)
+                .map_err(|e| format!("Failed to create output {}: {}", path.display(), e))?
+                .into(),
+        };
+
+        let mut command = std::process::Command::new(program.clone());
+        if self.env_clear {
+            command.env_clear();
+        }
+        command
             .stderr(Stdio::inherit())
-            .stdin(Stdio::null())
-            .stdout(Stdio::null())
+            .stdin(stdin)
+            .stdout(stdout)
             .args(&args)
-            .spawn()
-            .map_err(|e| format!("Failed to spawn process: {}", e))?;
+            .envs(self.env.iter().map(|(k, v)| (k.as_str(), v.as_str())))
+            .envs(inline_env);
+        if let Some(cwd) = &self.cwd {
+            command.current_dir(cwd);
+        }
+        // Its own process group keeps a terminal Ctrl-C from reaching the command
+        // directly, so lens decides what to forward and can reach its children
+        command.process_group(0);
+        if let Some(cgroup) = cgroup {
+            // Joining before exec means not even the first instruction goes unaccounted
+            let join = cgroup.joiner()?;
+            // SAFETY: the closure only makes a single write syscall
+            unsafe {
+                command.pre_exec(join);
+            }
+        }
+        if !self.scheduling.is_default() {
+            let apply = self.scheduling.applier()?;
+            // SAFETY: the closure only makes syscalls on prepared values
+            unsafe {
+                command.pre_exec(apply);
+            }
+        }
+        if !self.limits.rlimits.is_empty() {
+            let apply = self.limits.rlimit_applier();
+            // SAFETY: the closure only makes setrlimit syscalls on prepared values
+            unsafe {
+                command.pre_exec(apply);
+            }
+        }
+        Ok((command, program, args))
+    }
 
-        let mut process_info = ProcessInfo::new(program, args);
-        self.run(child, &mut process_info);
-        Ok(process_info.clone())
+    /// Failures before exec only come back as an errno, so name the likely cause.
+    fn spawn_error(&self, e: std::io::Error) -> String {
+        match e.raw_os_error() {
+            Some(libc::EPERM | libc::EINVAL) if !self.scheduling.is_default() => {
+                format!("Failed to apply {}: {}", self.scheduling, e)
+            }
+            Some(libc::EPERM | libc::EINVAL) if !self.limits.rlimits.is_empty() => {
+                format!("Failed to apply rlimits: {}", e)
+            }
+            _ => format!("Failed to spawn process: {}", e),
+        }
     }
 
-    fn run(&self, mut child: Child, process_info: &mut ProcessInfo) {
-        process_info.pid = child.id().unwrap_or(0);
+    async fn run(
+        &self,
+        child: Child,
+        process_info: &mut ProcessInfo,
+        mut monitor: impl Monitor,
+    ) -> std::io::Result<()> {
+        process_info.pid = child.id();
         let start_time = std::time::Instant::now();
         process_info.start_time = Some(start_time);
+        process_info.scheduling = SchedulingState::of(process_info.pid);
 
-        let status = tokio::spawn(async move {
-            child
-                .wait()
-                .await
-                .expect("child process encountered an error");
-        });
+        let mut ticks = tokio::time::interval(SAMPLE_INTERVAL);
+        ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+        let deadline = async {
+            match self.timeout {
+                Some(timeout) => tokio::time::sleep(timeout).await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::pin!(deadline);
+        let mut interrupt = self.interrupt.clone();
+        let pgid = process_info.pid;
+        let mut exit = tokio::task::spawn_blocking(move || wait(child));
 
-        let mut monitor = PollBased::new(process_info.pid);
-        loop {
-            if status.is_finished() {
-                let end_time = std::time::Instant::now();
-                process_info.end_time = Some(end_time);
-                process_info.duration = Some(end_time.duration_since(start_time));
-                break;
+        let (status, max_rss_kb) = loop {
+            tokio::select! {
+                exit = &mut exit => break exit??,
+                _ = ticks.tick() => monitor.scan(process_info),
+                _ = &mut deadline, if process_info.outcome != Outcome::TimedOut => {
+                    interrupt::signal_group(pgid, libc::SIGKILL);
+                    process_info.outcome = Outcome::TimedOut;
+                }
+                state = next_interrupt(&mut interrupt) => {
+                    let signal = if state.count > 1 { libc::SIGKILL } else { state.signal };
+                    interrupt::signal_group(pgid, signal);
+                    process_info.outcome = Outcome::Interrupted;
+                }
             }
-            monitor.scan(process_info);
+        };
+
+        let end_time = std::time::Instant::now();
+        process_info.end_time = Some(end_time);
+        process_info.duration = Some(end_time.duration_since(start_time));
+        process_info.status = status.code();
+        process_info.stat.max_rss_kb = Some(max_rss_kb);
+        // A cgroup still holds the final totals, children included
+        if self.monitor == MonitorKind::Cgroup {
+            monitor.read_cpu_usage(process_info);
+            monitor.read_disk_usage(process_info);
+        }
+        Ok(())
+    }
+}
+
+/// Waits for the command to exit and reaps it, returning its status and the
+/// peak RSS the kernel recorded for it, in KB. Unlike tokio's wait this keeps
+/// the resource usage, but it blocks.
+fn wait(child: Child) -> std::io::Result<(ExitStatus, u64)> {
+    let pid = child.id() as libc::pid_t;
+    let mut status = 0;
+    // SAFETY: all-zero is a valid rusage
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    loop {
+        // SAFETY: status and usage are valid for writes
+        if unsafe { libc::wait4(pid, &mut status, 0, &mut usage) } == pid {
+            break;
+        }
+        let error = std::io::Error::last_os_error();
+        if error.kind() != std::io::ErrorKind::Interrupted {
+            return Err(error);
         }
     }
+    Ok((ExitStatus::from_raw(status), usage.ru_maxrss as u64))
+}
+
+/// Kills the command's process group if `execute` is dropped before it exits.
+/// The blocking wait then reaps it.
+struct KillOnDrop(u32);
+
+impl Drop for KillOnDrop {
+    fn drop(&mut self) {
+        // The unreaped leader keeps the group id from being reused until the guard is forgotten
+        interrupt::signal_group(self.0, libc::SIGKILL);
+    }
+}
+
+async fn next_interrupt(interrupt: &mut Option<Interrupt>) -> interrupt::State {
+    match interrupt {
+        Some(interrupt) => interrupt.next().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Whether a word is a `NAME=value` variable assignment.
+pub(crate) fn is_assignment(word: &str) -> bool {
+    let Some((name, _)) = word.split_once('=') else {
+        return false;
+    };
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
 }
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -66,17 +459,16 @@ mod tests {
     #[tokio::test]
     async fn test_execute() {
         let executor = Executor::new("echo 'Hello, world!'".to_string());
-        let process_info = executor.execute().unwrap();
+        let process_info = executor.execute().await.unwrap();
         assert_eq!(process_info.command, "echo");
         assert_eq!(process_info.args, vec!["Hello, world!"]);
         assert_eq!(process_info.status, Some(0));
-        std::process::exit(0);
     }
 
     #[tokio::test]
     async fn test_execute_with_args() {
         let executor = Executor::new("echo 'Hello, world!'".to_string());
-        let process_info = executor.execute().unwrap();
+        let process_info = executor.execute().await.unwrap();
         assert_eq!(process_info.command, "echo");
         assert_eq!(process_info.args, vec!["Hello, world!"]);
         assert_eq!(process_info.status, Some(0));
@@ -88,17 +480,176 @@ mod tests {
         assert!(start_elapsed > 0);
         assert!(end_elapsed > 0);
         assert!(duration > 0);
-        std::process::exit(0);
+    }
+
+    #[tokio::test]
+    async fn test_execute_samples_while_running() {
+        let executor = Executor::new("sleep 0.1".to_string());
+        let process_info = executor.execute().await.unwrap();
+        let samples = &process_info.stat.memory_kb;
+        assert!(samples.len() > 5, "only {} samples", samples.len());
+        assert!(samples.windows(2).all(|w| w[0].elapsed < w[1].elapsed));
+    }
+
+    #[tokio::test]
+    async fn test_execute_records_peak_rss() {
+        // Over before a single sample could catch its peak
+        let process_info = Executor::new("true".to_string()).execute().await.unwrap();
+        assert!(process_info.stat.max_rss_kb.is_some_and(|kb| kb > 0));
+        assert!(process_info.stat.peak_memory_kb() > 0);
+    }
+
+    #[tokio::test]
+    async fn test_execute_with_scheduling() {
+        // Lowering priority and pinning to a CPU we already have needs no privileges
+        let cpu = SchedulingState::of(std::process::id()).unwrap().cpus[0];
+        let executor = Executor::new("sleep 0.05".to_string()).scheduling(Scheduling {
+            cpus: Some(vec![cpu]),
+            nice: Some(19),
+            policy: Some("batch".parse().unwrap()),
+            io_priority: Some("idle".parse().unwrap()),
+        });
+        let process_info = executor.execute().await.unwrap();
+        let state = process_info.scheduling.unwrap();
+        assert_eq!(state.cpus, [cpu]);
+        assert_eq!(state.nice, 19);
+        assert_eq!(state.policy, "batch");
+        assert_eq!(state.io_priority.as_deref(), Some("idle"));
+    }
+
+    #[tokio::test]
+    async fn test_execute_with_rlimit() {
+        let executor = Executor::new("sh -c 'ulimit -n'".to_string())
+            .output(OutputMode::Pipe)
+            .limits(Limits {
+                rlimits: vec!["nofile=64".parse().unwrap()],
+                ..Limits::default()
+            });
+        let process_info = executor.execute().await.unwrap();
+        assert_eq!(process_info.status, Some(0));
+        assert_eq!(
+            process_info.stdout,
+            Some(OutputDigest::of(b"64\n")),
+            "ulimit -n should print the limit"
+        );
+        assert!(process_info.limit_events.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_execute_memory_limit_oom_kill() {
+        if !crate::linux::cgroups_available(&["memory"]) {
+            eprintln!("skipping: no cgroup v2 memory controller available to lens");
+            return;
+        }
+        let executor = Executor::new(
+            "sh -c 'x=$(head -c 64000000 /dev/zero | tr \"\\0\" a); echo ${#x}'".to_string(),
+        )
+        .limits(Limits {
+            memory: Some(16 * 1024 * 1024),
+            ..Limits::default()
+        });
+        let process_info = executor.execute().await.unwrap();
+        assert_eq!(process_info.outcome, Outcome::OomKilled);
+        assert!(process_info.limit_events.unwrap().oom_kills > 0);
+    }
+
+    #[tokio::test]
+    async fn test_execute_exit_status() {
+        let executor = Executor::new("sh -c 'exit 7'".to_string());
+        let process_info = executor.execute().await.unwrap();
+        assert_eq!(process_info.status, Some(7));
+        assert_eq!(process_info.outcome, Outcome::Exited);
+    }
+
+    #[tokio::test]
+    async fn test_execute_timeout_kills_command() {
+        let executor =
+            Executor::new("sleep 5".to_string()).timeout(Some(Duration::from_millis(100)));
+        let process_info = executor.execute().await.unwrap();
+        assert_eq!(process_info.outcome, Outcome::TimedOut);
+        assert!(process_info.duration.unwrap() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_interrupt_is_forwarded_to_process_group() {
+        let (sender, interrupt) = Interrupt::manual();
+        let executor =
+            Executor::new("sh -c 'sleep 5 & wait'".to_string()).interrupt(Some(interrupt));
+        let interrupter = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            sender.send_modify(|state| {
+                state.count = 1;
+                state.signal = libc::SIGTERM;
+            });
+        });
+        let process_info = executor.execute().await.unwrap();
+        interrupter.await.unwrap();
+        assert_eq!(process_info.outcome, Outcome::Interrupted);
+        assert_eq!(process_info.status, None);
+        assert!(process_info.duration.unwrap() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_second_interrupt_kills() {
+        let (sender, interrupt) = Interrupt::manual();
+        let executor =
+            Executor::new("sh -c 'trap \"\" INT; sleep 5'".to_string()).interrupt(Some(interrupt));
+        let interrupter = tokio::spawn(async move {
+            for count in 1..=2 {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                sender.send_modify(|state| {
+                    state.count = count;
+                    state.signal = libc::SIGINT;
+                });
+            }
+        });
+        let process_info = executor.execute().await.unwrap();
+        interrupter.await.unwrap();
+        assert_eq!(process_info.outcome, Outcome::Interrupted);
+        assert!(process_info.duration.unwrap() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_dropping_execute_kills_command() {
+        let marker = std::env::temp_dir().join(format!("lens-drop-{}", std::process::id()));
+        let _ = std::fs::remove_file(&marker);
+        let executor = Executor::new(format!("sh -c 'sleep 0.3; touch {}'", marker.display()));
+        let cancelled = tokio::time::timeout(Duration::from_millis(50), executor.execute()).await;
+        assert!(cancelled.is_err());
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert!(!marker.exists());
+    }
+
+    #[tokio::test]
+    async fn test_dropping_execute_kills_grandchildren() {
+        let marker = std::env::temp_dir().join(format!("lens-drop-group-{}", std::process::id()));
+        let _ = std::fs::remove_file(&marker);
+        let executor = Executor::new(format!(
+            "sh -c '(sleep 0.3; touch {}) & wait'",
+            marker.display()
+        ));
+        let cancelled = tokio::time::timeout(Duration::from_millis(50), executor.execute()).await;
+        assert!(cancelled.is_err());
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert!(!marker.exists());
     }
 
     #[tokio::test]
     async fn test_execute_invalid_command_returns_error() {
         let executor = Executor::new("nonexistent_command_12345".to_string());
-        let result = executor.execute();
+        let result = executor.execute().await;
         assert!(
             result.is_err(),
             "Expected error for invalid command, but got Ok"
         );
-        std::process::exit(0);
+    }
+
+    #[test]
+    fn test_is_assignment() {
+        assert!(is_assignment("RUBY_YJIT_ENABLE=1"));
+        assert!(is_assignment("_X="));
+        assert!(!is_assignment("1X=2"));
+        assert!(!is_assignment("--level=9"));
+        assert!(!is_assignment("ruby"));
     }
 }
@@ -0,0 +1,253 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+/// Written to as a matter of course, so never an unexpected write.
+const DEVICES: &str = "/dev/";
+
+/// How the command tree used one path, like `ls -l` modes: `rw-` for a file
+/// opened for reading and writing, `r-x` for one executed.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FileAccess {
+    pub path: PathBuf,
+    pub read: bool,
+    pub write: bool,
+    pub exec: bool,
+    /// Successful opens; zero for files used only through inherited descriptors.
+    pub opens: u64,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+}
+
+impl FileAccess {
+    pub fn mode(&self) -> String {
+        [(self.read, 'r'), (self.write, 'w'), (self.exec, 'x')]
+            .iter()
+            .map(|&(set, letter)| if set { letter } else { '-' })
+            .collect()
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes_read + self.bytes_written
+    }
+}
+
+/// Every path a traced run opened, read, wrote or executed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileReport {
+    /// Where the command ran, which writes are expected under.
+    pub cwd: PathBuf,
+    /// Most bytes first.
+    pub files: Vec<FileAccess>,
+}
+
+impl FileReport {
+    /// Files opened for writing outside the working directory, devices aside.
+    pub fn writes_outside_cwd(&self) -> impl Iterator<Item = &FileAccess> {
+        self.files.iter().filter(|file| {
+            file.write && !file.path.starts_with(&self.cwd) && !file.path.starts_with(DEVICES)
+        })
+    }
+}
+
+/// What a syscall does to files, by the argument holding each descriptor.
+enum FileOp {
+    Open,
+    Read(usize),
+    Write(usize),
+    /// sendfile and friends: read from one descriptor, write to another.
+    Copy {
+        from: usize,
+        to: usize,
+    },
+}
+
+fn file_op(nr: u64) -> Option<FileOp> {
+    let nr = nr as libc::c_long;
+    #[cfg(target_arch = "x86_64")]
+    if nr == libc::SYS_open || nr == libc::SYS_creat {
+        return Some(FileOp::Open);
+    }
+    Some(match nr {
+        libc::SYS_openat | libc::SYS_openat2 => FileOp::Open,
+        libc::SYS_read
+        | libc::SYS_pread64
+        | libc::SYS_readv
+        | libc::SYS_preadv
+        | libc::SYS_preadv2 => FileOp::Read(0),
+        libc::SYS_write
+        | libc::SYS_pwrite64
+        | libc::SYS_writev
+        | libc::SYS_pwritev
+        | libc::SYS_pwritev2 => FileOp::Write(0),
+        libc::SYS_sendfile => FileOp::Copy { from: 1, to: 0 },
+        libc::SYS_copy_file_range | libc::SYS_splice => FileOp::Copy { from: 0, to: 2 },
+        _ => return None,
+    })
+}
+
+/// Builds a [`FileReport`] from the syscall exits of a trace. Paths come from
+/// /proc rather than the tracee's memory, so they are already resolved.
+pub(crate) struct FileRecorder {
+    cwd: PathBuf,
+    files: HashMap<PathBuf, FileAccess>,
+}
+
+impl FileRecorder {
+    pub(crate) fn new(cwd: PathBuf) -> Self {
+        Self {
+            // Compared against the resolved paths from /proc
+            cwd: cwd.canonicalize().unwrap_or(cwd),
+            files: HashMap::new(),
+        }
+    }
+
+    /// Records a finished syscall of task `tid`, if it touched a file.
+    pub(crate) fn syscall(&mut self, tid: i32, nr: u64, args: &[u64; 6], ret: i64) {
+        if ret < 0 {
+            return;
+        }
+        let Some(op) = file_op(nr) else {
+            return;
+        };
+        match op {
+            FileOp::Open => {
+                let fd = ret as u64;
+                if let Some(file) = self.file(tid, fd) {
+                    file.opens += 1;
+                    match open_flags(tid, fd).map(|flags| flags & libc::O_ACCMODE) {
+                        Some(libc::O_WRONLY) => file.write = true,
+                        Some(libc::O_RDWR) => (file.read, file.write) = (true, true),
+                        _ => file.read = true,
+                    }
+                }
+            }
+            FileOp::Read(fd) => self.read(tid, args[fd], ret as u64),
+            FileOp::Write(fd) => self.write(tid, args[fd], ret as u64),
+            FileOp::Copy { from, to } => {
+                self.read(tid, args[from], ret as u64);
+                self.write(tid, args[to], ret as u64);
+            }
+        }
+    }
+
+    /// Records that task `tid` now runs the program it exec'd.
+    pub(crate) fn exec(&mut self, tid: i32) {
+        if let Ok(path) = std::fs::read_link(format!("/proc/{}/exe", tid)) {
+            self.entry(path).exec = true;
+        }
+    }
+
+    pub(crate) fn finish(self) -> FileReport {
+        let mut files: Vec<FileAccess> = self.files.into_values().collect();
+        files.sort_by(|a, b| b.bytes().cmp(&a.bytes()).then(a.path.cmp(&b.path)));
+        FileReport {
+            cwd: self.cwd,
+            files,
+        }
+    }
+
+    fn read(&mut self, tid: i32, fd: u64, bytes: u64) {
+        if let Some(file) = self.file(tid, fd) {
+            file.read = true;
+            file.bytes_read += bytes;
+        }
+    }
+
+    fn write(&mut self, tid: i32, fd: u64, bytes: u64) {
+        if let Some(file) = self.file(tid, fd) {
+            file.write = true;
+            file.bytes_written += bytes;
+        }
+    }
+
+    /// The entry for what a descriptor refers to, unless that is a pipe,
+    /// socket or anything else without a path.
+    fn file(&mut self, tid: i32, fd: u64) -> Option<&mut FileAccess> {
+        let path = std::fs::read_link(format!("/proc/{}/fd/{}", tid, fd)).ok()?;
+        path.is_absolute().then(|| self.entry(path))
+    }
+
+    fn entry(&mut self, path: PathBuf) -> &mut FileAccess {
+        self.files
+            .entry(path)
+            .or_insert_with_key(|path| FileAccess {
+                path: path.clone(),
+                ..FileAccess::default()
+            })
+    }
+}
+
+/// Flags a descriptor was opened with, from the octal `flags:` of its fdinfo.
+fn open_flags(tid: i32, fd: u64) -> Option<i32> {
+    let info = std::fs::read_to_string(format!("/proc/{}/fdinfo/{}", tid, fd)).ok()?;
+    parse_fdinfo_flags(&info)
+}
+
+fn parse_fdinfo_flags(info: &str) -> Option<i32> {
+    let flags = info.lines().find_map(|line| line.strip_prefix("flags:"))?;
+    i32::from_str_radix(flags.trim(), 8).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn test_parse_fdinfo_flags() {
+        let info = "pos:\t0\nflags:\t0100001\nmnt_id:\t25\n";
+        assert_eq!(
+            parse_fdinfo_flags(info).map(|flags| flags & libc::O_ACCMODE),
+            Some(libc::O_WRONLY)
+        );
+        assert_eq!(parse_fdinfo_flags("pos:\t0\n"), None);
+    }
+
+    #[test]
+    fn test_mode_and_writes_outside_cwd() {
+        let file = |path: &str, write: bool| FileAccess {
+            path: PathBuf::from(path),
+            read: !write,
+            write,
+            ..FileAccess::default()
+        };
+        let report = FileReport {
+            cwd: PathBuf::from("/work"),
+            files: vec![
+                file("/work/out.o", true),
+                file("/etc/passwd", false),
+                file("/home/user/.cache/tool", true),
+                file("/dev/null", true),
+            ],
+        };
+        assert_eq!(report.files[0].mode(), "-w-");
+        assert_eq!(report.files[1].mode(), "r--");
+        let outside: Vec<&Path> = report
+            .writes_outside_cwd()
+            .map(|file| file.path.as_path())
+            .collect();
+        assert_eq!(outside, [Path::new("/home/user/.cache/tool")]);
+    }
+
+    #[test]
+    fn test_recorder_follows_descriptors() {
+        let path = std::env::temp_dir().join(format!("lens-files-{}", std::process::id()));
+        let mut file = std::fs::File::create(&path).unwrap();
+        let fd = std::os::fd::AsRawFd::as_raw_fd(&file) as u64;
+        let tid = std::process::id() as i32;
+        let mut recorder = FileRecorder::new(std::env::temp_dir());
+        recorder.syscall(tid, libc::SYS_openat as u64, &[0; 6], fd as i64);
+        recorder.syscall(tid, libc::SYS_write as u64, &[fd, 0, 0, 0, 0, 0], 100);
+        recorder.syscall(tid, libc::SYS_write as u64, &[fd, 0, 0, 0, 0, 0], -9);
+        std::io::Write::write_all(&mut file, b"x").unwrap();
+        let report = recorder.finish();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(report.files.len(), 1);
+        let access = &report.files[0];
+        assert_eq!(access.path, path);
+        assert_eq!(access.mode(), "-w-");
+        assert_eq!((access.opens, access.bytes_written), (1, 100));
+        assert_eq!(report.writes_outside_cwd().count(), 0);
+    }
+}
@@ -0,0 +1,256 @@
+use crate::html::escape;
+use std::collections::BTreeMap;
+
+const WIDTH: f64 = 1200.0;
+const FRAME_HEIGHT: f64 = 16.0;
+/// Room for the title above the frames.
+const TOP: f64 = 36.0;
+const FONT_SIZE: f64 = 12.0;
+/// Frames narrower than this are left out, as they could not be seen anyway.
+const MIN_WIDTH: f64 = 0.1;
+
+/// A frame and everything sampled below it, children in name order as
+/// flamegraphs put them.
+#[derive(Default)]
+struct Node {
+    samples: u64,
+    /// Share of all samples the same frame had in the profile compared against.
+    before: f64,
+    children: BTreeMap<String, Node>,
+}
+
+impl Node {
+    fn build(stacks: &BTreeMap<String, u64>) -> Node {
+        let mut root = Node::default();
+        for (stack, &count) in stacks {
+            root.samples += count;
+            let mut node = &mut root;
+            for frame in stack.split(';') {
+                node = node.children.entry(frame.to_string()).or_default();
+                node.samples += count;
+            }
+        }
+        root
+    }
+
+    /// Records the share each frame had in `before`, frames it lacks keeping 0.
+    fn compare(&mut self, before: &Node, total: f64) {
+        self.before = before.samples as f64 / total;
+        for (name, child) in &mut self.children {
+            if let Some(earlier) = before.children.get(name) {
+                child.compare(earlier, total);
+            }
+        }
+    }
+
+    fn depth(&self) -> usize {
+        self.children
+            .values()
+            .map(|c| c.depth() + 1)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+/// A self-contained flamegraph SVG of folded stacks: callers below, callees
+/// above, each frame as wide as its share of the samples. Hovering a frame
+/// shows its name and samples.
+pub fn render(title: &str, stacks: &BTreeMap<String, u64>) -> String {
+    let root = Node::build(stacks);
+    let mut svg = Svg::new(title, root.samples, root.depth());
+    svg.node("all", &root, 0.0, 0, &|name, _| hot_color(name));
+    svg.finish()
+}
+
+/// A differential flamegraph: laid out like [`render`] for `after`, with each
+/// frame colored by how its share of the samples changed from `before`, red
+/// where it grew and blue where it shrank, deeper for bigger changes.
+pub fn render_diff(
+    title: &str,
+    before: &BTreeMap<String, u64>,
+    after: &BTreeMap<String, u64>,
+) -> String {
+    let before = Node::build(before);
+    let mut root = Node::build(after);
+    root.compare(&before, before.samples.max(1) as f64);
+    let total = root.samples.max(1) as f64;
+    let mut svg = Svg::new(title, root.samples, root.depth());
+    svg.node("all", &root, 0.0, 0, &|_, node| {
+        diff_color(node.samples as f64 / total - node.before)
+    });
+    svg.finish()
+}
+
+struct Svg {
+    out: String,
+    samples: u64,
+    height: f64,
+}
+
+impl Svg {
+    fn new(title: &str, samples: u64, depth: usize) -> Self {
+        let height = TOP + (depth + 1) as f64 * FRAME_HEIGHT + 8.0;
+        let mut out = format!(
+            "<?xml version=\"1.0\" standalone=\"no\"?>\n\
+             <svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" \
+             viewBox=\"0 0 {w} {h}\" font-family=\"Verdana, sans-serif\" font-size=\"{f}\">\n\
+             <rect width=\"100%\" height=\"100%\" fill=\"#f8f8f8\"/>\n\
+             <text x=\"{x}\" y=\"22\" text-anchor=\"middle\" font-size=\"16\">{t}</text>\n",
+            w = WIDTH,
+            h = height,
+            f = FONT_SIZE,
+            x = WIDTH / 2.0,
+            t = escape(title)
+        );
+        if samples == 0 {
+            out.push_str(&format!(
+                "<text x=\"{}\" y=\"{}\" text-anchor=\"middle\">No samples</text>\n",
+                WIDTH / 2.0,
+                TOP + FRAME_HEIGHT
+            ));
+        }
+        Svg {
+            out,
+            samples,
+            height,
+        }
+    }
+
+    /// Draws a frame at `x` with `depth` frames below it, then its children.
+    fn node(
+        &mut self,
+        name: &str,
+        node: &Node,
+        x: f64,
+        depth: usize,
+        color: &dyn Fn(&str, &Node) -> String,
+    ) {
+        if self.samples == 0 {
+            return;
+        }
+        let width = node.samples as f64 / self.samples as f64 * WIDTH;
+        if width < MIN_WIDTH {
+            return;
+        }
+        let y = self.height - 8.0 - (depth + 1) as f64 * FRAME_HEIGHT;
+        let share = node.samples as f64 * 100.0 / self.samples as f64;
+        self.out.push_str(&format!(
+            "<g><title>{} ({} samples, {:.2}%)</title>\
+             <rect x=\"{:.2}\" y=\"{}\" width=\"{:.2}\" height=\"{}\" fill=\"{}\" rx=\"2\"/>",
+            escape(name),
+            node.samples,
+            share,
+            x,
+            y,
+            width,
+            FRAME_HEIGHT - 1.0,
+            color(name, node)
+        ));
+        // Verdana averages a little over half the font size per character
+        let fits = ((width - 6.0) / (FONT_SIZE * 0.6)) as usize;
+        if fits >= 3 {
+            let label = if name.chars().count() > fits {
+                format!("{}..", name.chars().take(fits - 2).collect::<String>())
+            } else {
+                name.to_string()
+            };
+            self.out.push_str(&format!(
+                "<text x=\"{:.2}\" y=\"{}\">{}</text>",
+                x + 3.0,
+                y + FRAME_HEIGHT - 4.5,
+                escape(&label)
+            ));
+        }
+        self.out.push_str("</g>\n");
+        let mut child_x = x;
+        for (child_name, child) in &node.children {
+            self.node(child_name, child, child_x, depth + 1, color);
+            child_x += child.samples as f64 / self.samples as f64 * WIDTH;
+        }
+    }
+
+    fn finish(mut self) -> String {
+        self.out.push_str("</svg>\n");
+        self.out
+    }
+}
+
+/// Warm colors as flamegraphs use, fixed per name so the same function looks
+/// the same in every graph.
+fn hot_color(name: &str) -> String {
+    // FNV-1a
+    let hash = name.bytes().fold(0xcbf29ce484222325u64, |hash, b| {
+        (hash ^ b as u64).wrapping_mul(0x100000001b3)
+    });
+    let (r, g, b) = (hash % 55, (hash >> 8) % 180, (hash >> 16) % 55);
+    format!("rgb({},{},{})", 200 + r, 50 + g, b)
+}
+
+/// White for no change, shading to red as a frame's share of samples grew
+/// by `delta` and to blue as it shrank. A change of 5 points or more is full color.
+fn diff_color(delta: f64) -> String {
+    let strength = (delta.abs() / 0.05).min(1.0);
+    let fade = (255.0 * (1.0 - strength)).round() as u8;
+    if delta > 0.0 {
+        format!("rgb(255,{},{})", fade, fade)
+    } else {
+        format!("rgb({},{},255)", fade, fade)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stacks(lines: &[(&str, u64)]) -> BTreeMap<String, u64> {
+        lines
+            .iter()
+            .map(|&(stack, count)| (stack.to_string(), count))
+            .collect()
+    }
+
+    #[test]
+    fn test_build_tree() {
+        let root = Node::build(&stacks(&[
+            ("sh;main;parse", 3),
+            ("sh;main", 1),
+            ("sh;exit", 1),
+        ]));
+        assert_eq!(root.samples, 5);
+        assert_eq!(root.depth(), 3);
+        let main = &root.children["sh"].children["main"];
+        assert_eq!(main.samples, 4);
+        assert_eq!(main.children["parse"].samples, 3);
+    }
+
+    #[test]
+    fn test_render() {
+        let svg = render(
+            "gzip <1>",
+            &stacks(&[("gzip;deflate", 3), ("gzip;main", 1)]),
+        );
+        assert!(svg.starts_with("<?xml"));
+        assert!(svg.ends_with("</svg>\n"));
+        assert!(svg.contains("gzip &lt;1&gt;"));
+        assert!(svg.contains("<title>deflate (3 samples, 75.00%)</title>"));
+        assert!(!svg.contains("<script"));
+    }
+
+    #[test]
+    fn test_render_nothing() {
+        assert!(render("idle", &BTreeMap::new()).contains("No samples"));
+    }
+
+    #[test]
+    fn test_render_diff_colors() {
+        let before = stacks(&[("a;slow", 1), ("a;fast", 1)]);
+        let after = stacks(&[("a;slow", 3), ("a;fast", 1)]);
+        let svg = render_diff("a", &before, &after);
+        // slow went from 50% to 75% of the samples, fast from 50% to 25%
+        assert!(svg.contains(&format!("fill=\"{}\"", diff_color(0.25))));
+        assert!(svg.contains(&format!("fill=\"{}\"", diff_color(-0.25))));
+        assert_eq!(diff_color(0.25), "rgb(255,0,0)");
+        assert_eq!(diff_color(-0.25), "rgb(0,0,255)");
+        assert_eq!(diff_color(0.0), "rgb(255,255,255)");
+    }
+}
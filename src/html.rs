@@ -0,0 +1,315 @@
+use crate::report::Report;
+
+/// Single page template. Tables are rendered up front, the charts are drawn
+/// client side from the embedded report JSON, with no external resources.
+const TEMPLATE: &str = r##"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>lens report</title>
+<style>
+  body { font-family: -apple-system, "Segoe UI", Helvetica, Arial, sans-serif; margin: 2rem auto; max-width: 1100px; color: #222; padding: 0 1rem; }
+  h1 { font-size: 1.6rem; margin-bottom: 0.2rem; }
+  h2 { font-size: 1.2rem; margin-top: 2rem; border-bottom: 1px solid #ddd; padding-bottom: 0.3rem; }
+  .muted { color: #777; font-size: 0.9rem; }
+  table { border-collapse: collapse; margin: 0.5rem 0 1rem; font-size: 0.9rem; }
+  th, td { text-align: left; padding: 0.25rem 0.8rem 0.25rem 0; }
+  td.num { text-align: right; font-variant-numeric: tabular-nums; }
+  thead th { border-bottom: 1px solid #ccc; }
+  .legend span { display: inline-block; margin-right: 1rem; cursor: pointer; user-select: none; }
+  .legend span.off { opacity: 0.35; }
+  .legend i { display: inline-block; width: 0.8rem; height: 0.8rem; border-radius: 2px; margin-right: 0.3rem; vertical-align: -1px; }
+  .chart { margin: 1rem 0; }
+  .chart svg { width: 100%; height: auto; overflow: visible; }
+  .chart text { font-size: 11px; fill: #555; }
+  #tooltip { position: fixed; pointer-events: none; background: rgba(30,30,30,0.9); color: #fff; padding: 0.3rem 0.5rem; border-radius: 3px; font-size: 0.8rem; display: none; white-space: pre; }
+</style>
+</head>
+<body>
+<h1>lens report</h1>
+<div class="muted">lens __LENS_VERSION__</div>
+__LENS_PARTIAL__
+
+<h2>System</h2>
+__LENS_SYSTEM__
+
+<h2>Summary</h2>
+__LENS_SUMMARY__
+
+<h2>Charts</h2>
+<div class="legend" id="legend"></div>
+<div class="chart" id="iterations"></div>
+<div class="chart" id="rss"></div>
+<div class="chart" id="cpu"></div>
+<div id="tooltip"></div>
+
+<script id="lens-data" type="application/json">__LENS_DATA__</script>
+<script>
+(function () {
+  "use strict";
+  var report = JSON.parse(document.getElementById("lens-data").textContent);
+  var palette = ["#1f77b4", "#ff7f0e", "#2ca02c", "#d62728", "#9467bd", "#8c564b", "#e377c2", "#17becf"];
+  var hidden = {};
+  var tooltip = document.getElementById("tooltip");
+  var NS = "http://www.w3.org/2000/svg";
+  var W = 1000, H = 280, M = { top: 28, right: 20, bottom: 36, left: 64 };
+
+  function el(tag, attrs, parent) {
+    var node = document.createElementNS(NS, tag);
+    for (var k in attrs) node.setAttribute(k, attrs[k]);
+    if (parent) parent.appendChild(node);
+    return node;
+  }
+
+  function ticks(max, count) {
+    var out = [];
+    for (var i = 0; i <= count; i++) out.push(max * i / count);
+    return out;
+  }
+
+  function fmt(v) {
+    if (v >= 100) return v.toFixed(0);
+    if (v >= 10) return v.toFixed(1);
+    return v.toFixed(2);
+  }
+
+  function showTip(evt, text) {
+    tooltip.textContent = text;
+    tooltip.style.display = "block";
+    tooltip.style.left = (evt.clientX + 12) + "px";
+    tooltip.style.top = (evt.clientY + 12) + "px";
+  }
+
+  function hideTip() { tooltip.style.display = "none"; }
+
+  // series: [{ name, color, points: [[x, y, tip]], line }]
+  function chart(id, title, xLabel, yLabel, series) {
+    var root = document.getElementById(id);
+    root.innerHTML = "";
+    var svg = el("svg", { viewBox: "0 0 " + W + " " + H }, root);
+    var visible = series.filter(function (s) { return !hidden[s.name]; });
+    var xMax = 0, yMax = 0;
+    visible.forEach(function (s) {
+      s.points.forEach(function (p) { xMax = Math.max(xMax, p[0]); yMax = Math.max(yMax, p[1]); });
+    });
+    xMax = xMax || 1; yMax = (yMax || 1) * 1.05;
+    var pw = W - M.left - M.right, ph = H - M.top - M.bottom;
+    function x(v) { return M.left + v / xMax * pw; }
+    function y(v) { return M.top + ph - v / yMax * ph; }
+
+    el("text", { x: M.left, y: 16, "font-weight": "bold" }, svg).textContent = title;
+    ticks(yMax, 4).forEach(function (t) {
+      el("line", { x1: M.left, x2: W - M.right, y1: y(t), y2: y(t), stroke: "#eee" }, svg);
+      el("text", { x: M.left - 6, y: y(t) + 4, "text-anchor": "end" }, svg).textContent = fmt(t);
+    });
+    ticks(xMax, 8).forEach(function (t) {
+      el("text", { x: x(t), y: H - M.bottom + 16, "text-anchor": "middle" }, svg).textContent = fmt(t);
+    });
+    el("line", { x1: M.left, x2: M.left, y1: M.top, y2: M.top + ph, stroke: "#999" }, svg);
+    el("line", { x1: M.left, x2: W - M.right, y1: M.top + ph, y2: M.top + ph, stroke: "#999" }, svg);
+    el("text", { x: M.left + pw / 2, y: H - 4, "text-anchor": "middle" }, svg).textContent = xLabel;
+    el("text", { x: 14, y: M.top + ph / 2, "text-anchor": "middle", transform: "rotate(-90 14 " + (M.top + ph / 2) + ")" }, svg).textContent = yLabel;
+
+    visible.forEach(function (s) {
+      if (s.line) {
+        var d = "";
+        s.points.forEach(function (p, i) { d += (i ? "L" : "M") + x(p[0]).toFixed(1) + " " + y(p[1]).toFixed(1); });
+        el("path", { d: d, fill: "none", stroke: s.color, "stroke-width": 1.5, "stroke-opacity": 0.7 }, svg);
+      }
+      s.points.forEach(function (p) {
+        var dot = el("circle", { cx: x(p[0]), cy: y(p[1]), r: s.line ? 6 : 4, fill: s.color, "fill-opacity": s.line ? 0 : 0.8 }, svg);
+        dot.addEventListener("mousemove", function (evt) { showTip(evt, s.name + "\n" + p[2]); });
+        dot.addEventListener("mouseleave", hideTip);
+      });
+    });
+  }
+
+  function timeline(key, scale, unit) {
+    var series = [];
+    report.commands.forEach(function (c, ci) {
+      c.iterations.forEach(function (it, ii) {
+        var points = [];
+        it.timeline.time.forEach(function (t, i) {
+          var v = it.timeline[key][i];
+          if (v !== null) points.push([t, v * scale, "run " + (ii + 1) + " at " + fmt(t) + "s: " + fmt(v * scale) + " " + unit]);
+        });
+        series.push({ name: c.name, color: palette[ci % palette.length], points: points, line: true });
+      });
+    });
+    return series;
+  }
+
+  function render() {
+    var scatter = report.commands.map(function (c, ci) {
+      return {
+        name: c.name,
+        color: palette[ci % palette.length],
+        points: c.iterations.map(function (it, i) { return [i + 1, it.wall_time, "run " + (i + 1) + ": " + it.wall_time.toFixed(4) + " s"]; }),
+        line: false
+      };
+    });
+    chart("iterations", "Wall time per iteration", "iteration", "seconds", scatter);
+    chart("rss", "Memory RSS over time", "seconds", "MB", timeline("rss_kb", 1 / 1024, "MB"));
+    chart("cpu", "CPU usage over time", "seconds", "%", timeline("cpu_percent", 1, "%"));
+  }
+
+  var legend = document.getElementById("legend");
+  report.commands.forEach(function (c, ci) {
+    var item = document.createElement("span");
+    var swatch = document.createElement("i");
+    swatch.style.background = palette[ci % palette.length];
+    item.appendChild(swatch);
+    item.appendChild(document.createTextNode(c.name));
+    item.title = "Click to show or hide";
+    item.addEventListener("click", function () {
+      hidden[c.name] = !hidden[c.name];
+      item.className = hidden[c.name] ? "off" : "";
+      render();
+    });
+    legend.appendChild(item);
+  });
+
+  render();
+})();
+</script>
+</body>
+</html>
+"##;
+
+/// Renders the report as a standalone HTML page.
+pub fn render(report: &Report) -> serde_json::Result<String> {
+    // Keep the embedded JSON from closing the surrounding script tag
+    let data = report.to_json()?.replace("</", "<\\/");
+    let partial = if report.partial {
+        "<p><strong>Partial results:</strong> lens was interrupted before every run completed.</p>"
+    } else {
+        ""
+    };
+    Ok(TEMPLATE
+        .replace("__LENS_VERSION__", &escape(&report.lens_version))
+        .replace("__LENS_PARTIAL__", partial)
+        .replace("__LENS_SYSTEM__", &system_table(report))
+        .replace("__LENS_SUMMARY__", &summary_tables(report))
+        .replace("__LENS_DATA__", &data))
+}
+
+fn system_table(report: &Report) -> String {
+    let rows = report.system.summary();
+    let mut out = String::from("<table>\n");
+    for (name, value) in rows {
+        out.push_str(&format!(
+            "<tr><th>{}</th><td>{}</td></tr>\n",
+            name,
+            escape(&value)
+        ));
+    }
+    out.push_str("</table>");
+    out
+}
+
+fn summary_tables(report: &Report) -> String {
+    let mut out = String::new();
+    for command in &report.commands {
+        let s = &command.summary;
+        out.push_str(&format!(
+            "<h3>{}</h3>\n<div class=\"muted\"><code>{}</code>, {} iterations</div>\n",
+            escape(&command.name),
+            escape(&command.command),
+            command.iterations.len()
+        ));
+        out.push_str("<table>\n<thead><tr><th>Metric</th><th>Min</th><th>Average</th><th>Max</th></tr></thead>\n");
+        let rows = [
+            (
+                "Wall time (s)",
+                s.min_duration,
+                s.average_duration,
+                s.max_duration,
+            ),
+            (
+                "User time (ticks)",
+                s.min_utime,
+                s.average_utime,
+                s.max_utime,
+            ),
+            (
+                "System time (ticks)",
+                s.min_stime,
+                s.average_stime,
+                s.max_stime,
+            ),
+            (
+                "RSS (MB)",
+                s.min_memory / 1024.0,
+                s.average_memory / 1024.0,
+                s.max_memory / 1024.0,
+            ),
+        ];
+        for (name, min, average, max) in rows {
+            out.push_str(&format!(
+                "<tr><td>{}</td><td class=\"num\">{:.3}</td><td class=\"num\">{:.3}</td><td class=\"num\">{:.3}</td></tr>\n",
+                name, min, average, max
+            ));
+        }
+        out.push_str(&format!(
+            "<tr><td>RSS final (MB)</td><td></td><td class=\"num\">{:.3}</td><td></td></tr>\n\
+             <tr><td>RSS growth (KB/s)</td><td></td><td class=\"num\">{:.1}</td><td></td></tr>\n",
+            s.final_memory / 1024.0,
+            s.memory_slope
+        ));
+        out.push_str("</table>\n");
+    }
+    out
+}
+
+pub(crate) fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::benchmark::Benchmark;
+    use crate::process::ProcessInfo;
+    use crate::system::SystemInfo;
+    use std::time::Duration;
+
+    fn create_report(command: &str) -> Report {
+        let mut process = ProcessInfo::new(String::from("ls"), vec![]);
+        process.duration = Some(Duration::from_millis(250));
+        let mut report = Report::new(SystemInfo::collect());
+        let stat = Benchmark::calculate(&process);
+        report.push(command.to_string(), command.to_string(), &[process], stat);
+        report
+    }
+
+    #[test]
+    fn test_render_is_self_contained() {
+        let html = render(&create_report("ls -l")).unwrap();
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(!html.contains("__LENS_"));
+        assert!(!html.contains("<script src"));
+        assert!(!html.contains("<link"));
+        assert!(html.contains("<h3>ls -l</h3>"));
+        assert!(html.contains("<code>ls -l</code>"));
+    }
+
+    #[test]
+    fn test_render_escapes_command() {
+        let html = render(&create_report("echo '</script><b>'")).unwrap();
+        assert!(html.contains("<h3>echo '&lt;/script&gt;&lt;b&gt;'</h3>"));
+        // Only the two real script tags may close
+        assert_eq!(html.matches("</script>").count(), 2);
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(
+            escape("a < b && \"c\""),
+            "a &lt; b &amp;&amp; &quot;c&quot;"
+        );
+    }
+}
@@ -0,0 +1,101 @@
+use tokio::signal::unix::{SignalKind, signal};
+use tokio::sync::watch;
+
+/// Interrupts received so far, and the signal that caused the last one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct State {
+    pub count: u32,
+    pub signal: i32,
+}
+
+/// Tracks SIGINT and SIGTERM so runs can wind down instead of dying with lens.
+/// The first one is forwarded to the command's process group, the second
+/// kills the group outright.
+#[derive(Debug, Clone)]
+pub struct Interrupt {
+    receiver: watch::Receiver<State>,
+}
+
+impl Interrupt {
+    /// Takes over SIGINT and SIGTERM for the rest of the process. A third
+    /// signal exits immediately, in case winding down itself hangs.
+    pub fn listen() -> std::io::Result<Self> {
+        let mut interrupts = signal(SignalKind::interrupt())?;
+        let mut terminates = signal(SignalKind::terminate())?;
+        let (sender, receiver) = watch::channel(State::default());
+        tokio::spawn(async move {
+            loop {
+                let signal = tokio::select! {
+                    Some(()) = interrupts.recv() => libc::SIGINT,
+                    Some(()) = terminates.recv() => libc::SIGTERM,
+                    else => break,
+                };
+                sender.send_modify(|state| {
+                    state.count += 1;
+                    state.signal = signal;
+                });
+                if sender.borrow().count >= 3 {
+                    std::process::exit(128 + signal);
+                }
+            }
+        });
+        Ok(Self { receiver })
+    }
+
+    /// An interrupt that can be triggered by hand, for embedding and tests.
+    pub fn manual() -> (watch::Sender<State>, Self) {
+        let (sender, receiver) = watch::channel(State::default());
+        (sender, Self { receiver })
+    }
+
+    pub fn state(&self) -> State {
+        *self.receiver.borrow()
+    }
+
+    pub fn is_interrupted(&self) -> bool {
+        self.state().count > 0
+    }
+
+    /// Waits for the next interrupt. Never completes once nothing can send one.
+    pub(crate) async fn next(&mut self) -> State {
+        match self.receiver.changed().await {
+            Ok(()) => *self.receiver.borrow_and_update(),
+            Err(_) => std::future::pending().await,
+        }
+    }
+}
+
+/// Sends a signal to every process in a group.
+pub(crate) fn signal_group(pgid: u32, signal: i32) {
+    // SAFETY: kill has no memory safety requirements
+    unsafe {
+        libc::kill(-(pgid as i32), signal);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_manual_interrupt() {
+        let (sender, mut interrupt) = Interrupt::manual();
+        assert!(!interrupt.is_interrupted());
+        sender.send_modify(|state| {
+            state.count += 1;
+            state.signal = libc::SIGINT;
+        });
+        let state = interrupt.next().await;
+        assert_eq!(state.count, 1);
+        assert_eq!(state.signal, libc::SIGINT);
+        assert!(interrupt.is_interrupted());
+    }
+
+    #[tokio::test]
+    async fn test_next_without_sender_never_completes() {
+        let (sender, mut interrupt) = Interrupt::manual();
+        drop(sender);
+        let next = tokio::time::timeout(std::time::Duration::from_millis(20), interrupt.next());
+        assert!(next.await.is_err());
+    }
+}
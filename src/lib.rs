@@ -0,0 +1,59 @@
+//! Measures the wall time, CPU time, memory and I/O of commands.
+//!
+//! [`Benchmark`] is the entry point: describe a command, run it a number of
+//! times and get back a [`Report`] with per-iteration measurements and a summary.
+
+pub mod benchmark;
+pub mod cache;
+pub mod executor;
+pub mod files;
+pub mod interrupt;
+pub mod limits;
+pub mod linux;
+pub mod monitor;
+pub mod output;
+pub mod process;
+pub mod profile;
+pub mod report;
+pub mod scheduling;
+pub mod syscalls;
+pub mod system;
+pub mod threads;
+
+mod symbols;
+
+// Support for the lens command line tool, not part of the library API.
+#[doc(hidden)]
+pub mod baseline;
+#[doc(hidden)]
+pub mod config;
+#[doc(hidden)]
+pub mod flamegraph;
+#[doc(hidden)]
+pub mod html;
+#[doc(hidden)]
+pub mod plan;
+#[doc(hidden)]
+pub mod plot;
+#[doc(hidden)]
+pub mod preflight;
+#[doc(hidden)]
+pub mod schedule;
+#[doc(hidden)]
+pub mod stats;
+#[doc(hidden)]
+pub mod threshold;
+#[doc(hidden)]
+pub mod units;
+
+pub use benchmark::{Benchmark, BenchmarkStat, ConcurrencyStat};
+pub use cache::CacheMode;
+pub use executor::Executor;
+pub use interrupt::Interrupt;
+pub use limits::Limits;
+pub use monitor::{Monitor, MonitorKind};
+pub use output::{OutputDigest, OutputMode};
+pub use process::{Outcome, ProcessInfo};
+pub use report::{CommandReport, IterationReport, Report, Timeline};
+pub use scheduling::Scheduling;
+pub use system::SystemInfo;
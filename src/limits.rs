@@ -0,0 +1,202 @@
+use crate::units;
+use serde::{Deserialize, Serialize};
+use std::io;
+
+/// How an rlimit's value is written.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RlimitUnit {
+    Bytes,
+    Seconds,
+    Count,
+}
+
+/// The rlimits that can be set, by the name `prlimit` uses.
+const RLIMITS: [(&str, libc::c_int, RlimitUnit); 9] = [
+    ("as", libc::RLIMIT_AS as libc::c_int, RlimitUnit::Bytes),
+    ("core", libc::RLIMIT_CORE as libc::c_int, RlimitUnit::Bytes),
+    ("cpu", libc::RLIMIT_CPU as libc::c_int, RlimitUnit::Seconds),
+    ("data", libc::RLIMIT_DATA as libc::c_int, RlimitUnit::Bytes),
+    (
+        "fsize",
+        libc::RLIMIT_FSIZE as libc::c_int,
+        RlimitUnit::Bytes,
+    ),
+    (
+        "memlock",
+        libc::RLIMIT_MEMLOCK as libc::c_int,
+        RlimitUnit::Bytes,
+    ),
+    (
+        "nofile",
+        libc::RLIMIT_NOFILE as libc::c_int,
+        RlimitUnit::Count,
+    ),
+    (
+        "nproc",
+        libc::RLIMIT_NPROC as libc::c_int,
+        RlimitUnit::Count,
+    ),
+    (
+        "stack",
+        libc::RLIMIT_STACK as libc::c_int,
+        RlimitUnit::Bytes,
+    ),
+];
+
+/// A soft and hard resource limit, e.g. `nofile=1024`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rlimit {
+    pub name: String,
+    /// `None` for unlimited.
+    pub value: Option<u64>,
+}
+
+impl std::str::FromStr for Rlimit {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let (name, value) = text
+            .split_once('=')
+            .ok_or_else(|| format!("expected NAME=VALUE, got '{}'", text))?;
+        let Some((_, _, unit)) = RLIMITS.iter().find(|(known, _, _)| *known == name) else {
+            let names: Vec<&str> = RLIMITS.iter().map(|(name, _, _)| *name).collect();
+            return Err(format!(
+                "unknown rlimit '{}', expected one of: {}",
+                name,
+                names.join(", ")
+            ));
+        };
+        let value = match (value, unit) {
+            ("unlimited", _) => None,
+            (value, RlimitUnit::Bytes) => Some(units::parse_bytes(value)?),
+            (value, RlimitUnit::Seconds) => Some(units::parse_duration(value)?.as_secs()),
+            (value, RlimitUnit::Count) => Some(
+                value
+                    .parse()
+                    .map_err(|_| format!("expected a number, got '{}'", value))?,
+            ),
+        };
+        Ok(Rlimit {
+            name: name.to_string(),
+            value,
+        })
+    }
+}
+
+/// Caps on what the command may use, to mimic the container it runs in in
+/// production. All but the rlimits are enforced through a cgroup per run.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Limits {
+    /// Bytes, for `memory.max`. Swap is disabled along with it.
+    pub memory: Option<u64>,
+    /// CPUs worth of time per period, for `cpu.max`.
+    pub cpu_quota: Option<f64>,
+    /// Processes and threads, for `pids.max`.
+    pub pids: Option<u64>,
+    pub rlimits: Vec<Rlimit>,
+}
+
+impl Limits {
+    pub fn needs_cgroup(&self) -> bool {
+        self.memory.is_some() || self.cpu_quota.is_some() || self.pids.is_some()
+    }
+
+    /// Sets the rlimits on the calling process. Everything is looked up up
+    /// front since nothing may allocate between fork and exec.
+    pub(crate) fn rlimit_applier(&self) -> impl Fn() -> io::Result<()> + use<> {
+        let rlimits: Vec<(libc::c_int, libc::rlimit)> = self
+            .rlimits
+            .iter()
+            .filter_map(|rlimit| {
+                let (_, resource, _) = RLIMITS.iter().find(|(name, _, _)| *name == rlimit.name)?;
+                let value = rlimit.value.unwrap_or(libc::RLIM_INFINITY);
+                Some((
+                    *resource,
+                    libc::rlimit {
+                        rlim_cur: value,
+                        rlim_max: value,
+                    },
+                ))
+            })
+            .collect();
+        move || {
+            for (resource, limit) in &rlimits {
+                // SAFETY: setrlimit only reads the struct it is given
+                if unsafe { libc::setrlimit(*resource as _, limit) } != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Parses a CPU quota in CPUs, such as `1.5`.
+pub fn parse_cpu_quota(text: &str) -> Result<f64, String> {
+    match text.parse::<f64>() {
+        Ok(quota) if quota > 0.0 && quota.is_finite() => Ok(quota),
+        _ => Err(format!(
+            "expected a positive number of CPUs, got '{}'",
+            text
+        )),
+    }
+}
+
+/// What the cgroup limits did to a run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LimitEvents {
+    /// Processes killed for exceeding the memory limit, from `memory.events`.
+    pub oom_kills: u64,
+    /// Periods the CPU quota ran out in, from `cpu.stat`.
+    pub throttled_periods: u64,
+    pub throttled_usec: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rlimit() {
+        assert_eq!(
+            "nofile=1024".parse(),
+            Ok(Rlimit {
+                name: String::from("nofile"),
+                value: Some(1024)
+            })
+        );
+        assert_eq!(
+            "as=512M".parse::<Rlimit>().unwrap().value,
+            Some(512 * 1024 * 1024)
+        );
+        assert_eq!("cpu=1m".parse::<Rlimit>().unwrap().value, Some(60));
+        assert_eq!("stack=unlimited".parse::<Rlimit>().unwrap().value, None);
+        assert!("nofile".parse::<Rlimit>().is_err());
+        assert!("files=10".parse::<Rlimit>().is_err());
+        assert!("nofile=many".parse::<Rlimit>().is_err());
+    }
+
+    #[test]
+    fn test_parse_cpu_quota() {
+        assert_eq!(parse_cpu_quota("1.5"), Ok(1.5));
+        assert!(parse_cpu_quota("0").is_err());
+        assert!(parse_cpu_quota("-1").is_err());
+        assert!(parse_cpu_quota("two").is_err());
+    }
+
+    #[test]
+    fn test_rlimit_applier_in_child() {
+        let limits = Limits {
+            rlimits: vec!["nofile=64".parse().unwrap()],
+            ..Limits::default()
+        };
+        let mut command = std::process::Command::new("sh");
+        command.args(["-c", "ulimit -n"]);
+        // SAFETY: the closure only makes setrlimit syscalls
+        unsafe {
+            std::os::unix::process::CommandExt::pre_exec(&mut command, limits.rlimit_applier());
+        }
+        let output = command.output().unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "64");
+    }
+}
@@ -1,106 +1,601 @@
+use crate::limits::{LimitEvents, Limits};
 use crate::monitor::Monitor;
 use crate::process::ProcessInfo;
-use crate::process::Stat;
+use crate::process::{Sample, ThreadTicks};
+use crate::threads::WaitState;
 
 use std::fs::File;
-use std::io::Read;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 
+/// USER_HZ, the unit of the CPU times in /proc/<pid>/stat, read once from
+/// `sysconf(_SC_CLK_TCK)`.
+pub fn clock_ticks_per_sec() -> u64 {
+    static TICKS: OnceLock<u64> = OnceLock::new();
+    *TICKS.get_or_init(|| {
+        // SAFETY: sysconf has no preconditions
+        let ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+        if ticks > 0 { ticks as u64 } else { 100 }
+    })
+}
+
+/// How often the threads are read. Each one is a file of its own, too many to
+/// read at every sample for a runtime with hundreds of threads.
+const THREAD_SAMPLE_INTERVAL: Duration = Duration::from_millis(25);
+
+const CGROUP_V2: &str = "/sys/fs/cgroup";
+/// Where systemd mounts the v2 hierarchy on hybrid v1/v2 hosts.
 const CGROUP_UNIFIED: &str = "/sys/fs/cgroup/unified";
-const CGROUP_V2: &str = "/sys/fs/cgroup/cgroup";
 const CGROUP_CONTROLLER_PATH: &str = "cgroup.controllers";
-const MAX_PATH: usize = 256;
-const MAX_BUFFER: usize = 4096;
 
-struct CgroupV2 {
-    pid: u32,
+/// Root of the mounted cgroup v2 hierarchy, if there is one.
+fn cgroup_root() -> Option<&'static Path> {
+    [CGROUP_V2, CGROUP_UNIFIED]
+        .into_iter()
+        .map(Path::new)
+        .find(|root| root.join(CGROUP_CONTROLLER_PATH).exists())
+}
+
+/// The v2 cgroup of a process, from the `0::/path` line of /proc/<pid>/cgroup.
+fn cgroup_of(pid: &str) -> Option<PathBuf> {
+    let cgroups = std::fs::read_to_string(format!("/proc/{}/cgroup", pid)).ok()?;
+    let path = cgroups.lines().find_map(|line| line.strip_prefix("0::"))?;
+    Some(cgroup_root()?.join(path.trim_start_matches('/')))
+}
+
+/// Period of the CPU quota lens sets, the kernel's default.
+const CPU_PERIOD_USEC: u64 = 100_000;
+
+/// Where cgroup v1 controllers are mounted, one directory each.
+const CGROUP_V1: &str = "/sys/fs/cgroup";
+/// Limits at or above this are the kernel's way of saying unlimited.
+const CGROUP_UNLIMITED_BYTES: u64 = 1 << 60;
+
+/// The v1 cgroup of lens itself for a controller, and where that controller
+/// is mounted. Inside a container the path often refers to the host's view, in
+/// which case the container's own cgroup is the mount point itself.
+fn v1_cgroup(controller: &str) -> Option<(PathBuf, PathBuf)> {
+    let cgroups = std::fs::read_to_string("/proc/self/cgroup").ok()?;
+    let path = cgroups.lines().find_map(|line| {
+        let mut fields = line.splitn(3, ':').skip(1);
+        let controllers = fields.next()?;
+        let path = fields.next()?;
+        controllers
+            .split(',')
+            .any(|c| c == controller)
+            .then_some(path)
+    })?;
+    let mount = Path::new(CGROUP_V1).join(controller);
+    let leaf = mount.join(path.trim_start_matches('/'));
+    Some((if leaf.exists() { leaf } else { mount.clone() }, mount))
+}
+
+/// Limits along a cgroup and its ancestors up to the root, any of which can
+/// constrain lens.
+fn ancestor_limits<T>(leaf: &Path, root: &Path, limit: impl Fn(&Path) -> Option<T>) -> Vec<T> {
+    leaf.ancestors()
+        .take_while(|dir| dir.starts_with(root))
+        .filter_map(limit)
+        .collect()
+}
+
+fn read_trimmed(path: &Path) -> Option<String> {
+    std::fs::read_to_string(path)
+        .ok()
+        .map(|text| text.trim().to_string())
+}
+
+/// CPUs worth of time per period allowed by a v2 `cpu.max`, e.g. `50000 100000`.
+fn parse_cpu_max(text: &str) -> Option<f64> {
+    let (quota, period) = text.trim().split_once(' ')?;
+    cpu_quota(quota.parse().ok()?, period.parse().ok()?)
+}
+
+/// CPUs worth of time from a CFS quota and period, negative meaning unlimited.
+fn cpu_quota(quota: i64, period: i64) -> Option<f64> {
+    (quota > 0 && period > 0).then(|| quota as f64 / period as f64)
+}
+
+/// A memory limit in bytes from `memory.max` or `memory.limit_in_bytes`.
+fn parse_memory_limit(text: &str) -> Option<u64> {
+    let bytes: u64 = text.trim().parse().ok()?;
+    (bytes < CGROUP_UNLIMITED_BYTES).then_some(bytes)
+}
+
+/// The tightest CPU limit, in CPUs, of the cgroups lens runs in.
+pub(crate) fn cgroup_cpu_limit() -> Option<f64> {
+    let mut limits = Vec::new();
+    if let (Some(leaf), Some(root)) = (cgroup_of("self"), cgroup_root()) {
+        limits.extend(ancestor_limits(&leaf, root, |dir| {
+            parse_cpu_max(&read_trimmed(&dir.join("cpu.max"))?)
+        }));
+    }
+    if let Some((leaf, mount)) = v1_cgroup("cpu") {
+        limits.extend(ancestor_limits(&leaf, &mount, |dir| {
+            let quota = read_trimmed(&dir.join("cpu.cfs_quota_us"))?;
+            let period = read_trimmed(&dir.join("cpu.cfs_period_us"))?;
+            cpu_quota(quota.parse().ok()?, period.parse().ok()?)
+        }));
+    }
+    limits.into_iter().min_by(|a, b| a.partial_cmp(b).unwrap())
+}
+
+/// The tightest memory limit, in bytes, of the cgroups lens runs in.
+pub(crate) fn cgroup_memory_limit() -> Option<u64> {
+    let mut limits = Vec::new();
+    if let (Some(leaf), Some(root)) = (cgroup_of("self"), cgroup_root()) {
+        limits.extend(ancestor_limits(&leaf, root, |dir| {
+            parse_memory_limit(&read_trimmed(&dir.join("memory.max"))?)
+        }));
+    }
+    if let Some((leaf, mount)) = v1_cgroup("memory") {
+        limits.extend(ancestor_limits(&leaf, &mount, |dir| {
+            parse_memory_limit(&read_trimmed(&dir.join("memory.limit_in_bytes"))?)
+        }));
+    }
+    limits.into_iter().min()
+}
+
+/// A value from a flat keyed cgroup file such as `memory.events`.
+fn flat_keyed(text: &str, key: &str) -> Option<u64> {
+    text.lines().find_map(|line| {
+        let (name, value) = line.split_once(' ')?;
+        (name == key).then(|| value.trim().parse().ok())?
+    })
+}
+
+/// Periods in which a cgroup used up its CPU quota, from `nr_throttled` in
+/// `cpu.stat`.
+fn parse_nr_throttled(text: &str) -> Option<u64> {
+    flat_keyed(text, "nr_throttled")
+}
+
+/// The resident memory of a cgroup from its `memory.stat`: anonymous memory
+/// plus mapped files, the cgroup's counterpart of VmRSS. `memory.current`
+/// would also count page cache and kernel memory.
+fn resident_bytes(memory_stat: &str) -> Option<u64> {
+    Some(flat_keyed(memory_stat, "anon")? + flat_keyed(memory_stat, "file_mapped")?)
+}
+
+/// How often the cgroup lens runs in was throttled for exceeding its CPU
+/// quota while it watched for `window`. The kernel's count covers the
+/// cgroup's whole life, so only its change says anything about now.
+pub(crate) fn cgroup_throttled(window: Duration) -> Option<u64> {
+    let before = nr_throttled()?;
+    std::thread::sleep(window);
+    Some(nr_throttled()?.saturating_sub(before))
+}
+
+fn nr_throttled() -> Option<u64> {
+    let v2 = cgroup_of("self").map(|cgroup| cgroup.join("cpu.stat"));
+    let v1 = v1_cgroup("cpu").map(|(cgroup, _)| cgroup.join("cpu.stat"));
+    [v2, v1]
+        .into_iter()
+        .flatten()
+        .find_map(|stat| parse_nr_throttled(&std::fs::read_to_string(stat).ok()?))
+}
+
+/// A cgroup created for a single run, so that everything the command forks is
+/// accounted to it. Anything still running in it is killed when it is dropped.
+pub(crate) struct RunCgroup {
+    path: PathBuf,
+    procs: File,
+}
+
+impl RunCgroup {
+    /// Creates an empty cgroup below the one lens itself runs in.
+    pub(crate) fn create() -> std::io::Result<Self> {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let parent = cgroup_of("self").ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "no cgroup v2 hierarchy is mounted",
+            )
+        })?;
+        let path = parent.join(format!(
+            "lens-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir(&path)?;
+        let procs = match File::options().write(true).open(path.join("cgroup.procs")) {
+            Ok(procs) => procs,
+            Err(e) => {
+                let _ = std::fs::remove_dir(&path);
+                return Err(e);
+            }
+        };
+        Ok(Self { path, procs })
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns a closure that moves the calling process into the cgroup. It
+    /// makes a single write, so it is safe to run between fork and exec.
+    pub(crate) fn joiner(&self) -> std::io::Result<impl Fn() -> std::io::Result<()> + use<>> {
+        let procs = self.procs.try_clone()?;
+        Ok(move || (&procs).write_all(b"0"))
+    }
+
+    /// Applies the cgroup limits, before anything has joined. Controllers not
+    /// yet enabled for the cgroup are enabled on the way.
+    pub(crate) fn limit(&self, limits: &Limits) -> std::io::Result<()> {
+        let mut files = Vec::new();
+        if let Some(memory) = limits.memory {
+            files.push(("memory", "memory.max", memory.to_string()));
+        }
+        if let Some(quota) = limits.cpu_quota {
+            let quota = (quota * CPU_PERIOD_USEC as f64).round() as u64;
+            files.push(("cpu", "cpu.max", format!("{} {}", quota, CPU_PERIOD_USEC)));
+        }
+        if let Some(pids) = limits.pids {
+            files.push(("pids", "pids.max", pids.to_string()));
+        }
+        if let Some(parent) = self.path.parent() {
+            for (controller, _, _) in &files {
+                // Fails when already enabled, or when the parent is not allowed
+                // to have both processes and children; the write below tells
+                let _ = std::fs::write(
+                    parent.join("cgroup.subtree_control"),
+                    format!("+{}", controller),
+                );
+            }
+        }
+        for (controller, file, value) in &files {
+            // The files of a disabled controller are simply missing
+            let path = self.path.join(file);
+            if !path.exists() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::Unsupported,
+                    format!("the {} controller is not available to lens", controller),
+                ));
+            }
+            std::fs::write(path, value)
+                .map_err(|e| std::io::Error::new(e.kind(), format!("{}: {}", file, e)))?;
+        }
+        if limits.memory.is_some() {
+            // Without this the limit only pushes the command into swap
+            let _ = std::fs::write(self.path.join("memory.swap.max"), "0");
+        }
+        Ok(())
+    }
+
+    /// What the limits did to the commands in the cgroup so far.
+    pub(crate) fn limit_events(&self) -> LimitEvents {
+        let memory_events = read_trimmed(&self.path.join("memory.events")).unwrap_or_default();
+        let cpu_stat = read_trimmed(&self.path.join("cpu.stat")).unwrap_or_default();
+        LimitEvents {
+            oom_kills: flat_keyed(&memory_events, "oom_kill").unwrap_or(0),
+            throttled_periods: parse_nr_throttled(&cpu_stat).unwrap_or(0),
+            throttled_usec: flat_keyed(&cpu_stat, "throttled_usec").unwrap_or(0),
+        }
+    }
+}
+
+impl Drop for RunCgroup {
+    fn drop(&mut self) {
+        let _ = std::fs::write(self.path.join("cgroup.kill"), "1");
+        // The kill is asynchronous, so the group may take a moment to empty
+        for _ in 0..50 {
+            if std::fs::remove_dir(&self.path).is_ok() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(2));
+        }
+    }
+}
+
+/// Whether a cgroup can be created below the one lens runs in, with each of
+/// `controllers` enabled for it. Tests that need cgroups check this first, so
+/// they skip on hosts without them instead of passing unnoticed.
+#[cfg(test)]
+pub(crate) fn cgroups_available(controllers: &[&str]) -> bool {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let Some(parent) = cgroup_of("self") else {
+        return false;
+    };
+    let probe = parent.join(format!(
+        "lens-probe-{}-{}",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ));
+    if std::fs::create_dir(&probe).is_err() {
+        return false;
+    }
+    for controller in controllers {
+        let _ = std::fs::write(
+            parent.join("cgroup.subtree_control"),
+            format!("+{}", controller),
+        );
+    }
+    let enabled = read_trimmed(&probe.join(CGROUP_CONTROLLER_PATH)).unwrap_or_default();
+    let _ = std::fs::remove_dir(&probe);
+    controllers
+        .iter()
+        .all(|controller| enabled.split(' ').any(|c| c == *controller))
+}
+
+/// Reads the accounting files of the command's cgroup. Falls back to polling
+/// /proc for what the cgroup does not track, such as network traffic or memory
+/// when the memory controller is not enabled for it.
+pub(crate) struct CgroupV2 {
+    path: Option<PathBuf>,
+    fallback: PollBased,
+}
+
+impl CgroupV2 {
+    pub(crate) fn at(pid: u32, path: PathBuf) -> Self {
+        Self {
+            path: Some(path),
+            fallback: PollBased::new(pid),
+        }
+    }
+
+    fn read(&self, file: &str) -> Option<String> {
+        std::fs::read_to_string(self.path.as_ref()?.join(file)).ok()
+    }
+}
+
+impl Monitor for CgroupV2 {
+    fn new(pid: u32) -> Self {
+        Self {
+            path: cgroup_of(&pid.to_string()),
+            fallback: PollBased::new(pid),
+        }
+    }
+
+    fn read_cpu_usage(&mut self, process_info: &mut ProcessInfo) {
+        let Some(cpu) = self.read("cpu.stat") else {
+            return self.fallback.read_cpu_usage(process_info);
+        };
+        let field = |name: &str| {
+            cpu.lines()
+                .find_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+                .and_then(|value| value.trim().parse::<u64>().ok())
+                .map(|usec| usec * clock_ticks_per_sec() / 1_000_000)
+        };
+        if let (Some(utime), Some(stime)) = (field("user_usec"), field("system_usec")) {
+            process_info.stat.utime = utime;
+            process_info.stat.stime = stime;
+            process_info.stat.total_time = utime + stime;
+            let elapsed = process_info.elapsed();
+            process_info
+                .stat
+                .cpu_ticks
+                .push(Sample::new(elapsed, utime + stime));
+        }
+    }
+
+    fn read_memory_usage(&mut self, process_info: &mut ProcessInfo) {
+        let Some(stat) = self.read("memory.stat") else {
+            return self.fallback.read_memory_usage(process_info);
+        };
+        if let Some(bytes) = resident_bytes(&stat) {
+            let elapsed = process_info.elapsed();
+            process_info
+                .stat
+                .memory_kb
+                .push(Sample::new(elapsed, bytes / 1024));
+        }
+    }
+
+    fn read_network_usage(&mut self, process_info: &mut ProcessInfo) {
+        self.fallback.read_network_usage(process_info);
+    }
+
+    fn read_thread_usage(&mut self, process_info: &mut ProcessInfo) {
+        self.fallback.read_thread_usage(process_info);
+    }
+
+    fn read_disk_usage(&mut self, process_info: &mut ProcessInfo) {
+        let Some(io) = self.read("io.stat") else {
+            return self.fallback.read_disk_usage(process_info);
+        };
+        // One line per device, e.g. "8:0 rbytes=4096 wbytes=0 rios=1 ..."
+        let (mut read_bytes, mut write_bytes) = (0, 0);
+        for field in io.split_whitespace() {
+            if let Some(value) = field.strip_prefix("rbytes=") {
+                read_bytes += value.parse::<u64>().unwrap_or(0);
+            } else if let Some(value) = field.strip_prefix("wbytes=") {
+                write_bytes += value.parse::<u64>().unwrap_or(0);
+            }
+        }
+        process_info.stat.read_bytes.push(read_bytes);
+        process_info.stat.write_bytes.push(write_bytes);
+    }
 }
 
 pub(crate) struct PollBased {
     pid: u32,
+    threads_read: Option<Instant>,
 }
 
 impl Monitor for PollBased {
     fn new(pid: u32) -> Self {
-        Self { pid }
+        Self {
+            pid,
+            threads_read: None,
+        }
     }
 
     fn read_cpu_usage(&mut self, process_info: &mut ProcessInfo) {
         let mut stat = String::new();
-        if let Ok(mut f) = File::open(format!("/proc/{}/stat", self.pid)) {
-            if f.read_to_string(&mut stat).is_ok() {
-                // Split the stat content and get CPU usage fields
-                let fields: Vec<&str> = stat.split_whitespace().collect();
-                if fields.len() >= 15 {
-                    // Fields 14 and 15 contain user and system CPU time
-                    let utime = fields[13].parse::<u64>().unwrap_or(0);
-                    let stime = fields[14].parse::<u64>().unwrap_or(0);
-                    let total_time = utime + stime;
-
-                    // Store individual readings rather than accumulating
-                    process_info.stat.utime = utime;
-                    process_info.stat.stime = stime;
-                    process_info.stat.total_time = total_time;
-                }
+        if let Ok(mut f) = File::open(format!("/proc/{}/stat", self.pid))
+            && f.read_to_string(&mut stat).is_ok()
+        {
+            // Split the stat content and get CPU usage fields
+            let fields: Vec<&str> = stat.split_whitespace().collect();
+            if fields.len() >= 15 {
+                // Fields 14 and 15 contain user and system CPU time
+                let utime = fields[13].parse::<u64>().unwrap_or(0);
+                let stime = fields[14].parse::<u64>().unwrap_or(0);
+                let total_time = utime + stime;
+
+                // Store individual readings rather than accumulating
+                process_info.stat.utime = utime;
+                process_info.stat.stime = stime;
+                process_info.stat.total_time = total_time;
+                let elapsed = process_info.elapsed();
+                process_info
+                    .stat
+                    .cpu_ticks
+                    .push(Sample::new(elapsed, total_time));
             }
         }
     }
     fn read_memory_usage(&mut self, process_info: &mut ProcessInfo) {
         let mut status = String::new();
-        if let Ok(mut f) = File::open(format!("/proc/{}/status", self.pid)) {
-            if f.read_to_string(&mut status).is_ok() {
-                // Find VmRSS line which shows actual physical memory usage
-                if let Some(line) = status.lines().find(|l| l.starts_with("VmRSS:")) {
-                    let fields: Vec<&str> = line.split_whitespace().collect();
-                    if fields.len() >= 2 {
-                        let memory_kb = fields[1].parse::<u64>().unwrap_or(0);
-                        process_info.stat.memory_kb.push(memory_kb);
-                    }
+        if let Ok(mut f) = File::open(format!("/proc/{}/status", self.pid))
+            && f.read_to_string(&mut status).is_ok()
+        {
+            // Find VmRSS line which shows actual physical memory usage
+            if let Some(line) = status.lines().find(|l| l.starts_with("VmRSS:")) {
+                let fields: Vec<&str> = line.split_whitespace().collect();
+                if fields.len() >= 2 {
+                    let memory_kb = fields[1].parse::<u64>().unwrap_or(0);
+                    let elapsed = process_info.elapsed();
+                    process_info
+                        .stat
+                        .memory_kb
+                        .push(Sample::new(elapsed, memory_kb));
                 }
             }
         }
     }
     fn read_network_usage(&mut self, process_info: &mut ProcessInfo) {
         let mut net = String::new();
-        if let Ok(mut f) = File::open(format!("/proc/{}/net/dev", self.pid)) {
-            if f.read_to_string(&mut net).is_ok() {
-                // Skip header lines and process network interface statistics
-                for line in net.lines().skip(2) {
-                    let fields: Vec<&str> = line.split_whitespace().collect();
-                    if fields.len() >= 10 {
-                        // let interface = fields[0].trim_end_matches(':');
-                        let bytes_received = fields[1].parse::<u64>().unwrap_or(0);
-                        let bytes_transmitted = fields[9].parse::<u64>().unwrap_or(0);
-                        process_info.stat.read_bytes.push(bytes_received);
-                        process_info.stat.transmitted.push(bytes_transmitted);
-                    }
+        if let Ok(mut f) = File::open(format!("/proc/{}/net/dev", self.pid))
+            && f.read_to_string(&mut net).is_ok()
+        {
+            // Skip header lines and process network interface statistics
+            for line in net.lines().skip(2) {
+                let fields: Vec<&str> = line.split_whitespace().collect();
+                if fields.len() >= 10 {
+                    // let interface = fields[0].trim_end_matches(':');
+                    let bytes_received = fields[1].parse::<u64>().unwrap_or(0);
+                    let bytes_transmitted = fields[9].parse::<u64>().unwrap_or(0);
+                    process_info.stat.received.push(bytes_received);
+                    process_info.stat.transmitted.push(bytes_transmitted);
                 }
             }
         }
     }
     fn read_disk_usage(&mut self, process_info: &mut ProcessInfo) {
         let mut io = String::new();
-        if let Ok(mut f) = File::open(format!("/proc/{}/io", self.pid)) {
-            if f.read_to_string(&mut io).is_ok() {
-                // Process IO statistics
-                for line in io.lines() {
-                    let fields: Vec<&str> = line.split_whitespace().collect();
-                    if fields.len() >= 2 {
-                        match fields[0] {
-                            "read_bytes:" => {
-                                let read_bytes = fields[1].parse::<u64>().unwrap_or(0);
-                                process_info.stat.read_bytes.push(read_bytes);
-                            }
-                            "write_bytes:" => {
-                                let write_bytes = fields[1].parse::<u64>().unwrap_or(0);
-                                process_info.stat.write_bytes.push(write_bytes);
-                            }
-                            _ => {}
+        if let Ok(mut f) = File::open(format!("/proc/{}/io", self.pid))
+            && f.read_to_string(&mut io).is_ok()
+        {
+            // Process IO statistics
+            for line in io.lines() {
+                let fields: Vec<&str> = line.split_whitespace().collect();
+                if fields.len() >= 2 {
+                    match fields[0] {
+                        "read_bytes:" => {
+                            let read_bytes = fields[1].parse::<u64>().unwrap_or(0);
+                            process_info.stat.read_bytes.push(read_bytes);
+                        }
+                        "write_bytes:" => {
+                            let write_bytes = fields[1].parse::<u64>().unwrap_or(0);
+                            process_info.stat.write_bytes.push(write_bytes);
                         }
+                        _ => {}
                     }
                 }
             }
         }
     }
+
+    fn read_thread_usage(&mut self, process_info: &mut ProcessInfo) {
+        if self
+            .threads_read
+            .is_some_and(|read| read.elapsed() < THREAD_SAMPLE_INTERVAL)
+        {
+            return;
+        }
+        self.threads_read = Some(Instant::now());
+        for tid in task_ids(self.pid) {
+            // Gone since the directory was listed; the last reading stands
+            if let Some(task) = read_task_stat(self.pid, tid) {
+                let wchan = match task.state {
+                    'R' => String::new(),
+                    _ => read_wchan(self.pid, tid),
+                };
+                let state = WaitState::classify(task.state, &wchan);
+                *process_info
+                    .stat
+                    .wait_samples
+                    .entry((task.name.clone(), state, wchan))
+                    .or_default() += 1;
+                let ticks = ThreadTicks {
+                    name: task.name,
+                    utime: task.utime,
+                    stime: task.stime,
+                };
+                process_info.stat.threads.insert(tid, ticks);
+            }
+        }
+    }
+}
+
+/// One thread as seen in /proc/<pid>/task/<tid>/stat.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct TaskStat {
+    pub(crate) name: String,
+    /// R running, S sleeping, D in uninterruptible (usually disk) sleep, and so on.
+    pub(crate) state: char,
+    pub(crate) utime: u64,
+    pub(crate) stime: u64,
+}
+
+pub(crate) fn read_task_stat(pid: u32, tid: u32) -> Option<TaskStat> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/task/{}/stat", pid, tid)).ok()?;
+    parse_task_stat(&stat)
+}
+
+/// The kernel function a thread is blocked in, empty if it is not blocked.
+pub(crate) fn read_wchan(pid: u32, tid: u32) -> String {
+    let wchan =
+        std::fs::read_to_string(format!("/proc/{}/task/{}/wchan", pid, tid)).unwrap_or_default();
+    match wchan.trim() {
+        "0" => String::new(),
+        wchan => wchan.to_string(),
+    }
+}
+
+/// Threads of a process by id, empty once it has exited.
+pub(crate) fn task_ids(pid: u32) -> Vec<u32> {
+    std::fs::read_dir(format!("/proc/{}/task", pid))
+        .map(|tasks| {
+            tasks
+                .flatten()
+                .filter_map(|task| task.file_name().to_str()?.parse().ok())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Parses a /proc stat line. The name is parenthesised and may itself hold
+/// spaces and parentheses, so fields are counted from the last `)`.
+fn parse_task_stat(stat: &str) -> Option<TaskStat> {
+    let (start, end) = (stat.find('(')?, stat.rfind(')')?);
+    let name = stat.get(start + 1..end)?.to_string();
+    // The state is field 3, utime and stime are fields 14 and 15
+    let mut fields = stat[end + 1..].split_whitespace();
+    let state = fields.next()?.chars().next()?;
+    let mut times = fields.skip(10);
+    let utime = times.next()?.parse().ok()?;
+    let stime = times.next()?.parse().ok()?;
+    Some(TaskStat {
+        name,
+        state,
+        utime,
+        stime,
+    })
 }
 
 #[cfg(test)]
@@ -162,7 +657,22 @@ mod tests {
         // Should handle nonexistent PID gracefully without panicking
 
         assert!(process_info.stat.memory_kb.is_empty());
-        assert!(process_info.stat.total_time > 0);
+        assert_eq!(process_info.stat.total_time, 0);
+    }
+
+    #[test]
+    fn test_memory_samples_are_timestamped() {
+        let mut monitor = PollBased::new(std::process::id());
+        let mut process_info = ProcessInfo::new(String::from("test"), vec![]);
+        process_info.start_time = Some(std::time::Instant::now());
+        monitor.read_memory_usage(&mut process_info);
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        monitor.read_memory_usage(&mut process_info);
+
+        let samples = &process_info.stat.memory_kb;
+        assert_eq!(samples.len(), 2);
+        assert!(samples[1].elapsed > samples[0].elapsed);
+        assert!(samples[0].value > 0);
     }
 
     #[test]
@@ -212,4 +722,115 @@ mod tests {
         monitor.read_network_usage(&mut process_info);
         monitor.read_disk_usage(&mut process_info);
     }
+
+    #[test]
+    fn test_parse_task_stat() {
+        let stat = "4242 (GC Thread#0) S 1 4242 4242 0 -1 4194368 212 0 0 0 37 5 0 0 20 0 12 0";
+        assert_eq!(
+            parse_task_stat(stat),
+            Some(TaskStat {
+                name: String::from("GC Thread#0"),
+                state: 'S',
+                utime: 37,
+                stime: 5
+            })
+        );
+        let stat = "7 (a) b) R 1 7 7 0 -1 0 0 0 0 0 3 4";
+        assert_eq!(parse_task_stat(stat).unwrap().name, "a) b");
+        assert_eq!(parse_task_stat("7 (short) R 1"), None);
+    }
+
+    #[test]
+    fn test_read_thread_usage() {
+        let mut monitor = PollBased::new(std::process::id());
+        let mut process_info = ProcessInfo::new(String::from("test"), vec![]);
+        monitor.read_thread_usage(&mut process_info);
+        assert!(process_info.stat.threads.contains_key(&std::process::id()));
+        assert!(!process_info.stat.wait_samples.is_empty());
+        // Read again only once the interval has passed
+        process_info.stat.threads.clear();
+        monitor.read_thread_usage(&mut process_info);
+        assert!(process_info.stat.threads.is_empty());
+    }
+
+    #[test]
+    fn test_cgroup_monitor_of_missing_pid_falls_back() {
+        let mut monitor = CgroupV2::new(u32::MAX);
+        assert_eq!(monitor.path, None);
+        let mut process_info = ProcessInfo::new(String::from("test"), vec![]);
+        monitor.scan(&mut process_info);
+        assert!(process_info.stat.memory_kb.is_empty());
+        assert_eq!(process_info.stat.total_time, 0);
+    }
+
+    #[test]
+    fn test_cgroup_resident_memory() {
+        let stat = "anon 8192\nfile 1048576\nkernel 4096\nfile_mapped 4096\nanon_thp 0\n";
+        assert_eq!(resident_bytes(stat), Some(12288));
+        assert_eq!(resident_bytes("file 1048576\n"), None);
+    }
+
+    #[test]
+    fn test_cgroup_limit_parsing() {
+        assert_eq!(parse_cpu_max("max 100000\n"), None);
+        assert_eq!(parse_cpu_max("150000 100000\n"), Some(1.5));
+        assert_eq!(cpu_quota(-1, 100000), None);
+        assert_eq!(cpu_quota(50000, 100000), Some(0.5));
+        assert_eq!(parse_memory_limit("max\n"), None);
+        assert_eq!(parse_memory_limit("9223372036854771712\n"), None);
+        assert_eq!(parse_memory_limit("536870912\n"), Some(512 * 1024 * 1024));
+    }
+
+    #[test]
+    fn test_nr_throttled_parsing() {
+        let stat = "usage_usec 1000\nnr_periods 20\nnr_throttled 3\nthrottled_usec 500\n";
+        assert_eq!(parse_nr_throttled(stat), Some(3));
+        assert_eq!(parse_nr_throttled("usage_usec 1000\n"), None);
+    }
+
+    #[test]
+    fn test_flat_keyed_parsing() {
+        let stat = "usage_usec 1000\nnr_periods 20\nnr_throttled 3\nthrottled_usec 500\n";
+        assert_eq!(flat_keyed(stat, "throttled_usec"), Some(500));
+        assert_eq!(flat_keyed("oom 1\noom_kill 2\n", "oom_kill"), Some(2));
+        assert_eq!(flat_keyed("oom_kill x\n", "oom_kill"), None);
+    }
+
+    #[test]
+    fn test_ancestor_limits_stop_at_root() {
+        let limits = ancestor_limits(Path::new("/cg/a/b"), Path::new("/cg"), |dir| {
+            Some(dir.to_string_lossy().into_owned())
+        });
+        assert_eq!(limits, ["/cg/a/b", "/cg/a", "/cg"]);
+    }
+
+    #[test]
+    fn test_run_cgroup_accounts_children() {
+        if !cgroups_available(&[]) {
+            eprintln!("skipping: no writable cgroup v2 hierarchy");
+            return;
+        }
+        let cgroup = RunCgroup::create().unwrap();
+        let path = cgroup.path().to_path_buf();
+        assert!(path.join("cgroup.procs").exists());
+
+        let join = cgroup.joiner().unwrap();
+        let mut command = std::process::Command::new("sh");
+        command.args(["-c", "i=0; while [ $i -lt 20000 ]; do i=$((i+1)); done"]);
+        // SAFETY: the closure only makes a single write syscall
+        unsafe {
+            std::os::unix::process::CommandExt::pre_exec(&mut command, join);
+        }
+        let mut child = command.spawn().unwrap();
+        let pid = child.id();
+        child.wait().unwrap();
+
+        let mut monitor = CgroupV2::at(pid, path.clone());
+        let mut process_info = ProcessInfo::new(String::from("sh"), vec![]);
+        monitor.read_cpu_usage(&mut process_info);
+        assert_eq!(process_info.stat.cpu_ticks.len(), 1);
+
+        drop(cgroup);
+        assert!(!path.exists());
+    }
 }
@@ -1,27 +1,387 @@
-mod benchmark;
-mod executor;
-mod linux;
-mod monitor;
-mod process;
+use clap::{Parser, Subcommand};
+use lens::baseline::BaselineStore;
+use lens::cache::{self, CacheMode};
+use lens::config::Config;
+use lens::files::FileReport;
+use lens::limits::{self, Rlimit};
+use lens::output::{OutputDigest, OutputMode};
+use lens::plan::BenchmarkSpec;
+use lens::plot::{Chart, PlotMode};
+use lens::process::{Outcome, ProcessInfo};
+use lens::profile::{self, Profile, ProfileSource};
+use lens::report::{self, Report};
+use lens::schedule::{self, Order};
+use lens::scheduling::{self, IoPriority, SchedPolicy};
+use lens::stats::Outlier;
+use lens::syscalls::{self, SyscallProfile};
+use lens::system::SystemInfo;
+use lens::threads::{ThreadStat, WaitStat};
+use lens::threshold::Thresholds;
+use lens::{Interrupt, MonitorKind};
+use lens::{baseline, config, flamegraph, html, output, plot, preflight, stats, units};
+use std::collections::BTreeMap;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use crate::process::ProcessInfo;
-use clap::Parser;
-use executor::Executor;
+/// Exit status when a --max-* assertion fails, distinct from runtime errors (1)
+/// and usage errors (2).
+const EXIT_ASSERTION_FAILED: i32 = 3;
 
-use crate::benchmark::Benchmark;
+/// Where --profile writes, unless given a directory.
+const PROFILE_DIR: &str = "lens-profile";
+
+use lens::benchmark::Benchmark;
+use lens::linux::clock_ticks_per_sec;
 
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, subcommand_negates_reqs = true)]
 struct Args {
+    #[command(subcommand)]
+    subcommand: Option<LensCommand>,
+
     #[arg(short, long, default_value_t = 0)]
     warm: u8,
 
     #[arg(short, long, default_value_t = 5)]
     iter: u8,
 
+    /// Iterations to run at least, overriding --iter, before --time-budget or --target-rel-ci can stop sampling
+    #[arg(global = true, long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    min_runs: Option<u32>,
+
+    /// Iterations to run at most
+    #[arg(global = true, long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    max_runs: Option<u32>,
+
+    /// Keep starting iterations until this much time was spent measuring, e.g. 30s
+    #[arg(global = true, long, value_name = "TIME", value_parser = units::parse_duration)]
+    time_budget: Option<Duration>,
+
+    /// Keep starting iterations until the 95% confidence interval of the mean wall time is within this, e.g. 1%
+    #[arg(global = true, long, value_name = "PERCENT", value_parser = units::parse_percent)]
+    target_rel_ci: Option<f64>,
+
+    /// Draw CPU and RSS over time, plus a wall time histogram
+    #[arg(
+        global = true,
+        long,
+        value_enum,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "separate"
+    )]
+    plot: Option<PlotMode>,
+
+    /// Write the full results as JSON
+    #[arg(global = true, long, value_name = "FILE")]
+    export_json: Option<PathBuf>,
+
+    /// Write a self-contained HTML report with charts
+    #[arg(global = true, long, value_name = "FILE")]
+    export_html: Option<PathBuf>,
+
+    /// Store the results under this name for later comparison
+    #[arg(global = true, long, value_name = "NAME")]
+    save_baseline: Option<String>,
+
+    /// Compare the results against a previously saved baseline
+    #[arg(global = true, long, value_name = "NAME")]
+    baseline: Option<String>,
+
+    /// Where saved baselines are kept
+    #[arg(
+        global = true,
+        long,
+        value_name = "DIR",
+        env = "LENS_RESULTS_DIR",
+        default_value = ".lens"
+    )]
+    results_dir: PathBuf,
+
+    /// Fail if the average wall time exceeds this, e.g. 2.5s
+    #[arg(global = true, long, value_name = "TIME", value_parser = units::parse_duration)]
+    max_time: Option<Duration>,
+
+    /// Fail if the peak RSS of the command, or of a child it waits for, exceeds this, e.g. 512MB
+    #[arg(global = true, long, value_name = "SIZE", value_parser = units::parse_bytes)]
+    max_rss: Option<u64>,
+
+    /// Fail if the wall time regresses against --baseline by more than this, e.g. 5%
+    #[arg(global = true, long, value_name = "PERCENT", value_parser = units::parse_percent, requires = "baseline")]
+    max_regression: Option<f64>,
+
+    /// Fail if the average user + system CPU time exceeds this, e.g. 1.5s
+    #[arg(global = true, long, value_name = "TIME", value_parser = units::parse_duration)]
+    max_cpu_time: Option<Duration>,
+
+    /// Set an environment variable for every command, e.g. LC_ALL=C. To set one
+    /// for a single command, start that command with it: "RUBY_YJIT_ENABLE=1 ruby a.rb"
+    #[arg(global = true, short, long = "env", value_name = "KEY=VAL", value_parser = parse_env)]
+    env: Vec<(String, String)>,
+
+    /// Run the commands with only the variables given by --env
+    #[arg(global = true, long)]
+    env_clear: bool,
+
+    /// Directory to run in, given once for all commands or once per command
+    #[arg(global = true, long, value_name = "DIR")]
+    cwd: Vec<PathBuf>,
+
+    /// File fed to stdin on each run, given once for all commands or once per command
+    #[arg(global = true, long, value_name = "FILE")]
+    input: Vec<PathBuf>,
+
+    /// Where stdout goes: null, pipe, inherit or file:<path>
+    #[arg(global = true, long, value_name = "MODE")]
+    output: Option<OutputMode>,
+
+    /// Warn when stdout differs between runs or between commands
+    #[arg(global = true, long)]
+    check_output: bool,
+
+    /// Refuse to run when the pre-flight checks find the machine unfit for benchmarking
+    #[arg(global = true, long)]
+    strict_env: bool,
+
+    /// Leave runs whose wall time is an outlier out of the summary
+    #[arg(global = true, long)]
+    exclude_outliers: bool,
+
+    /// Fail unless stdout matches this file, given once for all commands or once per command
+    #[arg(global = true, long, value_name = "FILE")]
+    expected_output: Vec<PathBuf>,
+
+    /// How commands are measured: poll /proc, or run each in its own cgroup
+    #[arg(global = true, long, value_enum, default_value_t = MonitorKind::Poll)]
+    monitor: MonitorKind,
+
+    /// Instances started together per iteration; a list like 1,2,4,8 runs each command at every level
+    #[arg(global = true, long, value_name = "N", value_delimiter = ',', value_parser = clap::value_parser!(u32).range(1..))]
+    concurrency: Vec<u32>,
+
+    /// CPUs the commands may run on, e.g. 2,3 or 0-3
+    #[arg(global = true, long, value_name = "LIST", value_parser = scheduling::parse_cpu_list)]
+    // Spelled out so clap takes the whole list as one value
+    cpus: Option<std::vec::Vec<usize>>,
+
+    /// Nice value the commands run with, from -20 to 19
+    #[arg(global = true, long, value_name = "N", allow_negative_numbers = true, value_parser = clap::value_parser!(i32).range(-20..=19))]
+    nice: Option<i32>,
+
+    /// Scheduling policy: other, batch, idle, fifo:<priority> or rr:<priority>
+    #[arg(global = true, long, value_name = "POLICY")]
+    sched: Option<SchedPolicy>,
+
+    /// I/O priority: realtime:<level>, best-effort:<level> or idle
+    #[arg(global = true, long, value_name = "CLASS")]
+    ionice: Option<IoPriority>,
+
+    /// Memory the commands may use, e.g. 512M; exceeding it gets them OOM-killed
+    #[arg(global = true, long, value_name = "SIZE", value_parser = units::parse_bytes)]
+    memory_limit: Option<u64>,
+
+    /// CPUs worth of time the commands get per period, e.g. 1.5
+    #[arg(global = true, long, value_name = "CPUS", value_parser = limits::parse_cpu_quota)]
+    cpu_quota: Option<f64>,
+
+    /// Processes and threads the commands may have at once
+    #[arg(global = true, long, value_name = "N")]
+    pids_max: Option<u64>,
+
+    /// Resource limit set with setrlimit, e.g. nofile=1024 or as=1G; may be repeated
+    #[arg(global = true, long, value_name = "NAME=VALUE")]
+    rlimit: Vec<Rlimit>,
+
+    /// Page cache before each iteration: cold drops it, or evicts the command's files without root; warm reads them in
+    #[arg(global = true, long, value_enum)]
+    cache: Option<CacheMode>,
+
+    /// Another file for --cache to evict or read in, besides --input and files named in the command
+    #[arg(global = true, long, value_name = "FILE", requires = "cache")]
+    cache_file: Vec<PathBuf>,
+
+    /// Also trace each command's syscalls in one extra run under ptrace, kept out of the timings
+    #[arg(global = true, long)]
+    syscalls: bool,
+
+    /// Also list the files each command reads, writes and executes, from the same traced run as --syscalls
+    #[arg(global = true, long)]
+    files: bool,
+
+    /// Also sample each command's stacks in one extra run, writing folded stacks and flamegraphs to DIR
+    #[arg(
+        global = true,
+        long,
+        value_name = "DIR",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = PROFILE_DIR
+    )]
+    profile: Option<PathBuf>,
+
+    /// Stack samples per second for --profile, which this turns on
+    #[arg(global = true, long, value_name = "HZ", value_parser = clap::value_parser!(u32).range(1..))]
+    profile_frequency: Option<u32>,
+
+    /// Order iterations of several commands run in, to spread drift in the machine's state over all of them
+    #[arg(global = true, long, value_enum, default_value_t = Order::Sequential)]
+    order: Order,
+
+    /// Seed for --order=random, chosen and printed when omitted
+    #[arg(global = true, long, value_name = "SEED")]
+    seed: Option<u64>,
+
+    /// Commands to benchmark. Leading KEY=VAL words set variables for that
+    /// command alone, as in a shell: "RUBY_YJIT_ENABLE=1 ruby a.rb" "RUBY_YJIT_ENABLE=0 ruby a.rb"
     #[arg(required = true)]
     commands: Vec<String>,
 }
+
+#[derive(Subcommand, Debug)]
+enum LensCommand {
+    /// Run benchmarks defined in a suite file
+    Run {
+        /// Suite file to read
+        #[arg(long, value_name = "FILE", default_value = config::DEFAULT_CONFIG)]
+        config: PathBuf,
+
+        /// Benchmarks to run, all of them when omitted
+        names: Vec<String>,
+    },
+}
+
+impl Args {
+    /// Thresholds given on the command line, applied on top of any from the suite file.
+    fn thresholds(&self) -> Thresholds {
+        Thresholds {
+            max_time: self.max_time,
+            max_rss: self.max_rss,
+            max_regression: self.max_regression,
+            max_cpu_time: self.max_cpu_time,
+        }
+    }
+
+    /// Turns either front-end, ad hoc commands or a suite file, into the
+    /// benchmarks to run.
+    fn plan(&self) -> Result<Vec<BenchmarkSpec>, Box<dyn std::error::Error>> {
+        let mut specs = match &self.subcommand {
+            Some(LensCommand::Run { config, names }) => Config::load(config)?.specs(names)?,
+            None => self
+                .commands
+                .iter()
+                .map(|command| {
+                    let mut spec = BenchmarkSpec::new(command.clone());
+                    spec.warmup = self.warm.into();
+                    spec.iterations = self.iter.into();
+                    spec
+                })
+                .collect(),
+        };
+        let thresholds = self.thresholds();
+        let cwds = per_command(&self.cwd, specs.len(), "--cwd")?;
+        let inputs = per_command(&self.input, specs.len(), "--input")?;
+        let expected = per_command(&self.expected_output, specs.len(), "--expected-output")?;
+        for (((spec, cwd), input), expected) in specs.iter_mut().zip(cwds).zip(inputs).zip(expected)
+        {
+            spec.thresholds = thresholds.clone().or(spec.thresholds.clone());
+            // Later variables win, so the command line overrides the suite file
+            spec.env.extend(self.env.iter().cloned());
+            spec.env_clear |= self.env_clear;
+            spec.monitor = self.monitor;
+            spec.iterations = self.min_runs.unwrap_or(spec.iterations);
+            let scheduling = &mut spec.scheduling;
+            scheduling.cpus = self.cpus.clone().or(scheduling.cpus.take());
+            scheduling.nice = self.nice.or(scheduling.nice);
+            scheduling.policy = self.sched.or(scheduling.policy);
+            scheduling.io_priority = self.ionice.or(scheduling.io_priority);
+            let limits = &mut spec.limits;
+            limits.memory = self.memory_limit.or(limits.memory);
+            limits.cpu_quota = self.cpu_quota.or(limits.cpu_quota);
+            limits.pids = self.pids_max.or(limits.pids);
+            limits.rlimits.extend(self.rlimit.iter().cloned());
+            spec.cache = self.cache.or(spec.cache);
+            spec.syscalls |= self.syscalls;
+            spec.files |= self.files;
+            if self.profile.is_some() || self.profile_frequency.is_some() || spec.profile.is_some()
+            {
+                spec.profile = self
+                    .profile_frequency
+                    .or(spec.profile)
+                    .or(Some(profile::DEFAULT_FREQUENCY));
+            }
+            spec.cache_files.extend(self.cache_file.iter().cloned());
+            let sampling = &mut spec.sampling;
+            sampling.max_iterations = self.max_runs.or(sampling.max_iterations);
+            sampling.time_budget = self.time_budget.or(sampling.time_budget);
+            sampling.target_rel_ci = self.target_rel_ci.or(sampling.target_rel_ci);
+            spec.cwd = cwd.or(spec.cwd.take());
+            spec.input = input.or(spec.input.take());
+            spec.expected_output = expected.or(spec.expected_output.take());
+            if let Some(output) = &self.output {
+                spec.output = output.clone();
+            }
+            // Output can only be checked when lens reads it
+            if self.check_output || spec.expected_output.is_some() {
+                match spec.output {
+                    OutputMode::Pipe => {}
+                    OutputMode::Null if self.output.is_none() => spec.output = OutputMode::Pipe,
+                    _ => {
+                        return Err(
+                            "--check-output and --expected-output need --output=pipe".into()
+                        );
+                    }
+                }
+            }
+        }
+        Ok(match self.concurrency.as_slice() {
+            [] => specs,
+            [concurrency] => specs
+                .into_iter()
+                .map(|mut spec| {
+                    spec.concurrency = *concurrency;
+                    spec
+                })
+                .collect(),
+            levels => specs
+                .into_iter()
+                .flat_map(|spec| {
+                    levels.iter().map(move |&concurrency| {
+                        let mut spec = spec.clone();
+                        spec.name = format!("{} (concurrency={})", spec.name, concurrency);
+                        spec.concurrency = concurrency;
+                        spec
+                    })
+                })
+                .collect(),
+        })
+    }
+}
+
+/// Spreads an option given either once for every command or once per command.
+fn per_command<T: Clone>(
+    values: &[T],
+    commands: usize,
+    option: &str,
+) -> Result<Vec<Option<T>>, String> {
+    match values.len() {
+        0 => Ok(vec![None; commands]),
+        1 => Ok(vec![Some(values[0].clone()); commands]),
+        n if n == commands => Ok(values.iter().cloned().map(Some).collect()),
+        n => Err(format!(
+            "{} was given {} times for {} commands, give it once or once per command",
+            option, n, commands
+        )),
+    }
+}
+
+fn parse_env(text: &str) -> Result<(String, String), String> {
+    match text.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("expected KEY=VAL, got '{}'", text)),
+    }
+}
+
 /// Command to use lens -w 3 "ruby a.rb" "another command to compare"
 /// lens -w 3 "ruby"
 /// CPU, Memory, Network, Disk
@@ -32,46 +392,11 @@ struct Args {
 /// lens rails runner app/jobs/something.rb
 /// lens gzip file.txt
 /// CPU, Memory, Network, Disk  
-fn show_sys_info() {
+fn show_sys_info(system: &SystemInfo) {
     println!("\n=== System Information ===");
-
-    // CPU Info
-    // TODO: Support for physical core
-    let cpu = sys_info::cpu_num().unwrap_or(0);
-    let cpu_speed = sys_info::cpu_speed().unwrap_or(0);
-    println!("CPUs: {}  cores, {} at MHz", cpu, cpu_speed);
-    const GB_CONVERSION: f64 = 1024.0 * 1024.0;
-
-    // Memory Info
-    if let Ok(mem) = sys_info::mem_info() {
-        println!(
-            "Memory: {:.1} GB total, {:.1} GB free",
-            mem.total as f64 / GB_CONVERSION,
-            mem.free as f64 / GB_CONVERSION
-        );
-    }
-
-    // Disk Info
-    if let Ok(disk) = sys_info::disk_info() {
-        println!(
-            "Disk: {:.1} GB total, {:.1} GB free",
-            disk.total as f64 / GB_CONVERSION,
-            disk.free as f64 / GB_CONVERSION
-        );
-    }
-
-    // OS Info
-    if let Ok(os) = sys_info::os_type() {
-        if let Ok(release) = sys_info::os_release() {
-            println!("\nOS: {} {}", os, release);
-        }
+    for (name, value) in system.summary() {
+        println!("{:<12} {}", format!("{}:", name), value);
     }
-
-    // Process Info
-    if let Ok(proc_total) = sys_info::proc_total() {
-        println!("Total Processes: {}", proc_total);
-    }
-
     println!("=====================\n");
 }
 
@@ -79,37 +404,255 @@ fn show_sys_info() {
 async fn main() {
     let args = Args::parse();
 
-    let mut processes = Vec::new();
+    let plan = match args.plan() {
+        Ok(plan) => plan,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let store = BaselineStore::new(&args.results_dir);
+    let baseline = match args.baseline.as_deref().map(|name| store.load(name)) {
+        Some(Ok(report)) => Some(report),
+        Some(Err(e)) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        None => None,
+    };
 
-    show_sys_info();
+    let interrupt = match Interrupt::listen() {
+        Ok(interrupt) => interrupt,
+        Err(e) => {
+            eprintln!("Failed to install signal handlers: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let system = SystemInfo::collect();
+    show_sys_info(&system);
+    let problems = preflight::check(&system);
+    for problem in &problems {
+        println!("Warning: {}", problem);
+    }
+    if args.strict_env && !problems.is_empty() {
+        eprintln!("Refusing to run with --strict-env, fix the warnings above first");
+        std::process::exit(1);
+    }
+    let mut report = Report::new(system);
+    let mut thresholds = Vec::new();
+    let plot_mode = args.plot;
+    let check_output = args.check_output;
+    let exclude_outliers = args.exclude_outliers;
+    // The first command's output, which the others are checked against
+    let mut reference_output: Option<(String, OutputDigest)> = None;
+    let mut output_failures = Vec::new();
+    let color = std::io::stdout().is_terminal();
+    let mut overlay = Vec::new();
+    let profile_dir = args
+        .profile
+        .clone()
+        .unwrap_or_else(|| PathBuf::from(PROFILE_DIR));
+    // The first command's profile, which the others are compared against
+    let mut reference_profile: Option<(String, Profile)> = None;
+    let mut profiled = 0;
 
-    for command in args.commands {
-        let mut command_processes = Vec::new();
-        for _ in 0..args.warm {
-            let _ = Executor::new(command.clone()).execute();
+    let seed = args.seed.unwrap_or_else(schedule::random_seed);
+    if args.order == Order::Random {
+        println!("Order: random (--seed {})", seed);
+    }
+    let mut benchmarks = Vec::new();
+    for (spec, measured) in schedule::run(&plan, args.order, seed, Some(&interrupt)).await {
+        if let Some(e) = &measured.error {
+            if measured.runs.is_empty() {
+                eprintln!("Skipping {}: {}", spec.name, e);
+            } else {
+                eprintln!(
+                    "Stopped {} after {} runs: {}",
+                    spec.name,
+                    measured.runs.len(),
+                    e
+                );
+            }
         }
-        for _ in 0..args.iter {
-            command_processes.push(Executor::new(command.clone()).execute());
+        if measured.error.is_none() || !measured.runs.is_empty() {
+            benchmarks.push((spec, measured));
         }
-        processes.push(command_processes);
+    }
+    report.partial = interrupt.is_interrupted();
+    if report.partial {
+        println!("\nInterrupted, showing results for the runs that completed");
     }
 
-    for command_processes in processes {
-        let processes: Vec<ProcessInfo> = command_processes
-            .into_iter()
-            .filter_map(|p| p.ok())
-            .collect();
-        // Need to fix this to calculate each process
-        let mut benchmarks = Vec::new();
+    for (spec, measured) in benchmarks {
+        let (processes, first_error) = measured.completed();
+        if processes.is_empty() {
+            match first_error {
+                Some(e) => eprintln!("\nNo successful runs of {}: {}", spec.name, e),
+                None => eprintln!("\nNo successful runs of {}", spec.name),
+            }
+            continue;
+        }
         let first_process = &processes[0];
         let command = first_process.command.clone();
         let args = first_process.args.join(" ");
+        if spec.name != spec.command {
+            println!("\nBenchmark: {}", spec.name);
+        }
         println!("\nCommand: {}", command);
         println!("Arguments: {}", args);
-        for process in processes {
-            benchmarks.push(Benchmark::calculate(&process));
+        if !spec.scheduling.is_default()
+            && let Some(state) = &first_process.scheduling
+        {
+            println!(
+                "Scheduling: CPUs {}, {} policy, nice {}, I/O priority {}",
+                scheduling::format_cpu_list(&state.cpus),
+                state.policy,
+                state.nice,
+                state.io_priority.as_deref().unwrap_or("from nice")
+            );
+        }
+        if let Some(mode) = spec.cache {
+            let files = spec.cached_files().len();
+            match mode {
+                CacheMode::Cold if cache::can_drop_page_cache() => {
+                    println!("Cache: cold, page cache dropped before each iteration")
+                }
+                CacheMode::Cold => println!(
+                    "Cache: cold, {} files evicted before each iteration (dropping the whole page cache needs root)",
+                    files
+                ),
+                CacheMode::Warm => {
+                    println!("Cache: warm, {} files read in before each iteration", files)
+                }
+            }
+            if files == 0 && !(mode == CacheMode::Cold && cache::can_drop_page_cache()) {
+                println!(
+                    "Warning: no files found for --cache {}, pass them with --cache-file",
+                    mode
+                );
+            }
+        }
+        let completed = processes.len() / spec.concurrency.max(1) as usize;
+        if completed < spec.iterations as usize && report.partial {
+            println!(
+                "Partial: {} of {} iterations completed",
+                completed, spec.iterations
+            );
+        }
+        let failed = processes.iter().filter(|p| p.status != Some(0)).count();
+        if failed > 0 {
+            println!(
+                "Warning: {} of {} runs exited with a non-zero status",
+                failed,
+                processes.len()
+            );
+        }
+        let timed_out = processes
+            .iter()
+            .filter(|p| p.outcome == Outcome::TimedOut)
+            .count();
+        if timed_out > 0 {
+            println!(
+                "Warning: {} of {} runs were killed after exceeding the timeout",
+                timed_out,
+                processes.len()
+            );
+        }
+        let oom_killed = processes
+            .iter()
+            .filter(|p| p.outcome == Outcome::OomKilled)
+            .count();
+        if oom_killed > 0 {
+            println!(
+                "Warning: {} of {} runs were OOM-killed after exceeding the memory limit",
+                oom_killed,
+                processes.len()
+            );
+        }
+        let throttled: Vec<u64> = processes
+            .iter()
+            .filter(|p| p.outcome == Outcome::Throttled)
+            .filter_map(|p| Some(p.limit_events?.throttled_usec))
+            .collect();
+        if !throttled.is_empty() {
+            println!(
+                "Warning: {} of {} runs were held back by the CPU quota, for {:.2}ms in total",
+                throttled.len(),
+                processes.len(),
+                throttled.iter().sum::<u64>() as f64 / 1000.0
+            );
         }
-        let stat = Benchmark::average_stat(&benchmarks);
+        let outliers: Vec<(usize, Outlier)> = report::wall_time_outliers(&processes)
+            .into_iter()
+            .enumerate()
+            .filter_map(|(i, outlier)| Some((i + 1, outlier?)))
+            .collect();
+        if !outliers.is_empty() {
+            let severe = outliers.iter().filter(|(_, o)| o.is_severe()).count();
+            println!(
+                "Warning: {} of {} runs are wall time outliers ({} mild, {} severe), other load on the machine may have skewed the results",
+                outliers.len(),
+                processes.len(),
+                outliers.len() - severe,
+                severe
+            );
+            let listed: Vec<String> = outliers
+                .iter()
+                .map(|(run, outlier)| format!("#{} ({})", run, outlier))
+                .collect();
+            println!("  Affected runs: {}", listed.join(", "));
+            if exclude_outliers {
+                println!("  Left out of the summary");
+            }
+        }
+        let digests: Vec<OutputDigest> = processes.iter().filter_map(|p| p.stdout).collect();
+        if check_output {
+            let distinct = output::distinct(&digests);
+            if distinct > 1 {
+                println!(
+                    "Warning: stdout differed between runs, {} distinct outputs in {} runs",
+                    distinct,
+                    digests.len()
+                );
+            }
+            match (&reference_output, digests.first()) {
+                (None, Some(first)) => reference_output = Some((spec.name.clone(), *first)),
+                (Some((name, reference)), Some(first)) if reference != first => {
+                    println!("Warning: stdout differs from {}", name);
+                }
+                _ => {}
+            }
+        }
+        if let Some(expected) = &spec.expected_output {
+            match std::fs::read(expected) {
+                Ok(data) => {
+                    let expected_digest = OutputDigest::of(&data);
+                    let mismatched = digests.iter().filter(|d| **d != expected_digest).count();
+                    if mismatched > 0 {
+                        output_failures.push(format!(
+                            "{}: stdout differs from --expected-output {} in {} of {} runs",
+                            spec.name,
+                            expected.display(),
+                            mismatched,
+                            digests.len()
+                        ));
+                    }
+                }
+                Err(e) => output_failures.push(format!(
+                    "{}: failed to read --expected-output {}: {}",
+                    spec.name,
+                    expected.display(),
+                    e
+                )),
+            }
+        }
+        let benchmark = Benchmark::spec(spec.clone())
+            .interrupt(interrupt.clone())
+            .exclude_outliers(exclude_outliers);
+        let mut command = benchmark.summarize(&processes);
+        let stat = &command.summary;
         println!("\nBenchmark Statistics:");
         println!("---------------------");
         println!("CPU Usage:");
@@ -121,15 +664,498 @@ async fn main() {
             "  System Time: {:.1}% (min: {:.2}ms, avg: {:.2}ms, max: {:.2}ms)",
             stat.stime_percentage, stat.min_stime, stat.average_stime, stat.max_stime
         );
-        println!("\nMemory Usage:");
-        println!("  Min:     {:.1} MB", stat.min_memory / 1024.0);
-        println!("  Average: {:.1} MB", stat.average_memory / 1024.0);
-        println!("  Max:     {:.1} MB", stat.max_memory / 1024.0);
+        if command.threads.len() > 1 {
+            show_threads(&command.threads);
+        }
+        if let Some(waits) = &command.waits {
+            show_waits(waits);
+        }
+        println!("\nMemory Usage (RSS):");
+        println!(
+            "  Peak:    {:.1} MB (largest process, children included)",
+            stat.max_memory / 1024.0
+        );
+        println!(
+            "  Average: {:.1} MB (time-weighted)",
+            stat.average_memory / 1024.0
+        );
+        println!("  Final:   {:.1} MB", stat.final_memory / 1024.0);
+        println!("  Growth:  {:.1} KB/s", stat.memory_slope);
+        if stat.probable_leak() {
+            println!("  Warning: memory grew steadily over the run, possible leak");
+        }
         println!("\nExecution Time:");
         println!("  Min:     {:.3} sec", stat.min_duration);
         println!("  Average: {:.3} sec", stat.average_duration);
         println!("  Max:     {:.3} sec", stat.max_duration);
+        if spec.sampling.is_adaptive() {
+            let durations: Vec<f64> = processes
+                .iter()
+                .filter_map(|p| p.duration)
+                .map(|d| d.as_secs_f64())
+                .collect();
+            match stats::relative_ci(&durations) {
+                Some(ci) => println!(
+                    "  Runs:    {} (mean ±{:.1}% at 95% confidence)",
+                    completed,
+                    ci * 100.0
+                ),
+                None => println!("  Runs:    {}", completed),
+            }
+        }
+        if let Some(concurrency) = &command.concurrency {
+            println!(
+                "\nConcurrency ({} instances per iteration):",
+                concurrency.concurrency
+            );
+            println!("  Throughput: {:.2} runs/sec", concurrency.throughput);
+            println!(
+                "  Latency:    p50 {:.3} sec, p90 {:.3} sec, p99 {:.3} sec (min: {:.3}, max: {:.3})",
+                concurrency.latency_p50,
+                concurrency.latency_p90,
+                concurrency.latency_p99,
+                concurrency.latency_min,
+                concurrency.latency_max
+            );
+            println!(
+                "  Combined:   {:.2} sec CPU, {:.1} MB peak RSS per iteration",
+                concurrency.combined_cpu_ticks / clock_ticks_per_sec() as f64,
+                concurrency.combined_peak_rss_kb / 1024.0
+            );
+        }
+        if let Err(e) = benchmark.add_trace(&mut command).await {
+            println!("\nWarning: no trace of the command: {}", e);
+        }
+        if let Some(syscalls) = &command.syscalls {
+            show_syscalls(syscalls);
+        }
+        if let Some(files) = &command.files {
+            show_files(files);
+        }
+        if let Err(e) = benchmark.add_profile(&mut command).await {
+            println!("\nWarning: no profile: {}", e);
+        }
+        if let Some(sampled) = &command.profile {
+            profiled += 1;
+            show_profile(sampled);
+            let name = format!("{}-{}", profiled, file_stem(&spec.name));
+            let reference = reference_profile
+                .as_ref()
+                .map(|(name, p)| (name.as_str(), p));
+            write_profile(&profile_dir, &name, &spec.name, sampled, reference);
+            if reference_profile.is_none() {
+                reference_profile = Some((spec.name.clone(), sampled.clone()));
+            }
+        }
         println!("---------------------\n");
+
+        let label = spec.name.clone();
+        if let Some(mode) = plot_mode {
+            if let Some(run) = plot::representative(&processes) {
+                match mode {
+                    PlotMode::Separate => {
+                        let runs = [(label.clone(), run)];
+                        println!("{}", Chart::cpu(&runs).render(color));
+                        println!("{}", Chart::memory(&runs).render(color));
+                    }
+                    PlotMode::Overlay => overlay.push((label.clone(), run.clone())),
+                }
+            }
+            let durations: Vec<f64> = processes
+                .iter()
+                .filter_map(|p| p.duration)
+                .map(|d| d.as_secs_f64())
+                .collect();
+            println!("{}", plot::histogram(&durations));
+        }
+
+        report.commands.push(command);
+        thresholds.push(spec.thresholds.clone());
+    }
+
+    if !overlay.is_empty() {
+        let runs: Vec<(String, &ProcessInfo)> = overlay
+            .iter()
+            .map(|(label, run)| (label.clone(), run))
+            .collect();
+        println!("{}", Chart::cpu(&runs).render(color));
+        println!("{}", Chart::memory(&runs).render(color));
+    }
+
+    if let (Some(baseline), Some(name)) = (&baseline, &args.baseline) {
+        show_comparison(name, baseline, &report);
+    }
+    if let Some(name) = &args.save_baseline
+        && report.partial
+    {
+        eprintln!("Not saving baseline '{}' from partial results", name);
+    } else if let Some(name) = &args.save_baseline {
+        match store.save(name, &report) {
+            Ok(path) => println!("Baseline '{}' saved to {}", name, path.display()),
+            Err(e) => eprintln!("Failed to save baseline '{}': {}", name, e),
+        }
+    }
+
+    if let Some(path) = &args.export_json {
+        match report.to_json() {
+            Ok(json) => write_export(path, &json),
+            Err(e) => eprintln!("Failed to serialize results: {}", e),
+        }
+    }
+    if let Some(path) = &args.export_html {
+        match html::render(&report) {
+            Ok(page) => write_export(path, &page),
+            Err(e) => eprintln!("Failed to serialize results: {}", e),
+        }
+    }
+
+    // Assertions over an incomplete run would be misleading either way
+    if report.partial {
+        std::process::exit(128 + interrupt.state().signal);
+    }
+
+    let asserted = thresholds.iter().any(|t| !t.is_empty()) || !output_failures.is_empty();
+    if asserted && !check_thresholds(&thresholds, &report, baseline.as_ref(), &output_failures) {
+        std::process::exit(EXIT_ASSERTION_FAILED);
+    }
+}
+
+/// Syscalls with the most time listed, the rest summed up in the total.
+const SYSCALLS_SHOWN: usize = 15;
+
+/// Thread names listed with their CPU time or wait states.
+const THREADS_SHOWN: usize = 10;
+
+/// Wait channels listed under each thread name's states.
+const WAIT_CHANNELS_SHOWN: usize = 5;
+
+/// Files with the most I/O listed by --files.
+const FILES_SHOWN: usize = 10;
+
+/// CPU time per iteration of each thread name, busiest first.
+fn show_threads(threads: &[ThreadStat]) {
+    let total: f64 = threads.iter().map(ThreadStat::ticks).sum();
+    let ms = |ticks: f64| ticks * 1000.0 / clock_ticks_per_sec() as f64;
+    println!("  By thread (main process, per iteration):");
+    for thread in threads.iter().take(THREADS_SHOWN) {
+        println!(
+            "    {:<16} x{:<3} {:>5.1}%  user {:.2}ms, system {:.2}ms",
+            thread.name,
+            thread.threads,
+            if total > 0.0 {
+                thread.ticks() * 100.0 / total
+            } else {
+                0.0
+            },
+            ms(thread.user_ticks),
+            ms(thread.system_ticks)
+        );
+    }
+    if threads.len() > THREADS_SHOWN {
+        println!("    ... and {} more", threads.len() - THREADS_SHOWN);
+    }
+}
+
+/// Shares of each thread name's samples by state, then where it blocked most.
+fn show_waits(waits: &WaitStat) {
+    println!(
+        "\nThread States ({} samples of the main process' threads, child processes are not sampled):",
+        waits.samples
+    );
+    for thread in waits.threads.iter().take(THREADS_SHOWN) {
+        println!(
+            "  {:<16} Running: {:.1}%  Disk: {:.1}%  I/O wait: {:.1}%  Lock wait: {:.1}%  Sleeping: {:.1}%",
+            thread.name,
+            thread.running * 100.0,
+            thread.disk * 100.0,
+            thread.io * 100.0,
+            thread.lock * 100.0,
+            thread.sleeping * 100.0
+        );
+        for wchan in thread.wait_channels.iter().take(WAIT_CHANNELS_SHOWN) {
+            println!(
+                "    {:<28} {:>5.1}%  {}",
+                wchan.name,
+                wchan.share * 100.0,
+                wchan.state
+            );
+        }
+    }
+    if waits.threads.len() > THREADS_SHOWN {
+        println!("  ... and {} more", waits.threads.len() - THREADS_SHOWN);
+    }
+}
+
+/// A `strace -c` style table, then latency histograms of the costliest syscalls.
+fn show_syscalls(profile: &SyscallProfile) {
+    let overhead = profile
+        .overhead
+        .map(|overhead| format!(", {:.1}x as long as an untraced run", overhead))
+        .unwrap_or_default();
+    println!(
+        "\nSyscalls (one extra run under ptrace{}; not part of the timings above):",
+        overhead
+    );
+    let total_usec = profile.total_usec();
+    println!(
+        "  {:>6} {:>11} {:>11} {:>9} {:>9}  syscall",
+        "% time", "seconds", "usecs/call", "calls", "errors"
+    );
+    for syscall in profile.syscalls.iter().take(SYSCALLS_SHOWN) {
+        println!(
+            "  {:>6.2} {:>11.6} {:>11} {:>9} {:>9}  {}",
+            syscall.total_usec as f64 * 100.0 / total_usec.max(1) as f64,
+            syscall.total_usec as f64 / 1e6,
+            syscall.total_usec / syscall.calls.max(1),
+            syscall.calls,
+            syscall.errors,
+            syscall.name
+        );
+    }
+    if profile.syscalls.len() > SYSCALLS_SHOWN {
+        println!("  ... and {} more", profile.syscalls.len() - SYSCALLS_SHOWN);
+    }
+    println!(
+        "  {:>6.2} {:>11.6} {:>11} {:>9} {:>9}  total ({} tasks)",
+        100.0,
+        total_usec as f64 / 1e6,
+        "",
+        profile.syscalls.iter().map(|s| s.calls).sum::<u64>(),
+        profile.syscalls.iter().map(|s| s.errors).sum::<u64>(),
+        profile.tasks
+    );
+
+    let buckets: Vec<String> = syscalls::BUCKET_BOUNDS_USEC
+        .iter()
+        .map(|&bound| format!("<{}", units::format_usec(bound)))
+        .chain([format!(
+            ">={}",
+            units::format_usec(syscalls::BUCKET_BOUNDS_USEC[5])
+        )])
+        .collect();
+    println!("\n  Latency, calls per bucket:");
+    print!("  {:<16}", "");
+    for bucket in &buckets {
+        print!(" {:>8}", bucket);
+    }
+    println!();
+    for syscall in profile.syscalls.iter().take(5) {
+        print!("  {:<16}", syscall.name);
+        for calls in syscall.histogram {
+            print!(" {:>8}", calls);
+        }
+        println!();
+    }
+}
+
+/// The files with the most I/O, then any written outside the working directory.
+fn show_files(report: &FileReport) {
+    println!(
+        "\nFiles ({} used, from the traced run):",
+        report.files.len()
+    );
+    println!(
+        "  {:<4} {:>5} {:>10} {:>10}  path",
+        "mode", "opens", "read", "written"
+    );
+    for file in report.files.iter().take(FILES_SHOWN) {
+        println!(
+            "  {:<4} {:>5} {:>10} {:>10}  {}",
+            file.mode(),
+            file.opens,
+            units::format_bytes(file.bytes_read as f64),
+            units::format_bytes(file.bytes_written as f64),
+            file.path.display()
+        );
+    }
+    if report.files.len() > FILES_SHOWN {
+        println!("  ... and {} more", report.files.len() - FILES_SHOWN);
+    }
+    let outside: Vec<String> = report
+        .writes_outside_cwd()
+        .map(|file| file.path.display().to_string())
+        .collect();
+    if !outside.is_empty() {
+        println!(
+            "\nWarning: written outside the working directory {}: {}",
+            report.cwd.display(),
+            outside.join(", ")
+        );
+    }
+}
+
+/// Functions most often on top of the stack listed by --profile.
+const HOT_FUNCTIONS_SHOWN: usize = 5;
+
+/// How the profile was taken and the functions with the most self samples.
+fn show_profile(profile: &Profile) {
+    println!(
+        "\nProfile (one extra run, {} samples at {} Hz through {}):",
+        profile.samples(),
+        profile.frequency,
+        profile.source
+    );
+    if profile.source == ProfileSource::Proc {
+        println!("  perf_event is not permitted here, so only where threads waited is known");
+    }
+    let mut leaves: BTreeMap<&str, u64> = BTreeMap::new();
+    for (stack, count) in &profile.stacks {
+        let leaf = stack.rsplit(';').next().unwrap_or_default();
+        *leaves.entry(leaf).or_default() += count;
+    }
+    let mut leaves: Vec<(&str, u64)> = leaves.into_iter().collect();
+    leaves.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+    for (function, count) in leaves.iter().take(HOT_FUNCTIONS_SHOWN) {
+        println!(
+            "  {:>5.1}%  {}",
+            *count as f64 * 100.0 / profile.samples().max(1) as f64,
+            function
+        );
+    }
+    if profile.lost > 0 {
+        println!(
+            "  Warning: {} samples were lost, try a lower --profile-frequency",
+            profile.lost
+        );
+    }
+}
+
+/// Writes `<name>.folded` and `<name>.svg`, plus `<name>.diff.svg` against
+/// the reference profile when there is one.
+fn write_profile(
+    dir: &Path,
+    name: &str,
+    title: &str,
+    profile: &Profile,
+    reference: Option<(&str, &Profile)>,
+) {
+    let mut files = vec![
+        (format!("{}.folded", name), profile.folded()),
+        (
+            format!("{}.svg", name),
+            flamegraph::render(title, &profile.stacks),
+        ),
+    ];
+    if let Some((reference_title, reference)) = reference {
+        let diff_title = format!("{} vs {}", title, reference_title);
+        files.push((
+            format!("{}.diff.svg", name),
+            flamegraph::render_diff(&diff_title, &reference.stacks, &profile.stacks),
+        ));
+    }
+    if let Err(e) = std::fs::create_dir_all(dir) {
+        eprintln!("Failed to create {}: {}", dir.display(), e);
+        return;
+    }
+    let mut written = Vec::new();
+    for (file, contents) in files {
+        let path = dir.join(file);
+        match std::fs::write(&path, contents) {
+            Ok(()) => written.push(path.display().to_string()),
+            Err(e) => eprintln!("Failed to write {}: {}", path.display(), e),
+        }
+    }
+    if !written.is_empty() {
+        println!("  Written to {}", written.join(", "));
+    }
+}
+
+/// A file name from a benchmark name, e.g. `gzip-k-f-1-data-txt`.
+fn file_stem(name: &str) -> String {
+    let stem: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    let stem: Vec<&str> = stem.split('-').filter(|part| !part.is_empty()).collect();
+    let stem = stem.join("-");
+    if stem.is_empty() {
+        String::from("command")
+    } else {
+        stem.chars().take(60).collect()
+    }
+}
+
+/// Prints the outcome of every assertion and returns whether all of them held.
+/// `thresholds` holds the limits for each command in the report, in order, and
+/// `output_failures` any commands whose output did not match what was expected.
+fn check_thresholds(
+    thresholds: &[Thresholds],
+    report: &Report,
+    baseline: Option<&Report>,
+    output_failures: &[String],
+) -> bool {
+    let violations: Vec<String> = report
+        .commands
+        .iter()
+        .zip(thresholds)
+        .flat_map(|(current, thresholds)| {
+            let before = baseline.and_then(|b| b.command(&current.name));
+            thresholds.check(current, before)
+        })
+        .map(|violation| violation.to_string())
+        .chain(output_failures.iter().cloned())
+        .collect();
+
+    println!("\nAssertions:");
+    println!("---------------------");
+    if violations.is_empty() {
+        println!("  All assertions passed");
+    }
+    for violation in &violations {
+        println!("  FAILED {}", violation);
+    }
+    println!("---------------------\n");
+    violations.is_empty()
+}
+
+fn show_comparison(name: &str, baseline: &Report, report: &Report) {
+    println!("\nComparison against baseline '{}':", name);
+    println!("---------------------");
+    for current in &report.commands {
+        println!("Command: {}", current.name);
+        let Some(before) = baseline.command(&current.name) else {
+            println!("  Not present in the baseline\n");
+            continue;
+        };
+        for comparison in baseline::compare(before, current) {
+            // Every metric here is lower-is-better
+            let significant = comparison.significant();
+            let verdict = if significant && comparison.change() > 0.0 {
+                "worse"
+            } else if significant && comparison.change() < 0.0 {
+                "better"
+            } else {
+                ""
+            };
+            let line = format!(
+                "  {:<12} {:>10.3} {:<5} -> {:>10.3} {:<5} {:>+7.1}% {:<3} {}",
+                format!("{}:", comparison.metric),
+                comparison.baseline,
+                comparison.unit,
+                comparison.current,
+                comparison.unit,
+                comparison.change() * 100.0,
+                comparison.marker(),
+                verdict
+            );
+            println!("{}", line.trim_end());
+        }
+        println!();
+    }
+    println!("  * p < 0.05, ** p < 0.01, *** p < 0.001, ~ no significant change");
+    println!("---------------------\n");
+}
+
+fn write_export(path: &Path, contents: &str) {
+    match std::fs::write(path, contents) {
+        Ok(()) => println!("Results written to {}", path.display()),
+        Err(e) => eprintln!("Failed to write {}: {}", path.display(), e),
     }
 }
 
@@ -155,6 +1181,72 @@ mod tests {
         assert_eq!(args.iter, 5);
     }
 
+    #[test]
+    fn test_args_plot_mode() {
+        let args = Args::try_parse_from(["test", "ruby a.rb"]).unwrap();
+        assert_eq!(args.plot, None);
+        let args = Args::try_parse_from(["test", "--plot", "ruby a.rb"]).unwrap();
+        assert_eq!(args.plot, Some(PlotMode::Separate));
+        let args = Args::try_parse_from(["test", "--plot=overlay", "a", "b"]).unwrap();
+        assert_eq!(args.plot, Some(PlotMode::Overlay));
+    }
+
+    #[test]
+    fn test_args_exports() {
+        let args = Args::try_parse_from([
+            "test",
+            "--export-json",
+            "out.json",
+            "--export-html",
+            "report.html",
+            "ruby a.rb",
+        ])
+        .unwrap();
+        assert_eq!(args.export_json, Some(PathBuf::from("out.json")));
+        assert_eq!(args.export_html, Some(PathBuf::from("report.html")));
+    }
+
+    #[test]
+    fn test_args_baseline() {
+        let args = Args::try_parse_from(["test", "ruby a.rb"]).unwrap();
+        assert_eq!(args.results_dir, PathBuf::from(".lens"));
+        assert_eq!(args.baseline, None);
+
+        let args =
+            Args::try_parse_from(["test", "--save-baseline", "main", "--baseline", "old", "ls"])
+                .unwrap();
+        assert_eq!(args.save_baseline.as_deref(), Some("main"));
+        assert_eq!(args.baseline.as_deref(), Some("old"));
+    }
+
+    #[test]
+    fn test_args_thresholds() {
+        let args = Args::try_parse_from([
+            "test",
+            "--max-time",
+            "2.5s",
+            "--max-rss",
+            "512MB",
+            "--max-cpu-time",
+            "1s",
+            "ls",
+        ])
+        .unwrap();
+        assert_eq!(args.max_time, Some(Duration::from_millis(2500)));
+        assert_eq!(args.max_rss, Some(512 * 1024 * 1024));
+        assert_eq!(args.max_cpu_time, Some(Duration::from_secs(1)));
+        assert!(Args::try_parse_from(["test", "--max-time", "soon", "ls"]).is_err());
+    }
+
+    #[test]
+    fn test_args_max_regression_requires_baseline() {
+        assert!(Args::try_parse_from(["test", "--max-regression", "5%", "ls"]).is_err());
+        let args =
+            Args::try_parse_from(["test", "--baseline", "main", "--max-regression", "5%", "ls"])
+                .unwrap();
+        assert_eq!(args.max_regression, Some(0.05));
+    }
+
     #[test]
     fn test_args_giving_multiple_commands() {
         let args =
@@ -163,4 +1255,342 @@ mod tests {
         assert_eq!(args.commands[0], "ruby a.rb");
         assert_eq!(args.commands[1], "another command to compare");
     }
+
+    #[test]
+    fn test_args_run_subcommand() {
+        let args = Args::try_parse_from(["test", "run"]).unwrap();
+        let Some(LensCommand::Run { config, names }) = &args.subcommand else {
+            panic!("expected the run subcommand");
+        };
+        assert_eq!(config, &PathBuf::from("lens.toml"));
+        assert!(names.is_empty());
+
+        let args = Args::try_parse_from([
+            "test",
+            "run",
+            "--config",
+            "bench/suite.toml",
+            "--max-time=2s",
+            "compress",
+            "list",
+        ])
+        .unwrap();
+        let Some(LensCommand::Run { config, names }) = &args.subcommand else {
+            panic!("expected the run subcommand");
+        };
+        assert_eq!(config, &PathBuf::from("bench/suite.toml"));
+        assert_eq!(names, &["compress", "list"]);
+        assert_eq!(args.max_time, Some(Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn test_plan_from_commands() {
+        let args = Args::try_parse_from(["test", "-w", "2", "-i", "3", "ls", "pwd"]).unwrap();
+        let specs = args.plan().unwrap();
+        assert_eq!(specs.len(), 2);
+        assert_eq!(specs[0].name, "ls");
+        assert_eq!(specs[0].warmup, 2);
+        assert_eq!(specs[1].iterations, 3);
+    }
+
+    #[test]
+    fn test_args_environment() {
+        let args = Args::try_parse_from([
+            "test",
+            "-e",
+            "RUBY_YJIT_ENABLE=1",
+            "--env",
+            "LC_ALL=C",
+            "--env-clear",
+            "--cwd",
+            "app",
+            "--input",
+            "in.txt",
+            "ruby a.rb",
+            "ruby b.rb",
+        ])
+        .unwrap();
+        let specs = args.plan().unwrap();
+        for spec in &specs {
+            assert_eq!(
+                spec.env,
+                vec![
+                    (String::from("RUBY_YJIT_ENABLE"), String::from("1")),
+                    (String::from("LC_ALL"), String::from("C")),
+                ]
+            );
+            assert!(spec.env_clear);
+            assert_eq!(spec.cwd, Some(PathBuf::from("app")));
+            assert_eq!(spec.input, Some(PathBuf::from("in.txt")));
+        }
+        assert!(Args::try_parse_from(["test", "--env", "NOVALUE", "ls"]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_args_inline_env_per_command() {
+        let args = Args::try_parse_from([
+            "test",
+            "--iter",
+            "1",
+            "--env",
+            "LENS_SHARED=1",
+            "LENS_MODE=on sh -c 'test \"$LENS_MODE$LENS_SHARED\" = on1'",
+            "LENS_MODE=off sh -c 'test \"$LENS_MODE$LENS_SHARED\" = off1'",
+        ])
+        .unwrap();
+        for spec in args.plan().unwrap() {
+            let measured = spec.run(None).await;
+            assert_eq!(measured.error, None);
+            let status = measured.runs[0].as_ref().unwrap().status;
+            assert_eq!(status, Some(0), "{}", spec.command);
+        }
+        let help = <Args as clap::CommandFactory>::command()
+            .render_long_help()
+            .to_string();
+        assert!(help.contains("\"RUBY_YJIT_ENABLE=1 ruby a.rb\""));
+    }
+
+    #[test]
+    fn test_args_cwd_per_command() {
+        let args = Args::try_parse_from(["test", "--cwd", "a", "--cwd", "b", "ls", "pwd"]).unwrap();
+        let specs = args.plan().unwrap();
+        assert_eq!(specs[0].cwd, Some(PathBuf::from("a")));
+        assert_eq!(specs[1].cwd, Some(PathBuf::from("b")));
+
+        let args = Args::try_parse_from(["test", "--input", "a", "--input", "b", "ls"]).unwrap();
+        assert_eq!(
+            args.plan().unwrap_err().to_string(),
+            "--input was given 2 times for 1 commands, give it once or once per command"
+        );
+    }
+
+    #[test]
+    fn test_args_output() {
+        let args = Args::try_parse_from(["test", "ls"]).unwrap();
+        assert_eq!(args.plan().unwrap()[0].output, OutputMode::Null);
+
+        let args = Args::try_parse_from(["test", "--output", "file:out.txt", "ls"]).unwrap();
+        assert_eq!(
+            args.plan().unwrap()[0].output,
+            OutputMode::File(PathBuf::from("out.txt"))
+        );
+
+        // Checking output switches to reading it
+        let args = Args::try_parse_from(["test", "--check-output", "ls", "pwd"]).unwrap();
+        assert!(
+            args.plan()
+                .unwrap()
+                .iter()
+                .all(|s| s.output == OutputMode::Pipe)
+        );
+
+        let args = Args::try_parse_from(["test", "--expected-output", "want.txt", "ls"]).unwrap();
+        let specs = args.plan().unwrap();
+        assert_eq!(specs[0].output, OutputMode::Pipe);
+        assert_eq!(specs[0].expected_output, Some(PathBuf::from("want.txt")));
+
+        let args =
+            Args::try_parse_from(["test", "--output", "inherit", "--check-output", "ls"]).unwrap();
+        assert!(args.plan().is_err());
+    }
+
+    #[test]
+    fn test_args_monitor() {
+        let args = Args::try_parse_from(["test", "ls"]).unwrap();
+        assert_eq!(args.plan().unwrap()[0].monitor, MonitorKind::Poll);
+        let args = Args::try_parse_from(["test", "--monitor", "cgroup", "ls"]).unwrap();
+        assert_eq!(args.plan().unwrap()[0].monitor, MonitorKind::Cgroup);
+    }
+
+    #[test]
+    fn test_args_concurrency() {
+        let args = Args::try_parse_from(["test", "ls"]).unwrap();
+        assert_eq!(args.plan().unwrap()[0].concurrency, 1);
+
+        let args = Args::try_parse_from(["test", "--concurrency", "4", "ls"]).unwrap();
+        let specs = args.plan().unwrap();
+        assert_eq!(specs.len(), 1);
+        assert_eq!(specs[0].concurrency, 4);
+
+        let args = Args::try_parse_from(["test", "--concurrency", "1,2", "ls", "pwd"]).unwrap();
+        let specs = args.plan().unwrap();
+        let names: Vec<(&str, u32)> = specs
+            .iter()
+            .map(|s| (s.name.as_str(), s.concurrency))
+            .collect();
+        assert_eq!(
+            names,
+            [
+                ("ls (concurrency=1)", 1),
+                ("ls (concurrency=2)", 2),
+                ("pwd (concurrency=1)", 1),
+                ("pwd (concurrency=2)", 2)
+            ]
+        );
+
+        assert!(Args::try_parse_from(["test", "--concurrency", "0", "ls"]).is_err());
+    }
+
+    #[test]
+    fn test_args_order() {
+        let args = Args::try_parse_from(["test", "ls"]).unwrap();
+        assert_eq!(args.order, Order::Sequential);
+        assert_eq!(args.seed, None);
+        let args =
+            Args::try_parse_from(["test", "--order", "random", "--seed", "42", "ls"]).unwrap();
+        assert_eq!(args.order, Order::Random);
+        assert_eq!(args.seed, Some(42));
+        assert!(Args::try_parse_from(["test", "--order", "shuffled", "ls"]).is_err());
+    }
+
+    #[test]
+    fn test_args_sampling() {
+        let args = Args::try_parse_from([
+            "test",
+            "--min-runs",
+            "3",
+            "--max-runs",
+            "50",
+            "--time-budget",
+            "30s",
+            "--target-rel-ci",
+            "1%",
+            "ls",
+        ])
+        .unwrap();
+        let spec = &args.plan().unwrap()[0];
+        assert_eq!(spec.iterations, 3);
+        assert_eq!(spec.sampling.max_iterations, Some(50));
+        assert_eq!(spec.sampling.time_budget, Some(Duration::from_secs(30)));
+        assert_eq!(spec.sampling.target_rel_ci, Some(0.01));
+
+        let args = Args::try_parse_from(["test", "-i", "9", "--min-runs", "3", "ls"]).unwrap();
+        assert_eq!(args.plan().unwrap()[0].iterations, 3);
+    }
+
+    #[test]
+    fn test_args_exclude_outliers() {
+        assert!(
+            !Args::try_parse_from(["test", "ls"])
+                .unwrap()
+                .exclude_outliers
+        );
+        let args = Args::try_parse_from(["test", "run", "--exclude-outliers"]).unwrap();
+        assert!(args.exclude_outliers);
+    }
+
+    #[test]
+    fn test_args_scheduling() {
+        let args = Args::try_parse_from([
+            "test",
+            "--cpus",
+            "2,3",
+            "--nice",
+            "-10",
+            "--sched",
+            "fifo:50",
+            "--ionice",
+            "best-effort:0",
+            "ls",
+        ])
+        .unwrap();
+        let scheduling = &args.plan().unwrap()[0].scheduling;
+        assert_eq!(scheduling.cpus, Some(vec![2, 3]));
+        assert_eq!(scheduling.nice, Some(-10));
+        assert_eq!(scheduling.policy, Some(SchedPolicy::Fifo(50)));
+        assert_eq!(scheduling.io_priority, Some(IoPriority::BestEffort(0)));
+
+        assert!(Args::try_parse_from(["test", "--nice", "-21", "ls"]).is_err());
+        assert!(Args::try_parse_from(["test", "--sched", "fifo", "ls"]).is_err());
+    }
+
+    #[test]
+    fn test_args_limits() {
+        let args = Args::try_parse_from([
+            "test",
+            "--memory-limit",
+            "512M",
+            "--cpu-quota",
+            "1.5",
+            "--pids-max",
+            "64",
+            "--rlimit",
+            "nofile=1024",
+            "--rlimit",
+            "core=0",
+            "ls",
+        ])
+        .unwrap();
+        let limits = &args.plan().unwrap()[0].limits;
+        assert_eq!(limits.memory, Some(512 * 1024 * 1024));
+        assert_eq!(limits.cpu_quota, Some(1.5));
+        assert_eq!(limits.pids, Some(64));
+        assert_eq!(limits.rlimits.len(), 2);
+        assert_eq!(limits.rlimits[1].name, "core");
+
+        assert!(Args::try_parse_from(["test", "--cpu-quota", "0", "ls"]).is_err());
+        assert!(Args::try_parse_from(["test", "--rlimit", "files=1", "ls"]).is_err());
+    }
+
+    #[test]
+    fn test_args_cache() {
+        let args = Args::try_parse_from([
+            "test",
+            "--cache",
+            "cold",
+            "--cache-file",
+            "dict.txt",
+            "gzip file.txt",
+        ])
+        .unwrap();
+        let spec = &args.plan().unwrap()[0];
+        assert_eq!(spec.cache, Some(CacheMode::Cold));
+        assert_eq!(spec.cache_files, [PathBuf::from("dict.txt")]);
+
+        assert!(Args::try_parse_from(["test", "--cache", "hot", "ls"]).is_err());
+        assert!(Args::try_parse_from(["test", "--cache-file", "dict.txt", "ls"]).is_err());
+    }
+
+    #[test]
+    fn test_args_syscalls() {
+        let args = Args::try_parse_from(["test", "ls"]).unwrap();
+        assert!(!args.plan().unwrap()[0].syscalls);
+        let args = Args::try_parse_from(["test", "--syscalls", "ls"]).unwrap();
+        assert!(args.plan().unwrap()[0].syscalls);
+    }
+
+    #[test]
+    fn test_args_profile() {
+        let args = Args::try_parse_from(["test", "ls"]).unwrap();
+        assert_eq!(args.plan().unwrap()[0].profile, None);
+        let args = Args::try_parse_from(["test", "--profile", "ls"]).unwrap();
+        assert_eq!(args.profile, Some(PathBuf::from(PROFILE_DIR)));
+        assert_eq!(
+            args.plan().unwrap()[0].profile,
+            Some(profile::DEFAULT_FREQUENCY)
+        );
+        let args =
+            Args::try_parse_from(["test", "--profile=out", "--profile-frequency", "99", "ls"])
+                .unwrap();
+        assert_eq!(args.profile, Some(PathBuf::from("out")));
+        assert_eq!(args.plan().unwrap()[0].profile, Some(99));
+        assert!(Args::try_parse_from(["test", "--profile-frequency", "0", "ls"]).is_err());
+        let args = Args::try_parse_from(["test", "--profile-frequency", "49", "ls"]).unwrap();
+        assert_eq!(args.plan().unwrap()[0].profile, Some(49));
+    }
+
+    #[test]
+    fn test_file_stem() {
+        assert_eq!(file_stem("gzip -k -f -1 data.txt"), "gzip-k-f-1-data-txt");
+        assert_eq!(file_stem("compress (level=9)"), "compress-level-9");
+        assert_eq!(file_stem("!!"), "command");
+    }
+
+    #[test]
+    fn test_args_files() {
+        let args = Args::try_parse_from(["test", "--files", "ls"]).unwrap();
+        let spec = &args.plan().unwrap()[0];
+        assert!(spec.files);
+        assert!(!spec.syscalls);
+    }
 }
@@ -1,15 +1,29 @@
 use crate::process::ProcessInfo;
 
+/// How a running command is measured.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum MonitorKind {
+    /// Poll the command's own entries under /proc.
+    #[default]
+    Poll,
+    /// Run the command in its own cgroup v2 group, so everything it forks is
+    /// accounted for as well.
+    Cgroup,
+}
+
 pub trait Monitor {
     fn new(pid: u32) -> Self;
     fn read_cpu_usage(&mut self, proces_info: &mut ProcessInfo);
     fn read_memory_usage(&mut self, proces_info: &mut ProcessInfo);
     fn read_network_usage(&mut self, proces_info: &mut ProcessInfo);
     fn read_disk_usage(&mut self, proces_info: &mut ProcessInfo);
+    /// CPU time of each thread, for monitors that can tell threads apart.
+    fn read_thread_usage(&mut self, _proces_info: &mut ProcessInfo) {}
     fn scan(&mut self, proces_info: &mut ProcessInfo) {
         self.read_cpu_usage(proces_info);
         self.read_memory_usage(proces_info);
         self.read_network_usage(proces_info);
         self.read_disk_usage(proces_info);
+        self.read_thread_usage(proces_info);
     }
 }
@@ -0,0 +1,122 @@
+use std::hash::{DefaultHasher, Hasher};
+use std::path::PathBuf;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Where a benchmarked command's stdout goes.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum OutputMode {
+    /// Discarded by the kernel.
+    #[default]
+    Null,
+    /// Read by lens, so it can be fingerprinted.
+    Pipe,
+    /// Shared with lens's own stdout.
+    Inherit,
+    /// Written to a file, truncated on every run.
+    File(PathBuf),
+}
+
+impl std::str::FromStr for OutputMode {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text {
+            "null" => Ok(OutputMode::Null),
+            "pipe" => Ok(OutputMode::Pipe),
+            "inherit" => Ok(OutputMode::Inherit),
+            _ => match text.strip_prefix("file:") {
+                Some(path) if !path.is_empty() => Ok(OutputMode::File(PathBuf::from(path))),
+                _ => Err(format!(
+                    "expected null, pipe, inherit or file:<path>, got '{}'",
+                    text
+                )),
+            },
+        }
+    }
+}
+
+/// Fingerprint of everything a run wrote to stdout. Only comparable with
+/// digests taken by the same lens process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutputDigest {
+    pub hash: u64,
+    pub bytes: u64,
+}
+
+impl OutputDigest {
+    pub fn of(data: &[u8]) -> Self {
+        let mut hasher = DefaultHasher::new();
+        hasher.write(data);
+        OutputDigest {
+            hash: hasher.finish(),
+            bytes: data.len() as u64,
+        }
+    }
+
+    /// Reads a stream to the end, hashing it as it arrives so large outputs
+    /// are never held in memory.
+    pub(crate) async fn read(mut reader: impl AsyncRead + Unpin) -> std::io::Result<Self> {
+        let mut hasher = DefaultHasher::new();
+        let mut bytes = 0;
+        let mut buffer = vec![0; 64 * 1024];
+        loop {
+            let read = reader.read(&mut buffer).await?;
+            if read == 0 {
+                break;
+            }
+            hasher.write(&buffer[..read]);
+            bytes += read as u64;
+        }
+        Ok(OutputDigest {
+            hash: hasher.finish(),
+            bytes,
+        })
+    }
+}
+
+/// Number of different outputs among the runs.
+pub fn distinct(digests: &[OutputDigest]) -> usize {
+    let mut seen: Vec<&OutputDigest> = Vec::new();
+    for digest in digests {
+        if !seen.contains(&digest) {
+            seen.push(digest);
+        }
+    }
+    seen.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_output_mode() {
+        assert_eq!("null".parse(), Ok(OutputMode::Null));
+        assert_eq!("pipe".parse(), Ok(OutputMode::Pipe));
+        assert_eq!("inherit".parse(), Ok(OutputMode::Inherit));
+        assert_eq!(
+            "file:out/run.txt".parse(),
+            Ok(OutputMode::File(PathBuf::from("out/run.txt")))
+        );
+        assert!("file:".parse::<OutputMode>().is_err());
+        assert!("stdout".parse::<OutputMode>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_read_matches_of() {
+        let data = vec![7u8; 200 * 1024];
+        let streamed = OutputDigest::read(&data[..]).await.unwrap();
+        assert_eq!(streamed, OutputDigest::of(&data));
+        assert_eq!(streamed.bytes, 200 * 1024);
+        assert_ne!(streamed, OutputDigest::of(b"different"));
+    }
+
+    #[test]
+    fn test_distinct() {
+        let a = OutputDigest::of(b"a");
+        let b = OutputDigest::of(b"b");
+        assert_eq!(distinct(&[]), 0);
+        assert_eq!(distinct(&[a, a, a]), 1);
+        assert_eq!(distinct(&[a, b, a]), 2);
+    }
+}
@@ -0,0 +1,698 @@
+use crate::cache::{self, CacheMode};
+use crate::executor::{self, Executor};
+use crate::interrupt::Interrupt;
+use crate::limits::Limits;
+use crate::monitor::MonitorKind;
+use crate::output::OutputMode;
+use crate::process::{Outcome, ProcessInfo};
+use crate::profile::{self, Profile};
+use crate::scheduling::Scheduling;
+use crate::stats;
+use crate::syscalls::{SyscallProfile, Trace};
+use crate::threshold::Thresholds;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+
+/// The result of each measured iteration, in order.
+pub type Runs = Vec<Result<ProcessInfo, executor::Error>>;
+
+/// The runs of one benchmark, and the hook failure that ended it early, if any.
+#[derive(Debug, Default)]
+pub struct Measured {
+    pub runs: Runs,
+    /// The runs completed before a hook failed are kept all the same.
+    pub error: Option<String>,
+}
+
+impl Measured {
+    /// Keeps the first failure, as later ones are usually caused by it.
+    pub(crate) fn fail(&mut self, error: String) {
+        self.error.get_or_insert(error);
+    }
+
+    /// The runs that completed, and the first error of those that did not.
+    pub fn completed(self) -> (Vec<ProcessInfo>, Option<executor::Error>) {
+        let mut processes = Vec::new();
+        let mut first_error = None;
+        for run in self.runs {
+            match run {
+                Ok(process) => processes.push(process),
+                Err(e) => {
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        (processes, first_error)
+    }
+}
+
+/// Measured iterations of one benchmark so far.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Progress {
+    pub iterations: u32,
+    /// Time spent in measured iterations, hooks included.
+    pub spent: Duration,
+}
+
+impl Progress {
+    pub fn record(&mut self, started: Instant) {
+        self.iterations += 1;
+        self.spent += started.elapsed();
+    }
+}
+
+/// Shell commands run around the measured iterations. None of them are timed.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Hooks {
+    /// Once, before the first warmup run.
+    pub setup: Option<String>,
+    /// Before every run, warmups included.
+    pub prepare: Option<String>,
+    /// After every run, warmups included.
+    pub conclude: Option<String>,
+    /// Once, after the last run.
+    pub cleanup: Option<String>,
+}
+
+/// Cap on iterations when only a target precision is given, in case it is
+/// never reached.
+pub const DEFAULT_MAX_ITERATIONS: u32 = 1000;
+
+/// When to stop measuring past the minimum number of iterations. With none of
+/// these set exactly `iterations` are run.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Sampling {
+    pub max_iterations: Option<u32>,
+    /// Measured time after which no further iterations are started.
+    pub time_budget: Option<Duration>,
+    /// Relative half-width of the 95% confidence interval of the mean wall
+    /// time to reach, e.g. 0.01.
+    pub target_rel_ci: Option<f64>,
+}
+
+impl Sampling {
+    pub fn is_adaptive(&self) -> bool {
+        self.time_budget.is_some() || self.target_rel_ci.is_some()
+    }
+}
+
+/// Everything needed to benchmark one command. Both the command line and
+/// lens.toml are turned into a list of these before anything runs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchmarkSpec {
+    pub name: String,
+    pub command: String,
+    pub cwd: Option<PathBuf>,
+    pub env: Vec<(String, String)>,
+    pub env_clear: bool,
+    /// Fed to stdin on every run, warmups included.
+    pub input: Option<PathBuf>,
+    pub output: OutputMode,
+    /// What stdout must match on every measured run.
+    pub expected_output: Option<PathBuf>,
+    pub monitor: MonitorKind,
+    pub scheduling: Scheduling,
+    pub limits: Limits,
+    /// Page cache state every measured iteration starts from, left alone when unset.
+    pub cache: Option<CacheMode>,
+    /// Files to evict or read in besides the input and those named in the command.
+    pub cache_files: Vec<PathBuf>,
+    /// Profile syscalls in an extra traced run after the measured ones.
+    pub syscalls: bool,
+    /// Record the files the command uses, in the same traced run.
+    pub files: bool,
+    /// Sample the command's stacks at this many times a second, in an extra run.
+    pub profile: Option<u32>,
+    pub warmup: u32,
+    /// The number of iterations, or the minimum when sampling is adaptive.
+    pub iterations: u32,
+    pub sampling: Sampling,
+    /// Instances started together for each iteration.
+    pub concurrency: u32,
+    pub timeout: Option<Duration>,
+    pub hooks: Hooks,
+    pub thresholds: Thresholds,
+}
+
+impl BenchmarkSpec {
+    pub fn new(command: String) -> Self {
+        BenchmarkSpec {
+            name: command.clone(),
+            command,
+            cwd: None,
+            env: Vec::new(),
+            env_clear: false,
+            input: None,
+            output: OutputMode::Null,
+            expected_output: None,
+            monitor: MonitorKind::Poll,
+            scheduling: Scheduling::default(),
+            limits: Limits::default(),
+            cache: None,
+            cache_files: Vec::new(),
+            syscalls: false,
+            files: false,
+            profile: None,
+            warmup: 0,
+            iterations: 5,
+            sampling: Sampling::default(),
+            concurrency: 1,
+            timeout: None,
+            hooks: Hooks::default(),
+            thresholds: Thresholds::default(),
+        }
+    }
+
+    /// One spec per combination of parameter values, with every `{name}` in the
+    /// command and hooks replaced by the value.
+    pub fn expand(&self, parameters: &BTreeMap<String, Vec<String>>) -> Vec<BenchmarkSpec> {
+        let mut combinations: Vec<Vec<(&String, &String)>> = vec![Vec::new()];
+        for (name, values) in parameters {
+            combinations = combinations
+                .into_iter()
+                .flat_map(|combination| {
+                    values.iter().map(move |value| {
+                        let mut next = combination.clone();
+                        next.push((name, value));
+                        next
+                    })
+                })
+                .collect();
+        }
+        if parameters.is_empty() {
+            return vec![self.clone()];
+        }
+
+        combinations
+            .into_iter()
+            .map(|combination| {
+                let substitute = |text: &str| {
+                    combination
+                        .iter()
+                        .fold(text.to_string(), |text, (name, value)| {
+                            text.replace(&format!("{{{}}}", name), value)
+                        })
+                };
+                let label = combination
+                    .iter()
+                    .map(|(name, value)| format!("{}={}", name, value))
+                    .collect::<Vec<_>>()
+                    .join(",");
+
+                let mut spec = self.clone();
+                spec.name = format!("{} ({})", self.name, label);
+                spec.command = substitute(&self.command);
+                for hook in [
+                    &mut spec.hooks.setup,
+                    &mut spec.hooks.prepare,
+                    &mut spec.hooks.conclude,
+                    &mut spec.hooks.cleanup,
+                ] {
+                    *hook = hook.as_deref().map(substitute);
+                }
+                spec
+            })
+            .collect()
+    }
+
+    fn executor(&self, interrupt: Option<&Interrupt>) -> Executor {
+        Executor::new(self.command.clone())
+            .cwd(self.cwd.clone())
+            .env(self.env.clone())
+            .env_clear(self.env_clear)
+            .input(self.input.clone())
+            .output(self.output.clone())
+            .monitor(self.monitor)
+            .scheduling(self.scheduling.clone())
+            .limits(self.limits.clone())
+            .timeout(self.timeout)
+            .interrupt(interrupt.cloned())
+    }
+
+    /// Runs the hooks, warmups and measured iterations in order. A failing
+    /// iteration is kept as an error in the list, while a failing hook stops
+    /// the benchmark, keeping the iterations completed before it. With a
+    /// concurrency above one, every instance of an iteration is listed.
+    ///
+    /// Once interrupted, no further runs are started and the interrupted
+    /// iteration is dropped, leaving only complete ones. Once the setup hook
+    /// has succeeded, the cleanup hook runs however the benchmark ends.
+    pub async fn run(&self, interrupt: Option<&Interrupt>) -> Measured {
+        let mut measured = Measured::default();
+        if let Err(e) = self.setup().await {
+            measured.fail(e);
+            return measured;
+        }
+        if let Err(e) = self.measure(interrupt, &mut measured.runs).await {
+            measured.fail(e);
+        }
+        if let Err(e) = self.finish().await {
+            measured.fail(e);
+        }
+        measured
+    }
+
+    /// The warmups and measured iterations, added to `runs` as they complete.
+    async fn measure(&self, interrupt: Option<&Interrupt>, runs: &mut Runs) -> Result<(), String> {
+        self.warmup(interrupt).await?;
+        let mut progress = Progress::default();
+        while !self.done(&progress, runs) {
+            if interrupt.is_some_and(Interrupt::is_interrupted) {
+                break;
+            }
+            let started = Instant::now();
+            runs.extend(self.iteration(interrupt).await?);
+            progress.record(started);
+        }
+        Ok(())
+    }
+
+    /// Whether enough iterations have been measured.
+    pub(crate) fn done(&self, progress: &Progress, runs: &Runs) -> bool {
+        let sampling = &self.sampling;
+        let max = match sampling.max_iterations {
+            Some(max) => max,
+            None if sampling.target_rel_ci.is_some() && sampling.time_budget.is_none() => {
+                DEFAULT_MAX_ITERATIONS.max(self.iterations)
+            }
+            None => u32::MAX,
+        };
+        if progress.iterations >= max {
+            return true;
+        }
+        if progress.iterations < self.iterations {
+            return false;
+        }
+        let precise = sampling.target_rel_ci.map(|target| {
+            let durations: Vec<f64> = runs
+                .iter()
+                .filter_map(|run| run.as_ref().ok()?.duration)
+                .map(|d| d.as_secs_f64())
+                .collect();
+            stats::relative_ci(&durations).is_some_and(|ci| ci <= target)
+        });
+        let spent = sampling.time_budget.map(|budget| progress.spent >= budget);
+        match (precise, spent) {
+            (None, None) => true,
+            (precise, spent) => precise.unwrap_or(false) || spent.unwrap_or(false),
+        }
+    }
+
+    /// Runs the setup hook.
+    pub(crate) async fn setup(&self) -> Result<(), String> {
+        self.hook("setup", &self.hooks.setup).await
+    }
+
+    /// Runs the warmups between their hooks.
+    pub(crate) async fn warmup(&self, interrupt: Option<&Interrupt>) -> Result<(), String> {
+        for _ in 0..self.warmup {
+            if interrupt.is_some_and(Interrupt::is_interrupted) {
+                break;
+            }
+            self.hook("prepare", &self.hooks.prepare).await?;
+            self.batch(interrupt).await;
+            self.hook("conclude", &self.hooks.conclude).await?;
+        }
+        Ok(())
+    }
+
+    /// Runs one measured iteration between its hooks. Empty if it was interrupted.
+    pub(crate) async fn iteration(&self, interrupt: Option<&Interrupt>) -> Result<Runs, String> {
+        self.hook("prepare", &self.hooks.prepare).await?;
+        if let Some(mode) = self.cache {
+            cache::prepare(mode, &self.cached_files())?;
+        }
+        let mut batch = self.batch(interrupt).await;
+        let interrupted = batch
+            .iter()
+            .any(|run| matches!(run, Ok(process) if process.outcome == Outcome::Interrupted));
+        if interrupted {
+            batch.clear();
+        }
+        self.hook("conclude", &self.hooks.conclude).await?;
+        Ok(batch)
+    }
+
+    /// A separate run of the command under ptrace for its syscall profile, with
+    /// all of its hooks. Its timings are skewed by the tracing, so it never
+    /// counts as an iteration.
+    pub async fn trace_syscalls(
+        &self,
+        interrupt: Option<&Interrupt>,
+    ) -> Result<SyscallProfile, String> {
+        let executor = self.executor(interrupt);
+        self.extra_run(executor.trace_syscalls()).await
+    }
+
+    /// Like [`BenchmarkSpec::trace_syscalls`], and with `files` also the file
+    /// report, from the same run.
+    pub async fn trace(&self, interrupt: Option<&Interrupt>) -> Result<Trace, String> {
+        let executor = self.executor(interrupt);
+        self.extra_run(executor.trace(self.files)).await
+    }
+
+    /// A separate run of the command with its stacks sampled, at the
+    /// frequency `profile` holds.
+    pub async fn profile(&self, interrupt: Option<&Interrupt>) -> Result<Profile, String> {
+        let frequency = self.profile.unwrap_or(profile::DEFAULT_FREQUENCY);
+        let executor = self.executor(interrupt);
+        self.extra_run(executor.profile(frequency)).await
+    }
+
+    /// Runs the command once outside the measured iterations, with all of
+    /// its hooks. Once the setup hook has succeeded, the cleanup hook runs
+    /// however the run ends, and the first error is returned.
+    async fn extra_run<T>(
+        &self,
+        run: impl Future<Output = Result<T, executor::Error>>,
+    ) -> Result<T, String> {
+        self.setup().await?;
+        let result = self.extra_iteration(run).await;
+        let finished = self.finish().await;
+        result.and_then(|value| finished.map(|()| value))
+    }
+
+    /// The run of `extra_run` between its prepare and conclude hooks.
+    async fn extra_iteration<T>(
+        &self,
+        run: impl Future<Output = Result<T, executor::Error>>,
+    ) -> Result<T, String> {
+        self.hook("prepare", &self.hooks.prepare).await?;
+        if let Some(mode) = self.cache {
+            cache::prepare(mode, &self.cached_files())?;
+        }
+        let result = run.await.map_err(|e| e.to_string());
+        let concluded = self.hook("conclude", &self.hooks.conclude).await;
+        result.and_then(|value| concluded.map(|()| value))
+    }
+
+    /// Files whose caching --cache controls: the input, the cache files and
+    /// every word of the command naming an existing file. Looked up
+    /// before each iteration, as a prepare hook may have created them.
+    pub fn cached_files(&self) -> Vec<PathBuf> {
+        // Words are relative to where the command runs
+        let words = shell_words::split(&self.command).unwrap_or_default();
+        let named = words
+            .iter()
+            .map(|word| match &self.cwd {
+                Some(cwd) => cwd.join(word),
+                None => PathBuf::from(word),
+            })
+            .filter(|path| path.is_file());
+        let mut files = Vec::new();
+        for file in self
+            .input
+            .iter()
+            .chain(&self.cache_files)
+            .cloned()
+            .chain(named)
+        {
+            if !files.contains(&file) {
+                files.push(file);
+            }
+        }
+        files
+    }
+
+    /// Runs the cleanup hook.
+    pub(crate) async fn finish(&self) -> Result<(), String> {
+        self.hook("cleanup", &self.hooks.cleanup).await
+    }
+
+    /// Starts one iteration's instances together and waits for all of them.
+    async fn batch(&self, interrupt: Option<&Interrupt>) -> Runs {
+        let mut instances = tokio::task::JoinSet::new();
+        for _ in 0..self.concurrency.max(1) {
+            let executor = self.executor(interrupt);
+            instances.spawn(async move { executor.execute().await });
+        }
+        let mut runs = Vec::new();
+        while let Some(run) = instances.join_next().await {
+            runs.push(run.unwrap_or_else(|e| Err(e.into())));
+        }
+        runs
+    }
+
+    /// Runs a hook through the shell, in the benchmark's directory and environment.
+    async fn hook(&self, stage: &str, hook: &Option<String>) -> Result<(), String> {
+        let Some(hook) = hook else {
+            return Ok(());
+        };
+        let mut command = tokio::process::Command::new("sh");
+        if self.env_clear {
+            command.env_clear();
+        }
+        command
+            .arg("-c")
+            .arg(hook)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .envs(self.env.iter().map(|(k, v)| (k, v)));
+        if let Some(cwd) = &self.cwd {
+            command.current_dir(cwd);
+        }
+        // Shielded from a terminal Ctrl-C like the command, so cleanup still happens
+        command.process_group(0);
+        let status = command
+            .status()
+            .await
+            .map_err(|e| format!("Failed to run {} hook '{}': {}", stage, hook, e))?;
+        if !status.success() {
+            return Err(format!(
+                "The {} hook '{}' failed with {}",
+                stage, hook, status
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parameters(entries: &[(&str, &[&str])]) -> BTreeMap<String, Vec<String>> {
+        entries
+            .iter()
+            .map(|(name, values)| {
+                (
+                    name.to_string(),
+                    values.iter().map(|v| v.to_string()).collect(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_new_defaults() {
+        let spec = BenchmarkSpec::new(String::from("ruby a.rb"));
+        assert_eq!(spec.name, "ruby a.rb");
+        assert_eq!(spec.warmup, 0);
+        assert_eq!(spec.iterations, 5);
+        assert!(spec.thresholds.is_empty());
+    }
+
+    #[test]
+    fn test_expand_without_parameters() {
+        let spec = BenchmarkSpec::new(String::from("ls"));
+        assert_eq!(spec.expand(&BTreeMap::new()), vec![spec]);
+    }
+
+    #[test]
+    fn test_expand_cartesian_product() {
+        let mut spec = BenchmarkSpec::new(String::from("gzip -{level} {file}"));
+        spec.name = String::from("gzip");
+        spec.hooks.prepare = Some(String::from("cp {file}.orig {file}"));
+
+        let specs = spec.expand(&parameters(&[
+            ("file", &["a.txt", "b.txt"]),
+            ("level", &["1", "9"]),
+        ]));
+        assert_eq!(specs.len(), 4);
+        assert_eq!(specs[0].name, "gzip (file=a.txt,level=1)");
+        assert_eq!(specs[0].command, "gzip -1 a.txt");
+        assert_eq!(specs[3].command, "gzip -9 b.txt");
+        assert_eq!(
+            specs[1].hooks.prepare.as_deref(),
+            Some("cp a.txt.orig a.txt")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failing_hook_stops_benchmark() {
+        let mut spec = BenchmarkSpec::new(String::from("true"));
+        spec.hooks.setup = Some(String::from("exit 3"));
+        let measured = spec.run(None).await;
+        assert!(measured.runs.is_empty());
+        assert!(
+            measured
+                .error
+                .unwrap()
+                .contains("setup hook 'exit 3' failed")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failing_prepare_hook_keeps_runs_and_cleans_up() {
+        let dir = std::env::temp_dir().join(format!("lens-cleanup-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut spec = BenchmarkSpec::new(String::from("true"));
+        spec.cwd = Some(dir.clone());
+        // Fails on the third iteration
+        spec.hooks.prepare = Some(String::from("echo >> count; [ $(wc -l < count) -lt 3 ]"));
+        spec.hooks.cleanup = Some(String::from("touch cleaned"));
+        let measured = spec.run(None).await;
+        let cleaned = dir.join("cleaned").exists();
+        let _ = std::fs::remove_dir_all(&dir);
+        assert_eq!(measured.runs.len(), 2);
+        assert!(measured.error.unwrap().contains("prepare hook"));
+        assert!(cleaned);
+    }
+
+    #[tokio::test]
+    async fn test_extra_run_cleans_up_after_failing_hook() {
+        let dir = std::env::temp_dir().join(format!("lens-extra-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut spec = BenchmarkSpec::new(String::from("true"));
+        spec.cwd = Some(dir.clone());
+        spec.hooks.prepare = Some(String::from("false"));
+        spec.hooks.cleanup = Some(String::from("touch cleaned"));
+        let result = spec.extra_run(async { Ok(()) }).await;
+        let cleaned = dir.join("cleaned").exists();
+        let _ = std::fs::remove_dir_all(&dir);
+        assert!(result.unwrap_err().contains("prepare hook"));
+        assert!(cleaned);
+    }
+
+    #[tokio::test]
+    async fn test_hook_uses_cwd_and_env() {
+        let dir = std::env::temp_dir().join(format!("lens-hook-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut spec = BenchmarkSpec::new(String::from("true"));
+        spec.cwd = Some(dir.clone());
+        spec.env = vec![(String::from("LENS_TEST"), String::from("42"))];
+        spec.hook("setup", &Some(String::from("echo $LENS_TEST > out.txt")))
+            .await
+            .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(dir.join("out.txt")).unwrap(),
+            "42\n"
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_interrupt_keeps_completed_iterations() {
+        let (sender, interrupt) = Interrupt::manual();
+        let mut spec = BenchmarkSpec::new(String::from("sleep 0.2"));
+        spec.iterations = 10;
+        let interrupter = tokio::spawn(async move {
+            tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+            sender.send_modify(|state| {
+                state.count = 1;
+                state.signal = libc::SIGINT;
+            });
+        });
+        let measured = spec.run(Some(&interrupt)).await;
+        assert_eq!(measured.error, None);
+        let runs = measured.runs;
+        interrupter.await.unwrap();
+        assert!((1..10).contains(&runs.len()), "{} runs", runs.len());
+        assert!(
+            runs.iter()
+                .all(|run| run.as_ref().unwrap().status == Some(0))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_instances_overlap() {
+        let mut spec = BenchmarkSpec::new(String::from("sleep 0.2"));
+        spec.iterations = 2;
+        spec.concurrency = 3;
+        let started = std::time::Instant::now();
+        let measured = spec.run(None).await;
+        assert_eq!(measured.error, None);
+        let runs = measured.runs;
+        assert_eq!(runs.len(), 6);
+        // Two rounds of three overlapping instances, not six in a row
+        assert!(started.elapsed() < std::time::Duration::from_millis(1000));
+    }
+
+    fn finished_runs(seconds: &[f64]) -> Runs {
+        seconds
+            .iter()
+            .map(|&s| {
+                let mut process = ProcessInfo::new(String::from("true"), Vec::new());
+                process.duration = Some(Duration::from_secs_f64(s));
+                Ok(process)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_done_fixed_iterations() {
+        let mut spec = BenchmarkSpec::new(String::from("true"));
+        spec.iterations = 3;
+        let progress = |iterations| Progress {
+            iterations,
+            spent: Duration::ZERO,
+        };
+        assert!(!spec.done(&progress(2), &Vec::new()));
+        assert!(spec.done(&progress(3), &Vec::new()));
+    }
+
+    #[test]
+    fn test_done_adaptive() {
+        let mut spec = BenchmarkSpec::new(String::from("true"));
+        spec.iterations = 2;
+        spec.sampling.target_rel_ci = Some(0.01);
+        spec.sampling.time_budget = Some(Duration::from_secs(10));
+        let progress = |iterations, spent| Progress {
+            iterations,
+            spent: Duration::from_secs(spent),
+        };
+        let noisy = finished_runs(&[1.0, 2.0, 3.0]);
+        let steady = finished_runs(&[1.0, 1.0, 1.0]);
+        assert!(!spec.done(&progress(1, 0), &finished_runs(&[1.0])));
+        assert!(!spec.done(&progress(3, 5), &noisy));
+        assert!(spec.done(&progress(3, 5), &steady));
+        assert!(spec.done(&progress(3, 10), &noisy));
+
+        spec.sampling.max_iterations = Some(3);
+        assert!(spec.done(&progress(3, 5), &noisy));
+    }
+
+    #[tokio::test]
+    async fn test_run_until_time_budget() {
+        let mut spec = BenchmarkSpec::new(String::from("sleep 0.05"));
+        spec.iterations = 2;
+        spec.sampling.time_budget = Some(Duration::from_millis(300));
+        let measured = spec.run(None).await;
+        assert_eq!(measured.error, None);
+        let runs = measured.runs;
+        assert!((5..=8).contains(&runs.len()), "{} runs", runs.len());
+    }
+
+    #[test]
+    fn test_cached_files() {
+        let dir = std::env::temp_dir().join(format!("lens-cached-files-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("data.txt"), "data").unwrap();
+        let mut spec = BenchmarkSpec::new(String::from("gzip -k data.txt missing.txt"));
+        spec.cwd = Some(dir.clone());
+        spec.input = Some(PathBuf::from("/etc/hostname"));
+        spec.cache_files = vec![PathBuf::from("/etc/hostname"), dir.join("extra")];
+        let files = spec.cached_files();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            files,
+            [
+                PathBuf::from("/etc/hostname"),
+                dir.join("extra"),
+                dir.join("data.txt")
+            ]
+        );
+    }
+}
@@ -0,0 +1,435 @@
+use crate::linux::clock_ticks_per_sec;
+use crate::process::{ProcessInfo, Sample};
+
+/// Chart size in terminal cells. Each braille cell packs 2x4 dots.
+const CHART_WIDTH: usize = 60;
+const CHART_HEIGHT: usize = 8;
+const DOTS_WIDTH: usize = CHART_WIDTH * 2;
+const DOTS_HEIGHT: usize = CHART_HEIGHT * 4;
+const LABEL_WIDTH: usize = 9;
+
+const HISTOGRAM_WIDTH: usize = 40;
+const MAX_HISTOGRAM_BINS: usize = 10;
+
+const COLORS: [&str; 6] = [
+    "\x1b[36m", "\x1b[33m", "\x1b[35m", "\x1b[32m", "\x1b[34m", "\x1b[31m",
+];
+const RESET: &str = "\x1b[0m";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum PlotMode {
+    /// One CPU and one RSS chart per command
+    Separate,
+    /// All commands drawn on shared CPU and RSS charts
+    Overlay,
+}
+
+/// A line chart of one or more series sharing the same time axis.
+pub struct Chart {
+    title: String,
+    span: f64,
+    series: Vec<(String, Vec<Option<f64>>)>,
+}
+
+impl Chart {
+    pub fn cpu(runs: &[(String, &ProcessInfo)]) -> Self {
+        let span = Self::span(runs);
+        let series = runs
+            .iter()
+            .map(|(label, run)| {
+                (
+                    label.clone(),
+                    cpu_columns(&run.stat.cpu_ticks, span, DOTS_WIDTH),
+                )
+            })
+            .collect();
+        Chart {
+            title: String::from("CPU usage (%)"),
+            span,
+            series,
+        }
+    }
+
+    pub fn memory(runs: &[(String, &ProcessInfo)]) -> Self {
+        let span = Self::span(runs);
+        let series = runs
+            .iter()
+            .map(|(label, run)| {
+                let columns = memory_columns(&run.stat.memory_kb, span, DOTS_WIDTH)
+                    .into_iter()
+                    .map(|kb| kb.map(|kb| kb / 1024.0))
+                    .collect();
+                (label.clone(), columns)
+            })
+            .collect();
+        Chart {
+            title: String::from("Memory RSS (MB)"),
+            span,
+            series,
+        }
+    }
+
+    /// Longest run, so every series fits on the shared x axis.
+    fn span(runs: &[(String, &ProcessInfo)]) -> f64 {
+        runs.iter()
+            .map(|(_, run)| {
+                let last_sample = run
+                    .stat
+                    .memory_kb
+                    .iter()
+                    .chain(&run.stat.cpu_ticks)
+                    .map(|s| s.elapsed)
+                    .max()
+                    .unwrap_or_default();
+                run.duration
+                    .unwrap_or_default()
+                    .max(last_sample)
+                    .as_secs_f64()
+            })
+            .fold(0.0, f64::max)
+    }
+
+    pub fn render(&self, color: bool) -> String {
+        let y_max = self
+            .series
+            .iter()
+            .flat_map(|(_, columns)| columns.iter().flatten())
+            .fold(0.0_f64, |a, &b| a.max(b));
+        let y_max = if y_max > 0.0 { y_max } else { 1.0 };
+
+        let mut canvas = Canvas::new();
+        for (index, (_, columns)) in self.series.iter().enumerate() {
+            let mut previous: Option<usize> = None;
+            for (x, value) in columns.iter().enumerate() {
+                let Some(value) = value else {
+                    previous = None;
+                    continue;
+                };
+                let y = ((value / y_max) * (DOTS_HEIGHT - 1) as f64).round() as usize;
+                // Fill the vertical gap so steep changes stay connected
+                let (low, high) = match previous {
+                    Some(p) => (p.min(y), p.max(y)),
+                    None => (y, y),
+                };
+                for dot in low..=high {
+                    canvas.set(x, dot, index);
+                }
+                previous = Some(y);
+            }
+        }
+
+        let mut out = format!("  {}\n", self.title);
+        for row in 0..CHART_HEIGHT {
+            let label = match row {
+                0 => format!("{:.1}", y_max),
+                r if r == CHART_HEIGHT - 1 => String::from("0.0"),
+                _ => String::new(),
+            };
+            let axis = if label.is_empty() { '│' } else { '┤' };
+            out.push_str(&format!("{:>width$} {}", label, axis, width = LABEL_WIDTH));
+            out.push_str(&canvas.row(row, color));
+            out.push('\n');
+        }
+        out.push_str(&format!(
+            "{:>width$} └{}\n",
+            "",
+            "─".repeat(CHART_WIDTH),
+            width = LABEL_WIDTH
+        ));
+        let end = format!("{:.3}s", self.span);
+        out.push_str(&format!(
+            "{:>width$}  0s{:>rest$}\n",
+            "",
+            end,
+            width = LABEL_WIDTH,
+            rest = CHART_WIDTH - 2
+        ));
+
+        if self.series.len() > 1 {
+            for (index, (label, _)) in self.series.iter().enumerate() {
+                let (start, end) = if color {
+                    (COLORS[index % COLORS.len()], RESET)
+                } else {
+                    ("", "")
+                };
+                out.push_str(&format!(
+                    "{:>width$} {}■{} {}\n",
+                    "",
+                    start,
+                    end,
+                    label,
+                    width = LABEL_WIDTH
+                ));
+            }
+        }
+        out
+    }
+}
+
+/// Braille dot grid. Each cell remembers which series drew into it last so
+/// overlaid series can be told apart by color.
+struct Canvas {
+    dots: Vec<u8>,
+    owners: Vec<Option<usize>>,
+}
+
+impl Canvas {
+    fn new() -> Self {
+        Canvas {
+            dots: vec![0; CHART_WIDTH * CHART_HEIGHT],
+            owners: vec![None; CHART_WIDTH * CHART_HEIGHT],
+        }
+    }
+
+    /// Sets the dot at `x` from the left and `y` from the bottom.
+    fn set(&mut self, x: usize, y: usize, series: usize) {
+        if x >= DOTS_WIDTH || y >= DOTS_HEIGHT {
+            return;
+        }
+        let y = DOTS_HEIGHT - 1 - y;
+        let cell = (y / 4) * CHART_WIDTH + x / 2;
+        // Braille dot numbering, column by column, with the bottom row last
+        let bit = match (x % 2, y % 4) {
+            (0, 0) => 0x01,
+            (0, 1) => 0x02,
+            (0, 2) => 0x04,
+            (1, 0) => 0x08,
+            (1, 1) => 0x10,
+            (1, 2) => 0x20,
+            (0, 3) => 0x40,
+            _ => 0x80,
+        };
+        self.dots[cell] |= bit;
+        self.owners[cell] = Some(series);
+    }
+
+    fn row(&self, row: usize, color: bool) -> String {
+        let mut out = String::new();
+        for column in 0..CHART_WIDTH {
+            let cell = row * CHART_WIDTH + column;
+            let glyph = char::from_u32(0x2800 + self.dots[cell] as u32).unwrap_or(' ');
+            match self.owners[cell] {
+                Some(series) if color => {
+                    out.push_str(COLORS[series % COLORS.len()]);
+                    out.push(glyph);
+                    out.push_str(RESET);
+                }
+                _ => out.push(glyph),
+            }
+        }
+        out
+    }
+}
+
+/// Renders a histogram of wall times, one horizontal bar per bin.
+pub fn histogram(durations: &[f64]) -> String {
+    let mut out = format!("  Wall time histogram ({} runs)\n", durations.len());
+    if durations.is_empty() {
+        return out;
+    }
+    let min = durations.iter().cloned().fold(f64::INFINITY, f64::min);
+    let max = durations.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    let bins = if max > min {
+        ((durations.len() as f64).sqrt().ceil() as usize).clamp(1, MAX_HISTOGRAM_BINS)
+    } else {
+        1
+    };
+    let width = (max - min) / bins as f64;
+
+    let mut counts = vec![0usize; bins];
+    for duration in durations {
+        let bin = if width > 0.0 {
+            (((duration - min) / width) as usize).min(bins - 1)
+        } else {
+            0
+        };
+        counts[bin] += 1;
+    }
+
+    let most = counts.iter().copied().max().unwrap_or(1).max(1);
+    for (bin, count) in counts.iter().enumerate() {
+        let low = min + width * bin as f64;
+        let high = low + width;
+        out.push_str(&format!(
+            "  {:>9.3}s - {:>9.3}s │{} {}\n",
+            low,
+            high,
+            bar(*count as f64 / most as f64 * HISTOGRAM_WIDTH as f64),
+            count
+        ));
+    }
+    out
+}
+
+/// A horizontal bar `length` cells long, using eighth blocks for the remainder.
+fn bar(length: f64) -> String {
+    const EIGHTHS: [char; 8] = [' ', '▏', '▎', '▍', '▌', '▋', '▊', '▉'];
+    let eighths = (length * 8.0).round() as usize;
+    let mut out = "█".repeat(eighths / 8);
+    if !eighths.is_multiple_of(8) {
+        out.push(EIGHTHS[eighths % 8]);
+    }
+    out
+}
+
+/// The run with the median wall time, used as the representative timeline.
+pub fn representative(processes: &[ProcessInfo]) -> Option<&ProcessInfo> {
+    let mut sorted: Vec<&ProcessInfo> = processes.iter().collect();
+    sorted.sort_by_key(|p| p.duration.unwrap_or_default());
+    sorted.get(sorted.len() / 2).copied()
+}
+
+/// Reading in effect at `at` seconds, i.e. the latest sample taken by then.
+fn value_at(samples: &[Sample], at: f64) -> Option<u64> {
+    let index = samples.partition_point(|s| s.elapsed.as_secs_f64() <= at);
+    index.checked_sub(1).map(|i| samples[i].value)
+}
+
+/// CPU utilisation over `columns` equal slices of `span` seconds, from
+/// cumulative tick samples.
+pub(crate) fn cpu_columns(samples: &[Sample], span: f64, columns: usize) -> Vec<Option<f64>> {
+    let last = samples.last().map(|s| s.elapsed.as_secs_f64());
+    let width = span / columns as f64;
+    (0..columns)
+        .map(|column| {
+            let start = column as f64 * width;
+            let end = start + width;
+            if width <= 0.0 || last.is_none_or(|last| start > last) {
+                return None;
+            }
+            let before = value_at(samples, start).unwrap_or(0);
+            let after = value_at(samples, end).unwrap_or(before);
+            let seconds = after.saturating_sub(before) as f64 / clock_ticks_per_sec() as f64;
+            Some(seconds / width * 100.0)
+        })
+        .collect()
+}
+
+/// Peak RSS over `columns` equal slices of `span` seconds, carrying the last
+/// reading through slices that happened to get no sample.
+pub(crate) fn memory_columns(samples: &[Sample], span: f64, columns: usize) -> Vec<Option<f64>> {
+    let last = samples.last().map(|s| s.elapsed.as_secs_f64());
+    let width = span / columns as f64;
+    (0..columns)
+        .map(|column| {
+            let start = column as f64 * width;
+            let end = start + width;
+            if width <= 0.0 || last.is_none_or(|last| start > last) {
+                return None;
+            }
+            let within = samples
+                .iter()
+                .filter(|s| (start..end).contains(&s.elapsed.as_secs_f64()))
+                .map(|s| s.value)
+                .max();
+            within
+                .or_else(|| value_at(samples, start))
+                .map(|kb| kb as f64)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn samples(values: &[(u64, u64)]) -> Vec<Sample> {
+        values
+            .iter()
+            .map(|&(ms, value)| Sample::new(Duration::from_millis(ms), value))
+            .collect()
+    }
+
+    #[test]
+    fn test_canvas_braille_bits() {
+        let mut canvas = Canvas::new();
+        // Bottom-left dot of the bottom-left cell
+        canvas.set(0, 0, 0);
+        let row = canvas.row(CHART_HEIGHT - 1, false);
+        assert_eq!(row.chars().next(), Some('\u{2840}'));
+
+        // Top-right dot of the top-left cell
+        canvas.set(1, DOTS_HEIGHT - 1, 0);
+        let row = canvas.row(0, false);
+        assert_eq!(row.chars().next(), Some('\u{2808}'));
+    }
+
+    #[test]
+    fn test_cpu_columns_full_core() {
+        // One full core: 100 ticks per second of wall time
+        let ticks = samples(&[(0, 0), (500, 50), (1000, 100)]);
+        let columns = cpu_columns(&ticks, 1.0, DOTS_WIDTH);
+        assert_eq!(columns.len(), DOTS_WIDTH);
+        let total: f64 = columns.iter().flatten().sum();
+        let average = total / columns.iter().flatten().count() as f64;
+        assert!((average - 100.0).abs() < 1.0);
+    }
+
+    #[test]
+    fn test_memory_columns_stop_after_last_sample() {
+        let rss = samples(&[(0, 1024), (500, 2048)]);
+        let columns = memory_columns(&rss, 1.0, DOTS_WIDTH);
+        assert_eq!(columns[0], Some(1024.0));
+        assert_eq!(columns[DOTS_WIDTH / 2], Some(2048.0));
+        assert_eq!(columns[DOTS_WIDTH - 1], None);
+    }
+
+    #[test]
+    fn test_histogram_counts() {
+        let rendered = histogram(&[1.0, 1.0, 1.1, 2.0]);
+        assert!(rendered.contains("(4 runs)"));
+        let counts: usize = rendered
+            .lines()
+            .skip(1)
+            .map(|l| l.rsplit(' ').next().unwrap().parse::<usize>().unwrap())
+            .sum();
+        assert_eq!(counts, 4);
+    }
+
+    #[test]
+    fn test_histogram_identical_durations() {
+        let rendered = histogram(&[0.5, 0.5, 0.5]);
+        assert_eq!(rendered.lines().count(), 2);
+        assert!(rendered.ends_with(" 3\n"));
+    }
+
+    #[test]
+    fn test_bar_eighths() {
+        assert_eq!(bar(2.0), "██");
+        assert_eq!(bar(1.5), "█▌");
+        assert_eq!(bar(0.0), "");
+    }
+
+    #[test]
+    fn test_representative_is_median() {
+        let runs: Vec<ProcessInfo> = [3, 1, 2]
+            .iter()
+            .map(|&secs| {
+                let mut p = ProcessInfo::new(String::from("ls"), vec![]);
+                p.duration = Some(Duration::from_secs(secs));
+                p
+            })
+            .collect();
+        let median = representative(&runs).unwrap();
+        assert_eq!(median.duration, Some(Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn test_render_overlay_has_legend() {
+        let mut first = ProcessInfo::new(String::from("a"), vec![]);
+        first.duration = Some(Duration::from_secs(1));
+        first.stat.memory_kb = samples(&[(0, 1024), (900, 4096)]);
+        let mut second = first.clone();
+        second.stat.memory_kb = samples(&[(0, 2048)]);
+
+        let runs = vec![
+            (String::from("first"), &first),
+            (String::from("second"), &second),
+        ];
+        let rendered = Chart::memory(&runs).render(false);
+        assert!(rendered.contains("■ first"));
+        assert!(rendered.contains("■ second"));
+        assert!(rendered.contains("4.0 ┤"));
+    }
+}
@@ -0,0 +1,117 @@
+use crate::system::SystemInfo;
+use crate::units;
+
+/// 1 minute load average per logical CPU above which other work is likely to
+/// compete with the benchmark.
+const MAX_LOAD_PER_CPU: f64 = 0.5;
+/// Fraction of memory that should be available, so the command's pages and
+/// the page cache are not squeezed.
+const MIN_AVAILABLE_MEMORY: f64 = 0.1;
+
+/// Conditions of the machine that make measurements noisy or unrepresentative,
+/// one message each. Empty when nothing looks wrong or could not be checked.
+pub fn check(system: &SystemInfo) -> Vec<String> {
+    let mut problems = Vec::new();
+    let load = system.load_average[0];
+    if system.cpus > 0 && load / system.cpus as f64 > MAX_LOAD_PER_CPU {
+        problems.push(format!(
+            "the load average is {:.2} on {} CPUs, other processes are competing for the CPU",
+            load, system.cpus
+        ));
+    }
+    if let Some(governor) = &system.governor
+        && governor != "performance"
+    {
+        problems.push(format!(
+            "the CPU frequency governor is '{}' rather than 'performance', clock speed will vary",
+            governor
+        ));
+    }
+    if system.turbo == Some(true) {
+        problems.push(String::from(
+            "turbo boost is enabled, clock speed depends on temperature and how many cores are busy",
+        ));
+    }
+    if system.memory_total_kb > 0
+        && (system.memory_available_kb as f64)
+            < system.memory_total_kb as f64 * MIN_AVAILABLE_MEMORY
+    {
+        problems.push(format!(
+            "only {} of {} memory is available",
+            units::format_bytes(system.memory_available_kb as f64 * 1024.0),
+            units::format_bytes(system.memory_total_kb as f64 * 1024.0)
+        ));
+    }
+    if system.swap_used_kb > 0 {
+        problems.push(format!(
+            "{} of swap is in use, pages may be swapped in during runs",
+            units::format_bytes(system.swap_used_kb as f64 * 1024.0)
+        ));
+    }
+    if system.on_battery == Some(true) {
+        problems.push(String::from(
+            "running on battery, power saving may throttle the CPU",
+        ));
+    }
+    if let Some(limit) = system.cgroup_cpu_limit
+        && limit < system.cpus as f64
+    {
+        problems.push(format!(
+            "lens runs in a cgroup limited to {:.2} of {} CPUs",
+            limit, system.cpus
+        ));
+    }
+    if let Some(throttled) = system.cgroup_throttled
+        && throttled > 0
+    {
+        problems.push(format!(
+            "the cgroup lens runs in was just CPU throttled {} times, other processes in it are using up its quota",
+            throttled
+        ));
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quiet_system() -> SystemInfo {
+        let mut system = SystemInfo::collect();
+        system.cpus = 4;
+        system.load_average = [0.1, 0.1, 0.1];
+        system.governor = Some(String::from("performance"));
+        system.turbo = Some(false);
+        system.memory_total_kb = 8 * 1024 * 1024;
+        system.memory_available_kb = 6 * 1024 * 1024;
+        system.swap_used_kb = 0;
+        system.on_battery = None;
+        system.cgroup_cpu_limit = None;
+        system.cgroup_throttled = Some(0);
+        system
+    }
+
+    #[test]
+    fn test_quiet_system_passes() {
+        assert!(check(&quiet_system()).is_empty());
+    }
+
+    #[test]
+    fn test_noisy_system() {
+        let mut system = quiet_system();
+        system.load_average = [3.0, 1.0, 1.0];
+        system.governor = Some(String::from("powersave"));
+        system.turbo = Some(true);
+        system.memory_available_kb = 512 * 1024;
+        system.swap_used_kb = 1024;
+        system.on_battery = Some(true);
+        system.cgroup_cpu_limit = Some(1.5);
+        system.cgroup_throttled = Some(12);
+        let problems = check(&system);
+        assert_eq!(problems.len(), 8, "{:?}", problems);
+        assert!(problems[0].contains("load average is 3.00 on 4 CPUs"));
+        assert!(problems[1].contains("'powersave'"));
+        assert!(problems[3].contains("only 512.0 MB of 8.0 GB"));
+        assert!(problems[7].contains("just CPU throttled 12 times"));
+    }
+}
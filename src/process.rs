@@ -1,5 +1,28 @@
+use crate::limits::LimitEvents;
+use crate::output::OutputDigest;
+use crate::scheduling::SchedulingState;
+use crate::threads::WaitState;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::time::{Duration, Instant};
 
+/// How a run ended.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    /// The command exited on its own, successfully or not.
+    #[default]
+    Exited,
+    /// The command was killed after exceeding its timeout.
+    TimedOut,
+    /// lens was interrupted and passed the signal on to the command.
+    Interrupted,
+    /// A process was killed for exceeding the memory limit.
+    OomKilled,
+    /// The command ran, but was held back by the CPU quota.
+    Throttled,
+}
+
 #[derive(Debug, Clone)]
 pub struct ProcessInfo {
     pub pid: u32,
@@ -9,22 +32,65 @@ pub struct ProcessInfo {
     pub start_time: Option<Instant>,
     pub end_time: Option<Instant>,
     pub duration: Option<Duration>,
+    pub outcome: Outcome,
+    /// Set when stdout was piped back to lens.
+    pub stdout: Option<OutputDigest>,
+    /// CPU affinity and priorities read back once the command started.
+    pub scheduling: Option<SchedulingState>,
+    /// Set when the command ran under cgroup limits.
+    pub limit_events: Option<LimitEvents>,
     pub(crate) stat: Stat,
 }
 impl ProcessInfo {
     pub(crate) fn new(command: String, args: Vec<String>) -> Self {
         ProcessInfo {
             pid: 0,
-            command: command,
-            args: args,
+            command,
+            args,
             status: Some(0),
             start_time: None,
             end_time: None,
             duration: None, //end_time.duration_since(start_time),
+            outcome: Outcome::Exited,
+            stdout: None,
+            scheduling: None,
+            limit_events: None,
             stat: Stat::new(),
         }
     }
+
+    /// Time elapsed since the process was started, used to stamp samples.
+    pub(crate) fn elapsed(&self) -> Duration {
+        self.start_time
+            .map(|start| start.elapsed())
+            .unwrap_or_default()
+    }
+}
+
+/// A single reading taken while the process was running, stamped with the
+/// time elapsed since it was started.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Sample {
+    pub(crate) elapsed: Duration,
+    pub(crate) value: u64,
+}
+
+impl Sample {
+    pub(crate) fn new(elapsed: Duration, value: u64) -> Self {
+        Sample { elapsed, value }
+    }
+}
+
+/// CPU time of one thread as last read, which is close to its total once it
+/// has exited.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ThreadTicks {
+    /// The thread's comm, which runtimes set to e.g. `GC Thread#0`.
+    pub(crate) name: String,
+    pub(crate) utime: u64,
+    pub(crate) stime: u64,
 }
+
 #[derive(Debug, Clone)]
 pub(crate) struct Stat {
     pub(crate) read_bytes: Vec<u64>,
@@ -33,8 +99,19 @@ pub(crate) struct Stat {
     pub(crate) transmitted: Vec<u64>,
     pub(crate) utime: u64,
     pub(crate) stime: u64,
-    pub(crate) memory_kb: Vec<u64>,
+    pub(crate) memory_kb: Vec<Sample>,
+    /// Largest RSS of the command or of any descendant it waited for, as the
+    /// kernel kept it (`ru_maxrss`), which catches peaks between samples. Set
+    /// once the command has been reaped.
+    pub(crate) max_rss_kb: Option<u64>,
+    /// Cumulative user + system clock ticks, for CPU utilisation over time.
+    pub(crate) cpu_ticks: Vec<Sample>,
     pub(crate) total_time: u64,
+    /// Threads of the command's own process, by thread id.
+    pub(crate) threads: BTreeMap<u32, ThreadTicks>,
+    /// Samples of those threads by thread name, state and wait channel, the
+    /// channel empty when running.
+    pub(crate) wait_samples: BTreeMap<(String, WaitState, String), u64>,
 }
 
 impl Stat {
@@ -47,7 +124,18 @@ impl Stat {
             utime: 0,
             stime: 0,
             memory_kb: vec![],
+            max_rss_kb: None,
+            cpu_ticks: vec![],
             total_time: 0,
+            threads: BTreeMap::new(),
+            wait_samples: BTreeMap::new(),
         }
     }
+
+    /// The highest RSS seen, sampled or recorded by the kernel. This may be
+    /// that of a child the command waited for rather than its own.
+    pub(crate) fn peak_memory_kb(&self) -> u64 {
+        let sampled = self.memory_kb.iter().map(|s| s.value).max().unwrap_or(0);
+        sampled.max(self.max_rss_kb.unwrap_or(0))
+    }
 }
@@ -0,0 +1,712 @@
+use crate::linux;
+use crate::symbols::{self, Mapping, Symbolizer};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Samples per second when none is given. Odd, so as not to run in lockstep
+/// with timers of the command.
+pub const DEFAULT_FREQUENCY: u32 = 999;
+
+/// Reading /proc takes a file per thread for each sample, too slow for perf's rates.
+const PROC_MAX_FREQUENCY: u32 = 100;
+/// Most data pages of each per-CPU perf ring buffer, a power of two: 256 KiB
+/// at 4 KiB pages. Fewer are used where they would not all fit the limit.
+const RING_PAGES: usize = 64;
+/// What perf_event_mlock_kb is unless it says otherwise.
+const DEFAULT_MLOCK_KB: usize = 516;
+/// How often the ring buffer is emptied.
+const DRAIN_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Where the samples of a profile came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProfileSource {
+    /// perf_event sampling of the whole command tree, with stacks walked by
+    /// frame pointers. Code built without them shows up with short stacks.
+    Perf,
+    /// The kernel stacks or wait channels of the command's own threads, read
+    /// from /proc when perf_event is not permitted. Shows where threads wait
+    /// rather than what they compute.
+    Proc,
+}
+
+impl std::fmt::Display for ProfileSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ProfileSource::Perf => write!(f, "perf_event"),
+            ProfileSource::Proc => write!(f, "/proc"),
+        }
+    }
+}
+
+/// Where a command spent its time, from a separate sampled run.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Profile {
+    pub source: ProfileSource,
+    /// Samples per second taken of each running thread.
+    pub frequency: u32,
+    /// Samples the kernel dropped because lens fell behind reading them.
+    pub lost: u64,
+    /// Frames from the thread name down to the sampled function, joined by
+    /// `;`, with the number of samples of each such stack.
+    pub stacks: BTreeMap<String, u64>,
+}
+
+impl Profile {
+    pub fn samples(&self) -> u64 {
+        self.stacks.values().sum()
+    }
+
+    /// The folded format flamegraph tools read: one `stack count` per line.
+    pub fn folded(&self) -> String {
+        self.stacks
+            .iter()
+            .map(|(stack, count)| format!("{} {}\n", stack, count))
+            .collect()
+    }
+}
+
+/// Runs the command and samples its stacks `frequency` times a second until
+/// it exits. `started` gets the pid once the command runs.
+///
+/// The command opens perf events on itself just before it execs, to be
+/// enabled by the exec and inherited by everything it starts, and hands them
+/// to lens over a socket. Inherited events can only be mapped per CPU, so
+/// there is one on each. Where perf_event is not permitted or the buffers
+/// can't be mapped, the command's threads are sampled through /proc instead.
+pub(crate) fn record(
+    mut command: std::process::Command,
+    frequency: u32,
+    started: impl FnOnce(u32),
+) -> io::Result<Profile> {
+    let (receiver, sender) = socket_pair()?;
+    let attr = PerfEventAttr::sampling(frequency);
+    let sender_fd = sender.as_raw_fd();
+    // SAFETY: sysconf has no preconditions
+    let cpus = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_CONF) }.max(1) as i32;
+    // SAFETY: the closure only makes syscalls on memory it owns
+    unsafe {
+        command.pre_exec(move || {
+            send_events(sender_fd, attr, cpus);
+            Ok(())
+        });
+    }
+    let mut child = command.spawn()?;
+    drop(sender);
+    let pid = child.id();
+    started(pid);
+    // Read before anything else, while the program has just started
+    let maps = symbols::read_maps(pid);
+    let rings = receive_events(&receiver).and_then(|events| {
+        let pages = ring_pages(events.len());
+        events
+            .into_iter()
+            .map(|event| RingBuffer::map(event, pages))
+            .collect::<io::Result<Vec<_>>>()
+    });
+    match rings {
+        Ok(rings) => record_perf(rings, &mut child, pid, maps, frequency),
+        // Any events mapped so far are closed again, so they cost nothing
+        Err(_) => record_proc(&mut child, pid, frequency),
+    }
+}
+
+/// Data pages per ring buffer such that `buffers` of them fit within
+/// perf_event_mlock_kb, which caps what an unprivileged user may map across
+/// all of their buffers. Root is not held to it.
+fn ring_pages(buffers: usize) -> usize {
+    // SAFETY: neither call has preconditions
+    let (euid, page) = unsafe { (libc::geteuid(), libc::sysconf(libc::_SC_PAGESIZE)) };
+    if euid == 0 {
+        return RING_PAGES;
+    }
+    let limit_kb = std::fs::read_to_string("/proc/sys/kernel/perf_event_mlock_kb")
+        .ok()
+        .and_then(|text| text.trim().parse().ok())
+        .unwrap_or(DEFAULT_MLOCK_KB);
+    fit_ring_pages(limit_kb * 1024, buffers, page as usize)
+}
+
+fn fit_ring_pages(limit: usize, buffers: usize, page: usize) -> usize {
+    let per_buffer = limit / buffers.max(1) / page;
+    let mut pages = RING_PAGES;
+    // Each buffer maps a metadata page besides its data
+    while pages > 1 && pages + 1 > per_buffer {
+        pages /= 2;
+    }
+    pages
+}
+
+fn record_perf(
+    mut rings: Vec<RingBuffer>,
+    child: &mut std::process::Child,
+    pid: u32,
+    maps: Vec<Mapping>,
+    frequency: u32,
+) -> io::Result<Profile> {
+    let mut recorder = PerfRecorder::new(pid, maps);
+    let mut running = true;
+    while running {
+        running = child.try_wait()?.is_none();
+        if running {
+            std::thread::sleep(DRAIN_INTERVAL);
+        }
+        // Records of one CPU are in order, but not across CPUs
+        for ring in &mut rings {
+            ring.drain(|kind, misc, body| recorder.record(kind, misc, body));
+        }
+    }
+    Ok(recorder.finish(frequency))
+}
+
+fn record_proc(child: &mut std::process::Child, pid: u32, frequency: u32) -> io::Result<Profile> {
+    let frequency = frequency.clamp(1, PROC_MAX_FREQUENCY);
+    let interval = Duration::from_secs(1) / frequency;
+    let mut stacks = BTreeMap::new();
+    while child.try_wait()?.is_none() {
+        for tid in linux::task_ids(pid) {
+            if let Some(stack) = proc_stack(pid, tid) {
+                *stacks.entry(stack).or_default() += 1;
+            }
+        }
+        std::thread::sleep(interval);
+    }
+    Ok(Profile {
+        source: ProfileSource::Proc,
+        frequency,
+        lost: 0,
+        stacks,
+    })
+}
+
+/// The folded stack of a thread from /proc: `[running]` when on a CPU,
+/// otherwise its kernel stack, which takes root, or else its wait channel.
+fn proc_stack(pid: u32, tid: u32) -> Option<String> {
+    let task = linux::read_task_stat(pid, tid)?;
+    let stack = std::fs::read_to_string(format!("/proc/{}/task/{}/stack", pid, tid));
+    let mut frames: Vec<String> = match task.state {
+        'R' => vec![String::from("[running]")],
+        _ => stack
+            .unwrap_or_default()
+            .lines()
+            .rev()
+            .filter_map(kernel_stack_frame)
+            .collect(),
+    };
+    if frames.is_empty() {
+        frames.push(match linux::read_wchan(pid, tid).as_str() {
+            "" => format!("[{}]", task_state_name(task.state)),
+            wchan => format!("{}_[k]", wchan),
+        });
+    }
+    Some(folded_stack(&task.name, frames))
+}
+
+/// The function of a /proc stack line such as `[<0>] do_wait+0x5c/0x70`.
+fn kernel_stack_frame(line: &str) -> Option<String> {
+    let (_, function) = line.split_once("] ")?;
+    let name = function.split('+').next()?;
+    Some(format!("{}_[k]", name))
+}
+
+fn task_state_name(state: char) -> &'static str {
+    match state {
+        'S' => "sleeping",
+        'D' => "disk sleep",
+        'T' | 't' => "stopped",
+        'Z' => "zombie",
+        _ => "waiting",
+    }
+}
+
+/// The thread name, then frames from the outermost in.
+fn folded_stack(name: &str, frames: impl IntoIterator<Item = String>) -> String {
+    // `;` separates frames, yet Rust names like `[u8; 4]` hold it
+    std::iter::once(name.to_string())
+        .chain(frames)
+        .map(|frame| frame.replace(';', ","))
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+/// Symbolises the records of a perf ring buffer once the run is over. Sampled
+/// addresses are kept until then, along with the mappings of each process.
+struct PerfRecorder {
+    maps: HashMap<u32, Vec<Mapping>>,
+    /// Thread names by thread id.
+    names: HashMap<u32, String>,
+    /// Samples by process, thread name and callchain.
+    samples: HashMap<(u32, String, Vec<u64>), u64>,
+    lost: u64,
+}
+
+const PERF_RECORD_MMAP: u32 = 1;
+const PERF_RECORD_LOST: u32 = 2;
+const PERF_RECORD_COMM: u32 = 3;
+const PERF_RECORD_FORK: u32 = 7;
+const PERF_RECORD_SAMPLE: u32 = 9;
+const PERF_RECORD_MISC_COMM_EXEC: u16 = 1 << 13;
+/// Callchain entries at or above this mark where kernel or user frames start.
+const PERF_CONTEXT_MAX: u64 = -4095i64 as u64;
+const PERF_CONTEXT_KERNEL: u64 = -128i64 as u64;
+
+impl PerfRecorder {
+    fn new(pid: u32, maps: Vec<Mapping>) -> Self {
+        let name = std::fs::read_to_string(format!("/proc/{}/comm", pid)).unwrap_or_default();
+        Self {
+            maps: HashMap::from([(pid, maps)]),
+            names: HashMap::from([(pid, name.trim().to_string())]),
+            samples: HashMap::new(),
+            lost: 0,
+        }
+    }
+
+    fn record(&mut self, kind: u32, misc: u16, body: &[u8]) {
+        let u32_at = |at: usize| read_u32(body, at).unwrap_or_default();
+        let u64_at = |at: usize| read_u64(body, at).unwrap_or_default();
+        match kind {
+            PERF_RECORD_MMAP if u32_at(0) != u32::MAX => {
+                let mapping = Mapping {
+                    start: u64_at(8),
+                    end: u64_at(8) + u64_at(16),
+                    offset: u64_at(24),
+                    path: read_cstr(body, 32),
+                };
+                self.maps.entry(u32_at(0)).or_default().push(mapping);
+            }
+            PERF_RECORD_COMM => {
+                if misc & PERF_RECORD_MISC_COMM_EXEC != 0 {
+                    // The new program's own mappings follow
+                    self.maps.entry(u32_at(0)).or_default().clear();
+                }
+                self.names.insert(u32_at(4), read_cstr(body, 8));
+            }
+            PERF_RECORD_FORK => {
+                let (pid, ppid, tid, ptid) = (u32_at(0), u32_at(4), u32_at(8), u32_at(12));
+                let name = self.names.get(&ptid).cloned().unwrap_or_default();
+                self.names.insert(tid, name);
+                if pid != ppid {
+                    let maps = self.maps.get(&ppid).cloned().unwrap_or_default();
+                    self.maps.insert(pid, maps);
+                }
+            }
+            PERF_RECORD_SAMPLE => {
+                let (pid, tid) = (u32_at(8), u32_at(12));
+                let depth = u64_at(16) as usize;
+                let mut chain: Vec<u64> = (0..depth).map(|i| u64_at(24 + i * 8)).collect();
+                if chain.is_empty() {
+                    chain.push(u64_at(0));
+                }
+                let name = self.names.get(&tid).cloned().unwrap_or_default();
+                *self.samples.entry((pid, name, chain)).or_default() += 1;
+            }
+            PERF_RECORD_LOST => self.lost += u64_at(8),
+            _ => {}
+        }
+    }
+
+    fn finish(self, frequency: u32) -> Profile {
+        let mut symbolizer = Symbolizer::default();
+        let mut stacks = BTreeMap::new();
+        for ((pid, name, chain), count) in self.samples {
+            let maps = self.maps.get(&pid).map(Vec::as_slice).unwrap_or_default();
+            let mut frames = Vec::new();
+            let mut kernel = false;
+            // Return addresses point past the call; only the sampled one is exact
+            let mut exact = true;
+            for address in chain {
+                if address >= PERF_CONTEXT_MAX {
+                    kernel = address == PERF_CONTEXT_KERNEL;
+                    exact = true;
+                    continue;
+                }
+                if address == 0 {
+                    // Where an unwind gave up
+                    continue;
+                }
+                let lookup = if exact { address } else { address - 1 };
+                frames.push(if kernel {
+                    symbolizer.kernel(lookup)
+                } else {
+                    symbolizer.user(pid, maps, lookup)
+                });
+                exact = false;
+            }
+            frames.reverse();
+            *stacks.entry(folded_stack(&name, frames)).or_default() += count;
+        }
+        Profile {
+            source: ProfileSource::Perf,
+            frequency,
+            lost: self.lost,
+            stacks,
+        }
+    }
+}
+
+fn read_u32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_ne_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn read_u64(data: &[u8], at: usize) -> Option<u64> {
+    Some(u64::from_ne_bytes(data.get(at..at + 8)?.try_into().ok()?))
+}
+
+fn read_cstr(data: &[u8], at: usize) -> String {
+    let bytes = data.get(at..).unwrap_or_default();
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+/// `struct perf_event_attr` as of Linux 4.1, which libc does not define.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct PerfEventAttr {
+    kind: u32,
+    size: u32,
+    config: u64,
+    sample_freq: u64,
+    sample_type: u64,
+    read_format: u64,
+    flags: u64,
+    wakeup_events: u32,
+    bp_type: u32,
+    config1: u64,
+    config2: u64,
+    branch_sample_type: u64,
+    sample_regs_user: u64,
+    sample_stack_user: u32,
+    clockid: i32,
+    sample_regs_intr: u64,
+    aux_watermark: u32,
+    sample_max_stack: u16,
+    reserved: u16,
+}
+
+const PERF_TYPE_SOFTWARE: u32 = 1;
+const PERF_COUNT_SW_CPU_CLOCK: u64 = 0;
+const PERF_SAMPLE_IP: u64 = 1 << 0;
+const PERF_SAMPLE_TID: u64 = 1 << 1;
+const PERF_SAMPLE_CALLCHAIN: u64 = 1 << 5;
+const FLAG_DISABLED: u64 = 1 << 0;
+const FLAG_INHERIT: u64 = 1 << 1;
+const FLAG_EXCLUDE_KERNEL: u64 = 1 << 5;
+const FLAG_EXCLUDE_HV: u64 = 1 << 6;
+const FLAG_MMAP: u64 = 1 << 8;
+const FLAG_COMM: u64 = 1 << 9;
+const FLAG_FREQ: u64 = 1 << 10;
+const FLAG_ENABLE_ON_EXEC: u64 = 1 << 12;
+const FLAG_TASK: u64 = 1 << 13;
+const FLAG_COMM_EXEC: u64 = 1 << 24;
+const PERF_FLAG_FD_CLOEXEC: libc::c_ulong = 1 << 3;
+
+impl PerfEventAttr {
+    /// CPU clock sampling with callchains, off until the next exec, along
+    /// with the records needed to symbolise the samples.
+    fn sampling(frequency: u32) -> Self {
+        PerfEventAttr {
+            kind: PERF_TYPE_SOFTWARE,
+            size: std::mem::size_of::<PerfEventAttr>() as u32,
+            config: PERF_COUNT_SW_CPU_CLOCK,
+            sample_freq: frequency.max(1) as u64,
+            sample_type: PERF_SAMPLE_IP | PERF_SAMPLE_TID | PERF_SAMPLE_CALLCHAIN,
+            flags: FLAG_DISABLED
+                | FLAG_INHERIT
+                | FLAG_MMAP
+                | FLAG_COMM
+                | FLAG_FREQ
+                | FLAG_ENABLE_ON_EXEC
+                | FLAG_TASK
+                | FLAG_COMM_EXEC,
+            ..PerfEventAttr::default()
+        }
+    }
+}
+
+/// Opens an event on the calling process for each CPU, without kernel frames
+/// if those are not permitted, and sends each over the socket in a message
+/// of its own. A last message without one carries the errno if none could be
+/// opened. Runs between fork and exec, so it must not allocate.
+fn send_events(socket: RawFd, mut attr: PerfEventAttr, cpus: i32) {
+    let open = |attr: &PerfEventAttr, cpu: i32| {
+        // SAFETY: attr is a valid perf_event_attr of the size it states
+        unsafe {
+            libc::syscall(
+                libc::SYS_perf_event_open,
+                attr as *const PerfEventAttr,
+                0,
+                cpu,
+                -1,
+                PERF_FLAG_FD_CLOEXEC,
+            ) as RawFd
+        }
+    };
+    let mut errno = 0;
+    let mut sent = false;
+    for cpu in 0..cpus {
+        let mut fd = open(&attr, cpu);
+        if fd == -1 && !sent && attr.flags & FLAG_EXCLUDE_KERNEL == 0 {
+            attr.flags |= FLAG_EXCLUDE_KERNEL | FLAG_EXCLUDE_HV;
+            fd = open(&attr, cpu);
+        }
+        if fd == -1 {
+            // An offline CPU has no events, so carry on with the next
+            errno = io::Error::last_os_error()
+                .raw_os_error()
+                .unwrap_or(libc::EIO);
+            continue;
+        }
+        send_message(socket, 0, Some(fd));
+        // SAFETY: the descriptor was opened above and has been sent
+        unsafe { libc::close(fd) };
+        sent = true;
+    }
+    send_message(socket, if sent { 0 } else { errno }, None);
+}
+
+fn send_message(socket: RawFd, mut errno: i32, fd: Option<RawFd>) {
+    let mut iov = libc::iovec {
+        iov_base: &mut errno as *mut i32 as *mut libc::c_void,
+        iov_len: std::mem::size_of::<i32>(),
+    };
+    let mut control = [0u64; 4];
+    // SAFETY: the message and its one control header point into the buffers above
+    unsafe {
+        let mut message: libc::msghdr = std::mem::zeroed();
+        message.msg_iov = &mut iov;
+        message.msg_iovlen = 1;
+        if let Some(fd) = fd {
+            message.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+            message.msg_controllen = libc::CMSG_SPACE(std::mem::size_of::<RawFd>() as u32) as _;
+            let header = libc::CMSG_FIRSTHDR(&message);
+            (*header).cmsg_level = libc::SOL_SOCKET;
+            (*header).cmsg_type = libc::SCM_RIGHTS;
+            (*header).cmsg_len = libc::CMSG_LEN(std::mem::size_of::<RawFd>() as u32) as _;
+            std::ptr::write_unaligned(libc::CMSG_DATA(header) as *mut RawFd, fd);
+        }
+        libc::sendmsg(socket, &message, 0);
+    }
+}
+
+/// The events sent by [`send_events`], or why none could be opened.
+fn receive_events(socket: &OwnedFd) -> io::Result<Vec<OwnedFd>> {
+    let mut events = Vec::new();
+    loop {
+        let mut errno: i32 = 0;
+        let mut iov = libc::iovec {
+            iov_base: &mut errno as *mut i32 as *mut libc::c_void,
+            iov_len: std::mem::size_of::<i32>(),
+        };
+        let mut control = [0u64; 4];
+        // SAFETY: the message points into the buffers above, which outlive the call
+        unsafe {
+            let mut message: libc::msghdr = std::mem::zeroed();
+            message.msg_iov = &mut iov;
+            message.msg_iovlen = 1;
+            message.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+            message.msg_controllen = std::mem::size_of_val(&control) as _;
+            // Sent before the exec, so nothing is left to wait for
+            match libc::recvmsg(
+                socket.as_raw_fd(),
+                &mut message,
+                libc::MSG_CMSG_CLOEXEC | libc::MSG_DONTWAIT,
+            ) {
+                -1 => return Err(io::Error::last_os_error()),
+                0 => return Err(io::Error::other("the command sent no perf events")),
+                _ => {}
+            }
+            let header = libc::CMSG_FIRSTHDR(&message);
+            if header.is_null() || (*header).cmsg_type != libc::SCM_RIGHTS {
+                return match errno {
+                    0 => Ok(events),
+                    errno => Err(io::Error::from_raw_os_error(errno)),
+                };
+            }
+            let fd = std::ptr::read_unaligned(libc::CMSG_DATA(header) as *const RawFd);
+            events.push(OwnedFd::from_raw_fd(fd));
+        }
+    }
+}
+
+fn socket_pair() -> io::Result<(OwnedFd, OwnedFd)> {
+    let mut fds = [0; 2];
+    // SAFETY: socketpair writes two descriptors into the array
+    let result = unsafe {
+        libc::socketpair(
+            libc::AF_UNIX,
+            libc::SOCK_DGRAM | libc::SOCK_CLOEXEC,
+            0,
+            fds.as_mut_ptr(),
+        )
+    };
+    if result == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: both descriptors were just opened and are owned by nobody else
+    Ok(unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) })
+}
+
+/// The mapped ring buffer of a perf event: a metadata page, then `pages` of data.
+struct RingBuffer {
+    base: *mut u8,
+    len: usize,
+    page: usize,
+    pages: usize,
+    /// Kept open for as long as the buffer is mapped.
+    _event: OwnedFd,
+}
+
+impl RingBuffer {
+    fn map(event: OwnedFd, pages: usize) -> io::Result<Self> {
+        // SAFETY: sysconf has no preconditions
+        let page = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let len = page * (pages + 1);
+        // SAFETY: a shared mapping of the event, unmapped on drop
+        let base = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                event.as_raw_fd(),
+                0,
+            )
+        };
+        if base == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(RingBuffer {
+            base: base as *mut u8,
+            len,
+            page,
+            pages,
+            _event: event,
+        })
+    }
+
+    /// Hands each record written since the last call to `each`, with its
+    /// type, misc flags and body, then frees the space they took.
+    fn drain(&mut self, mut each: impl FnMut(u32, u16, &[u8])) {
+        // data_head and data_tail of struct perf_event_mmap_page
+        // SAFETY: both lie within the metadata page and are 8-byte aligned
+        let (head, tail) = unsafe {
+            (
+                &*(self.base.add(1024) as *const AtomicU64),
+                &*(self.base.add(1032) as *const AtomicU64),
+            )
+        };
+        let size = (self.pages * self.page) as u64;
+        // SAFETY: the data area follows the metadata page
+        let data = unsafe { std::slice::from_raw_parts(self.base.add(self.page), size as usize) };
+        let end = head.load(Ordering::Acquire);
+        let mut position = tail.load(Ordering::Relaxed);
+        let mut record = Vec::new();
+        while position + 8 <= end {
+            // Records may wrap around the end of the buffer
+            record.clear();
+            let header: Vec<u8> = (0..8)
+                .map(|i| data[((position + i) % size) as usize])
+                .collect();
+            let kind = read_u32(&header, 0).unwrap_or_default();
+            let misc = u16::from_ne_bytes([header[4], header[5]]);
+            let len = u16::from_ne_bytes([header[6], header[7]]) as u64;
+            if len < 8 {
+                break;
+            }
+            record.extend((8..len).map(|i| data[((position + i) % size) as usize]));
+            each(kind, misc, &record);
+            position += len;
+        }
+        tail.store(position, Ordering::Release);
+    }
+}
+
+impl Drop for RingBuffer {
+    fn drop(&mut self) {
+        // SAFETY: base and len are the mapping made in map
+        unsafe { libc::munmap(self.base as *mut libc::c_void, self.len) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_attr_size() {
+        // PERF_ATTR_SIZE_VER5
+        assert_eq!(std::mem::size_of::<PerfEventAttr>(), 112);
+    }
+
+    #[test]
+    fn test_fit_ring_pages() {
+        let limit = DEFAULT_MLOCK_KB * 1024;
+        assert_eq!(fit_ring_pages(limit, 1, 4096), RING_PAGES);
+        // 32 pages each, one of which is metadata
+        assert_eq!(fit_ring_pages(limit, 4, 4096), 16);
+        assert_eq!(fit_ring_pages(limit, 64, 4096), 1);
+        let pages = fit_ring_pages(limit, 8, 4096);
+        assert!(8 * (pages + 1) * 4096 <= limit);
+    }
+
+    #[test]
+    fn test_kernel_stack_frame() {
+        assert_eq!(
+            kernel_stack_frame("[<0>] do_wait+0x5c/0x70"),
+            Some(String::from("do_wait_[k]"))
+        );
+        assert_eq!(kernel_stack_frame("garbage"), None);
+    }
+
+    #[test]
+    fn test_folded() {
+        let profile = Profile {
+            source: ProfileSource::Perf,
+            frequency: 99,
+            lost: 0,
+            stacks: BTreeMap::from([
+                (folded_stack("sh", [String::from("main")]), 3),
+                (
+                    folded_stack("sh", [String::from("main"), String::from("[u8; 4]")]),
+                    2,
+                ),
+            ]),
+        };
+        assert_eq!(profile.samples(), 5);
+        assert_eq!(profile.folded(), "sh;main 3\nsh;main;[u8, 4] 2\n");
+    }
+
+    #[test]
+    fn test_record_proc_samples_waits() {
+        let mut child = std::process::Command::new("sleep")
+            .arg("0.2")
+            .spawn()
+            .unwrap();
+        let pid = child.id();
+        let profile = record_proc(&mut child, pid, 1000).unwrap();
+        assert_eq!(profile.source, ProfileSource::Proc);
+        assert_eq!(profile.frequency, PROC_MAX_FREQUENCY);
+        assert!(profile.samples() > 0);
+        assert!(
+            profile
+                .stacks
+                .keys()
+                .all(|stack| stack.starts_with("sleep;"))
+        );
+    }
+
+    #[test]
+    fn test_record_samples_busy_command() {
+        let mut command = std::process::Command::new("sh");
+        command.args(["-c", "i=0; while [ $i -lt 200000 ]; do i=$((i+1)); done"]);
+        let profile = record(command, 999, |_| {}).unwrap();
+        assert!(profile.samples() > 0, "{:?}", profile);
+        // Every stack starts with a thread name
+        assert!(profile.stacks.keys().all(|stack| !stack.starts_with(';')));
+    }
+}
@@ -0,0 +1,280 @@
+use crate::benchmark::{BenchmarkStat, ConcurrencyStat};
+use crate::cache::CacheMode;
+use crate::files::FileReport;
+use crate::limits::LimitEvents;
+use crate::plot;
+use crate::process::{Outcome, ProcessInfo};
+use crate::profile::Profile;
+use crate::scheduling::SchedulingState;
+use crate::stats::{self, Outlier};
+use crate::syscalls::SyscallProfile;
+use crate::system::SystemInfo;
+use crate::threads::{ThreadStat, WaitStat};
+use serde::{Deserialize, Serialize};
+
+/// Points kept per iteration timeline, enough for a chart without bloating exports.
+const TIMELINE_POINTS: usize = 200;
+
+/// Everything measured in one lens invocation. This is the model behind every
+/// export format.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Report {
+    pub lens_version: String,
+    pub system: SystemInfo,
+    pub commands: Vec<CommandReport>,
+    /// Set when lens was interrupted, so some iterations or commands are missing.
+    #[serde(default)]
+    pub partial: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CommandReport {
+    /// The benchmark's name, which is the command itself unless a suite file named it.
+    #[serde(default)]
+    pub name: String,
+    pub command: String,
+    pub summary: BenchmarkStat,
+    /// Only present when instances were run concurrently.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub concurrency: Option<ConcurrencyStat>,
+    /// Page cache state each iteration started from, when --cache set one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache: Option<CacheMode>,
+    /// From a separate traced run, so it does not affect the summary.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub syscalls: Option<SyscallProfile>,
+    /// Files used during the traced run, when --files asked for them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub files: Option<FileReport>,
+    /// Sampled stacks from a separate run, when --profile asked for them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<Profile>,
+    /// CPU time by thread name, for commands running more than one thread.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub threads: Vec<ThreadStat>,
+    /// Where the command's threads spent the wall time, from sampling their state.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub waits: Option<WaitStat>,
+    pub iterations: Vec<IterationReport>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IterationReport {
+    pub wall_time: f64,
+    pub user_ticks: u64,
+    pub system_ticks: u64,
+    pub exit_status: Option<i32>,
+    #[serde(default)]
+    pub outcome: Outcome,
+    /// Largest RSS of the command or of a child it waited for.
+    pub peak_rss_kb: u64,
+    /// Set when the wall time falls outside Tukey's fences.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub outlier: Option<Outlier>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scheduling: Option<SchedulingState>,
+    /// Present when the command ran under cgroup limits.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit_events: Option<LimitEvents>,
+    pub timeline: Timeline,
+}
+
+/// CPU and RSS resampled onto evenly spaced points in time.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Timeline {
+    pub time: Vec<f64>,
+    pub cpu_percent: Vec<Option<f64>>,
+    pub rss_kb: Vec<Option<f64>>,
+}
+
+impl Report {
+    pub fn new(system: SystemInfo) -> Self {
+        Report {
+            lens_version: env!("CARGO_PKG_VERSION").to_string(),
+            system,
+            commands: Vec::new(),
+            partial: false,
+        }
+    }
+
+    pub fn push(
+        &mut self,
+        name: String,
+        command: String,
+        processes: &[ProcessInfo],
+        summary: BenchmarkStat,
+    ) {
+        self.commands
+            .push(CommandReport::new(name, command, processes, summary));
+    }
+
+    /// Results of the benchmark with this name, if it was part of the run.
+    pub fn command(&self, name: &str) -> Option<&CommandReport> {
+        // Reports saved before benchmarks had names only carry the command
+        self.commands
+            .iter()
+            .find(|c| c.name == name || (c.name.is_empty() && c.command == name))
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+}
+
+impl CommandReport {
+    /// A command's summary and iterations, with nothing else filled in yet.
+    pub fn new(
+        name: String,
+        command: String,
+        processes: &[ProcessInfo],
+        summary: BenchmarkStat,
+    ) -> Self {
+        let iterations = processes
+            .iter()
+            .zip(wall_time_outliers(processes))
+            .map(|(process, outlier)| IterationReport {
+                outlier,
+                ..IterationReport::new(process)
+            })
+            .collect();
+        CommandReport {
+            name,
+            command,
+            summary,
+            concurrency: None,
+            cache: None,
+            syscalls: None,
+            files: None,
+            profile: None,
+            threads: Vec::new(),
+            waits: None,
+            iterations,
+        }
+    }
+}
+
+impl IterationReport {
+    fn new(process: &ProcessInfo) -> Self {
+        IterationReport {
+            wall_time: process.duration.unwrap_or_default().as_secs_f64(),
+            user_ticks: process.stat.utime,
+            system_ticks: process.stat.stime,
+            exit_status: process.status,
+            outcome: process.outcome,
+            outlier: None,
+            scheduling: process.scheduling.clone(),
+            limit_events: process.limit_events,
+            peak_rss_kb: process.stat.peak_memory_kb(),
+            timeline: Timeline::new(process),
+        }
+    }
+}
+
+impl Timeline {
+    fn new(process: &ProcessInfo) -> Self {
+        let span = process.duration.unwrap_or_default().as_secs_f64();
+        let step = span / TIMELINE_POINTS as f64;
+        Timeline {
+            time: (0..TIMELINE_POINTS).map(|i| i as f64 * step).collect(),
+            cpu_percent: plot::cpu_columns(&process.stat.cpu_ticks, span, TIMELINE_POINTS),
+            rss_kb: plot::memory_columns(&process.stat.memory_kb, span, TIMELINE_POINTS),
+        }
+    }
+}
+
+/// Where each run's wall time lies relative to the others. Runs without a wall
+/// time, such as those that failed to start, are left out of the fences.
+pub fn wall_time_outliers(processes: &[ProcessInfo]) -> Vec<Option<Outlier>> {
+    let wall_times: Vec<f64> = processes
+        .iter()
+        .filter_map(|p| p.duration)
+        .map(|d| d.as_secs_f64())
+        .collect();
+    let mut outliers = stats::outliers(&wall_times).into_iter();
+    processes
+        .iter()
+        .map(|p| p.duration.and_then(|_| outliers.next().flatten()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::benchmark::Benchmark;
+    use crate::process::Sample;
+    use std::time::Duration;
+
+    fn create_test_process() -> ProcessInfo {
+        let mut process = ProcessInfo::new(String::from("ls"), vec![]);
+        process.duration = Some(Duration::from_secs(2));
+        process.stat.utime = 30;
+        process.stat.stime = 10;
+        process.stat.total_time = 40;
+        process.stat.memory_kb = vec![
+            Sample::new(Duration::from_millis(0), 1024),
+            Sample::new(Duration::from_millis(1000), 4096),
+        ];
+        process.stat.cpu_ticks = vec![
+            Sample::new(Duration::from_millis(0), 0),
+            Sample::new(Duration::from_millis(2000), 40),
+        ];
+        process
+    }
+
+    #[test]
+    fn test_push_keeps_iterations() {
+        let processes = vec![create_test_process(), create_test_process()];
+        let mut report = Report::new(SystemInfo::collect());
+        let stat = Benchmark::calculate(&processes[0]);
+        report.push(String::from("ls"), String::from("ls"), &processes, stat);
+
+        assert_eq!(report.commands.len(), 1);
+        let iterations = &report.commands[0].iterations;
+        assert_eq!(iterations.len(), 2);
+        assert_eq!(iterations[0].wall_time, 2.0);
+        assert_eq!(iterations[0].peak_rss_kb, 4096);
+        assert_eq!(iterations[0].timeline.time.len(), TIMELINE_POINTS);
+    }
+
+    #[test]
+    fn test_outliers_leave_out_runs_without_wall_time() {
+        let mut processes: Vec<ProcessInfo> = [1.0, 1.1, 1.0, 1.2, 1.1, 3.3]
+            .iter()
+            .map(|&seconds| {
+                let mut process = create_test_process();
+                process.duration = Some(Duration::from_secs_f64(seconds));
+                process
+            })
+            .collect();
+        let mut untimed = create_test_process();
+        untimed.duration = None;
+        processes.insert(1, untimed);
+        let outliers = wall_time_outliers(&processes);
+        assert_eq!(outliers.len(), 7);
+        assert_eq!(outliers[1], None);
+        assert_eq!(outliers[6], Some(Outlier::HighSevere));
+        assert_eq!(outliers.iter().flatten().count(), 1);
+    }
+
+    #[test]
+    fn test_to_json() {
+        let processes = vec![create_test_process()];
+        let mut report = Report::new(SystemInfo::collect());
+        report.push(
+            String::from("list"),
+            String::from("ls -l"),
+            &processes,
+            Benchmark::calculate(&processes[0]),
+        );
+
+        let json = report.to_json().unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["lens_version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(value["commands"][0]["name"], "list");
+        assert_eq!(value["commands"][0]["command"], "ls -l");
+        assert!(report.command("list").is_some());
+        assert!(report.command("ls -l").is_none());
+        assert_eq!(value["commands"][0]["summary"]["max_memory"], 4096.0);
+        assert_eq!(value["commands"][0]["iterations"][0]["user_ticks"], 30);
+    }
+}
@@ -0,0 +1,273 @@
+use crate::interrupt::Interrupt;
+use crate::plan::{BenchmarkSpec, Measured, Progress};
+use std::time::Instant;
+
+/// The order measured iterations of several benchmarks are run in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Order {
+    /// Every iteration of one benchmark, then the next.
+    #[default]
+    Sequential,
+    /// One iteration of each benchmark in turn.
+    Interleaved,
+    /// All iterations shuffled together.
+    Random,
+}
+
+/// SplitMix64, plenty for shuffling and reproducible from the seed alone.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in 0..bound.
+    fn below(&mut self, bound: usize) -> usize {
+        (self.next() % bound as u64) as usize
+    }
+
+    /// Fisher-Yates.
+    fn shuffle(&mut self, slots: &mut [usize]) {
+        for i in (1..slots.len()).rev() {
+            slots.swap(i, self.below(i + 1));
+        }
+    }
+}
+
+/// A seed for when none is given, printed so the order can be reproduced.
+pub fn random_seed() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or_default()
+        ^ u64::from(std::process::id())
+}
+
+/// Indexes of the benchmarks in the order their iterations run, given how
+/// many iterations each has.
+pub fn schedule(iterations: &[u32], order: Order, seed: u64) -> Vec<usize> {
+    let mut slots: Vec<usize> = Vec::new();
+    match order {
+        Order::Sequential => {
+            for (index, &count) in iterations.iter().enumerate() {
+                slots.extend(std::iter::repeat_n(index, count as usize));
+            }
+        }
+        Order::Interleaved | Order::Random => {
+            let rounds = iterations.iter().copied().max().unwrap_or(0);
+            for round in 0..rounds {
+                for (index, &count) in iterations.iter().enumerate() {
+                    if round < count {
+                        slots.push(index);
+                    }
+                }
+            }
+        }
+    }
+    if order == Order::Random {
+        Rng(seed).shuffle(&mut slots);
+    }
+    slots
+}
+
+/// Runs every benchmark, iterations scheduled in the given order. Setup and
+/// warmups of all benchmarks come first and cleanups last, except in
+/// sequential order where each benchmark runs start to finish on its own.
+/// Benchmarks sampled adaptively get further rounds, in the same order, once
+/// the minimum iterations of all of them are scheduled.
+///
+/// Results stay grouped per benchmark. Benchmarks never started because of an
+/// interrupt are left out, and one whose hook fails keeps its completed runs
+/// with the error and is not scheduled again. Every benchmark whose setup
+/// hook succeeded gets its cleanup hook, failed or not.
+pub async fn run<'a>(
+    specs: &'a [BenchmarkSpec],
+    order: Order,
+    seed: u64,
+    interrupt: Option<&Interrupt>,
+) -> Vec<(&'a BenchmarkSpec, Measured)> {
+    let interrupted = || interrupt.is_some_and(Interrupt::is_interrupted);
+    let mut results = Vec::new();
+    if order == Order::Sequential {
+        for spec in specs {
+            if interrupted() {
+                break;
+            }
+            results.push((spec, spec.run(interrupt).await));
+        }
+        return results;
+    }
+
+    let mut set_up = Vec::new();
+    for spec in specs {
+        if interrupted() {
+            break;
+        }
+        let mut measured = Measured::default();
+        match spec.setup().await {
+            Ok(()) => {
+                set_up.push(true);
+                if let Err(e) = spec.warmup(interrupt).await {
+                    measured.fail(e);
+                }
+            }
+            Err(e) => {
+                set_up.push(false);
+                measured.fail(e);
+            }
+        }
+        results.push((spec, measured));
+    }
+    let mut progress = vec![Progress::default(); results.len()];
+    let minimums: Vec<u32> = results
+        .iter()
+        .map(|(spec, _)| {
+            let max = spec.sampling.max_iterations.unwrap_or(u32::MAX);
+            spec.iterations.min(max)
+        })
+        .collect();
+    let mut slots = schedule(&minimums, order, seed);
+    let mut rng = Rng(!seed);
+    loop {
+        for index in slots {
+            if interrupted() {
+                break;
+            }
+            let (spec, measured) = &mut results[index];
+            if measured.error.is_some() {
+                continue;
+            }
+            let started = Instant::now();
+            match spec.iteration(interrupt).await {
+                Ok(batch) => measured.runs.extend(batch),
+                Err(e) => measured.fail(e),
+            }
+            progress[index].record(started);
+        }
+        if interrupted() {
+            break;
+        }
+        slots = results
+            .iter()
+            .enumerate()
+            .filter(|(index, (spec, measured))| {
+                measured.error.is_none() && !spec.done(&progress[*index], &measured.runs)
+            })
+            .map(|(index, _)| index)
+            .collect();
+        if slots.is_empty() {
+            break;
+        }
+        if order == Order::Random {
+            rng.shuffle(&mut slots);
+        }
+    }
+    for ((spec, measured), set_up) in results.iter_mut().zip(set_up) {
+        if set_up && let Err(e) = spec.finish().await {
+            measured.fail(e);
+        }
+    }
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_schedule_sequential() {
+        assert_eq!(schedule(&[2, 3], Order::Sequential, 0), [0, 0, 1, 1, 1]);
+    }
+
+    #[test]
+    fn test_schedule_interleaved() {
+        assert_eq!(
+            schedule(&[2, 3, 1], Order::Interleaved, 0),
+            [0, 1, 2, 0, 1, 1]
+        );
+    }
+
+    #[test]
+    fn test_schedule_random() {
+        let order = schedule(&[20, 20], Order::Random, 7);
+        assert_eq!(order, schedule(&[20, 20], Order::Random, 7));
+        assert_ne!(order, schedule(&[20, 20], Order::Random, 8));
+        assert_eq!(order.iter().filter(|&&i| i == 0).count(), 20);
+        assert_ne!(order, schedule(&[20, 20], Order::Interleaved, 7));
+    }
+
+    #[tokio::test]
+    async fn test_run_interleaved_keeps_grouping() {
+        let dir = std::env::temp_dir().join(format!("lens-schedule-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let log = dir.join("log.txt");
+        let specs: Vec<BenchmarkSpec> = ["a", "b"]
+            .iter()
+            .map(|name| {
+                let mut spec =
+                    BenchmarkSpec::new(format!("sh -c 'echo {} >> {}'", name, log.display()));
+                spec.iterations = 2;
+                spec
+            })
+            .collect();
+
+        let results = run(&specs, Order::Interleaved, 0, None).await;
+        assert_eq!(results.len(), 2);
+        for (_, measured) in &results {
+            assert_eq!(measured.error, None);
+            assert_eq!(measured.runs.len(), 2);
+        }
+        assert_eq!(std::fs::read_to_string(&log).unwrap(), "a\nb\na\nb\n");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_run_drops_benchmark_with_failing_hook() {
+        let mut failing = BenchmarkSpec::new(String::from("true"));
+        failing.hooks.prepare = Some(String::from("exit 1"));
+        let specs = [failing, BenchmarkSpec::new(String::from("true"))];
+        let results = run(&specs, Order::Interleaved, 0, None).await;
+        assert!(results[0].1.error.is_some());
+        assert_eq!(results[1].1.error, None);
+        assert_eq!(results[1].1.runs.len(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_run_keeps_runs_and_cleans_up_after_failing_hook() {
+        let dir = std::env::temp_dir().join(format!("lens-schedule-hook-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut failing = BenchmarkSpec::new(String::from("true"));
+        failing.cwd = Some(dir.clone());
+        // Fails on the third iteration
+        failing.hooks.prepare = Some(String::from("echo >> count; [ $(wc -l < count) -lt 3 ]"));
+        failing.hooks.cleanup = Some(String::from("touch cleaned"));
+        let specs = [failing, BenchmarkSpec::new(String::from("true"))];
+        let results = run(&specs, Order::Interleaved, 0, None).await;
+        let cleaned = dir.join("cleaned").exists();
+        let _ = std::fs::remove_dir_all(&dir);
+        assert!(results[0].1.error.is_some());
+        assert_eq!(results[0].1.runs.len(), 2);
+        assert!(cleaned);
+        assert_eq!(results[1].1.runs.len(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_run_adaptive_rounds() {
+        let mut adaptive = BenchmarkSpec::new(String::from("sleep 0.02"));
+        adaptive.iterations = 2;
+        adaptive.sampling.time_budget = Some(std::time::Duration::from_millis(200));
+        adaptive.sampling.max_iterations = Some(6);
+        let mut fixed = BenchmarkSpec::new(String::from("true"));
+        fixed.iterations = 3;
+        let specs = [adaptive, fixed];
+        let results = run(&specs, Order::Random, 1, None).await;
+        assert_eq!(results[0].1.runs.len(), 6);
+        assert_eq!(results[1].1.runs.len(), 3);
+        assert!(results.iter().all(|(_, measured)| measured.error.is_none()));
+    }
+}
@@ -0,0 +1,385 @@
+use serde::{Deserialize, Serialize};
+use std::io;
+
+/// Highest CPU number a `cpu_set_t` can hold.
+const MAX_CPUS: usize = libc::CPU_SETSIZE as usize;
+/// `IOPRIO_WHO_PROCESS` from linux/ioprio.h, which libc does not export.
+const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+const IOPRIO_CLASS_SHIFT: u32 = 13;
+
+/// A kernel scheduling policy, with the static priority the real-time ones take.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchedPolicy {
+    Other,
+    Batch,
+    Idle,
+    Fifo(u8),
+    RoundRobin(u8),
+}
+
+impl std::str::FromStr for SchedPolicy {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let (name, priority) = match text.split_once(':') {
+            Some((name, priority)) => (name, Some(priority)),
+            None => (text, None),
+        };
+        let real_time = |priority: Option<&str>| -> Result<u8, String> {
+            let priority =
+                priority.ok_or_else(|| format!("'{}' needs a priority, e.g. {}:50", name, name))?;
+            match priority.parse() {
+                Ok(priority @ 1..=99) => Ok(priority),
+                _ => Err(format!(
+                    "expected a priority from 1 to 99, got '{}'",
+                    priority
+                )),
+            }
+        };
+        match (name, priority) {
+            ("other", None) => Ok(SchedPolicy::Other),
+            ("batch", None) => Ok(SchedPolicy::Batch),
+            ("idle", None) => Ok(SchedPolicy::Idle),
+            ("fifo", priority) => Ok(SchedPolicy::Fifo(real_time(priority)?)),
+            ("rr", priority) => Ok(SchedPolicy::RoundRobin(real_time(priority)?)),
+            _ => Err(format!(
+                "expected other, batch, idle, fifo:<priority> or rr:<priority>, got '{}'",
+                text
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for SchedPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SchedPolicy::Other => write!(f, "other"),
+            SchedPolicy::Batch => write!(f, "batch"),
+            SchedPolicy::Idle => write!(f, "idle"),
+            SchedPolicy::Fifo(priority) => write!(f, "fifo:{}", priority),
+            SchedPolicy::RoundRobin(priority) => write!(f, "rr:{}", priority),
+        }
+    }
+}
+
+impl SchedPolicy {
+    fn raw(self) -> (libc::c_int, libc::c_int) {
+        match self {
+            SchedPolicy::Other => (libc::SCHED_OTHER, 0),
+            SchedPolicy::Batch => (libc::SCHED_BATCH, 0),
+            SchedPolicy::Idle => (libc::SCHED_IDLE, 0),
+            SchedPolicy::Fifo(priority) => (libc::SCHED_FIFO, priority.into()),
+            SchedPolicy::RoundRobin(priority) => (libc::SCHED_RR, priority.into()),
+        }
+    }
+}
+
+/// An I/O scheduling class and level, as taken by `ionice`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoPriority {
+    RealTime(u8),
+    BestEffort(u8),
+    Idle,
+}
+
+impl std::str::FromStr for IoPriority {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let level = |level: &str| match level.parse() {
+            Ok(level @ 0..=7) => Ok(level),
+            _ => Err(format!("expected a level from 0 to 7, got '{}'", level)),
+        };
+        match text.split_once(':') {
+            None if text == "idle" => Ok(IoPriority::Idle),
+            Some(("realtime", data)) => Ok(IoPriority::RealTime(level(data)?)),
+            Some(("best-effort", data)) => Ok(IoPriority::BestEffort(level(data)?)),
+            _ => Err(format!(
+                "expected realtime:<level>, best-effort:<level> or idle, got '{}'",
+                text
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for IoPriority {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            IoPriority::RealTime(level) => write!(f, "realtime:{}", level),
+            IoPriority::BestEffort(level) => write!(f, "best-effort:{}", level),
+            IoPriority::Idle => write!(f, "idle"),
+        }
+    }
+}
+
+impl IoPriority {
+    fn raw(self) -> libc::c_int {
+        let (class, data) = match self {
+            IoPriority::RealTime(level) => (1, level),
+            IoPriority::BestEffort(level) => (2, level),
+            IoPriority::Idle => (3, 0),
+        };
+        (class << IOPRIO_CLASS_SHIFT) | libc::c_int::from(data)
+    }
+
+    fn from_raw(raw: libc::c_int) -> Option<Self> {
+        let data = (raw & ((1 << IOPRIO_CLASS_SHIFT) - 1)) as u8;
+        match raw >> IOPRIO_CLASS_SHIFT {
+            1 => Some(IoPriority::RealTime(data)),
+            2 => Some(IoPriority::BestEffort(data)),
+            3 => Some(IoPriority::Idle),
+            _ => None,
+        }
+    }
+}
+
+/// Where and how eagerly the command is scheduled. Applied in the child
+/// between fork and exec, so the command never runs with lens's own settings.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Scheduling {
+    /// CPUs the command may run on.
+    pub cpus: Option<Vec<usize>>,
+    pub nice: Option<i32>,
+    pub policy: Option<SchedPolicy>,
+    pub io_priority: Option<IoPriority>,
+}
+
+impl std::fmt::Display for Scheduling {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(cpus) = &self.cpus {
+            parts.push(format!("--cpus {}", format_cpu_list(cpus)));
+        }
+        if let Some(nice) = self.nice {
+            parts.push(format!("--nice {}", nice));
+        }
+        if let Some(policy) = self.policy {
+            parts.push(format!("--sched {}", policy));
+        }
+        if let Some(io_priority) = self.io_priority {
+            parts.push(format!("--ionice {}", io_priority));
+        }
+        f.write_str(&parts.join(" "))
+    }
+}
+
+impl Scheduling {
+    pub fn is_default(&self) -> bool {
+        *self == Scheduling::default()
+    }
+
+    /// Everything the child needs to apply the settings, prepared up front
+    /// since nothing may allocate between fork and exec.
+    pub(crate) fn applier(&self) -> io::Result<impl Fn() -> io::Result<()> + use<>> {
+        let cpus = match &self.cpus {
+            Some(cpus) => Some(cpu_set(cpus)?),
+            None => None,
+        };
+        let nice = self.nice;
+        let policy = self.policy.map(SchedPolicy::raw);
+        let io_priority = self.io_priority.map(IoPriority::raw);
+        Ok(move || {
+            // SAFETY: plain syscalls on the calling process with valid arguments
+            unsafe {
+                if let Some(cpus) = &cpus
+                    && libc::sched_setaffinity(0, size_of::<libc::cpu_set_t>(), cpus) != 0
+                {
+                    return Err(io::Error::last_os_error());
+                }
+                if let Some((policy, priority)) = policy {
+                    let param = libc::sched_param {
+                        sched_priority: priority,
+                    };
+                    if libc::sched_setscheduler(0, policy, &param) != 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+                // After the policy, since SCHED_OTHER and SCHED_BATCH keep the nice value
+                if let Some(nice) = nice
+                    && libc::setpriority(libc::PRIO_PROCESS as _, 0, nice) != 0
+                {
+                    return Err(io::Error::last_os_error());
+                }
+                if let Some(io_priority) = io_priority
+                    && libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, io_priority) != 0
+                {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        })
+    }
+}
+
+/// How a run was actually scheduled, read back from the kernel once it started.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SchedulingState {
+    pub cpus: Vec<usize>,
+    pub policy: String,
+    pub nice: i32,
+    /// `None` when no I/O priority was set, so it follows the nice value.
+    pub io_priority: Option<String>,
+}
+
+impl SchedulingState {
+    pub(crate) fn of(pid: u32) -> Option<Self> {
+        let pid = pid as libc::pid_t;
+        // SAFETY: plain syscalls reading another process's settings into locals
+        unsafe {
+            let mut set: libc::cpu_set_t = std::mem::zeroed();
+            if libc::sched_getaffinity(pid, size_of::<libc::cpu_set_t>(), &mut set) != 0 {
+                return None;
+            }
+            let cpus = (0..MAX_CPUS)
+                .filter(|&cpu| libc::CPU_ISSET(cpu, &set))
+                .collect();
+
+            let mut param: libc::sched_param = std::mem::zeroed();
+            let policy = match libc::sched_getscheduler(pid) {
+                libc::SCHED_BATCH => SchedPolicy::Batch,
+                libc::SCHED_IDLE => SchedPolicy::Idle,
+                libc::SCHED_FIFO if libc::sched_getparam(pid, &mut param) == 0 => {
+                    SchedPolicy::Fifo(param.sched_priority as u8)
+                }
+                libc::SCHED_RR if libc::sched_getparam(pid, &mut param) == 0 => {
+                    SchedPolicy::RoundRobin(param.sched_priority as u8)
+                }
+                _ => SchedPolicy::Other,
+            };
+            // getpriority can legitimately return -1, so errors show only in errno
+            *libc::__errno_location() = 0;
+            let nice = libc::getpriority(libc::PRIO_PROCESS as _, pid as libc::id_t);
+            if *libc::__errno_location() != 0 {
+                return None;
+            }
+            let io_priority = libc::syscall(libc::SYS_ioprio_get, IOPRIO_WHO_PROCESS, pid);
+            Some(SchedulingState {
+                cpus,
+                policy: policy.to_string(),
+                nice,
+                io_priority: IoPriority::from_raw(io_priority as libc::c_int)
+                    .map(|priority| priority.to_string()),
+            })
+        }
+    }
+}
+
+fn cpu_set(cpus: &[usize]) -> io::Result<libc::cpu_set_t> {
+    // SAFETY: cpu_set_t is a plain bit mask, for which all zeroes is empty
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    for &cpu in cpus {
+        if cpu >= MAX_CPUS {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("CPU {} is out of range", cpu),
+            ));
+        }
+        // SAFETY: the CPU was checked to fit in the set
+        unsafe { libc::CPU_SET(cpu, &mut set) };
+    }
+    Ok(set)
+}
+
+/// Parses a CPU list such as `2,3` or `0-3,8`, as used by `taskset -c`.
+pub fn parse_cpu_list(text: &str) -> Result<Vec<usize>, String> {
+    let mut cpus = Vec::new();
+    for part in text.split(',') {
+        let parse = |cpu: &str| {
+            cpu.trim()
+                .parse::<usize>()
+                .ok()
+                .filter(|&cpu| cpu < MAX_CPUS)
+                .ok_or_else(|| format!("expected a CPU number or range, got '{}'", part))
+        };
+        match part.split_once('-') {
+            Some((first, last)) => {
+                let (first, last) = (parse(first)?, parse(last)?);
+                if first > last {
+                    return Err(format!("CPU range '{}' is reversed", part));
+                }
+                cpus.extend(first..=last);
+            }
+            None => cpus.push(parse(part)?),
+        }
+    }
+    cpus.sort_unstable();
+    cpus.dedup();
+    Ok(cpus)
+}
+
+/// Formats CPUs back into the compact list form, e.g. `0-3,8`.
+pub fn format_cpu_list(cpus: &[usize]) -> String {
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for &cpu in cpus {
+        match ranges.last_mut() {
+            Some((_, last)) if *last + 1 == cpu => *last = cpu,
+            _ => ranges.push((cpu, cpu)),
+        }
+    }
+    ranges
+        .iter()
+        .map(|&(first, last)| {
+            if first == last {
+                first.to_string()
+            } else {
+                format!("{}-{}", first, last)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cpu_list() {
+        assert_eq!(parse_cpu_list("2,3"), Ok(vec![2, 3]));
+        assert_eq!(parse_cpu_list("0-3,8,2"), Ok(vec![0, 1, 2, 3, 8]));
+        assert!(parse_cpu_list("3-1").is_err());
+        assert!(parse_cpu_list("a").is_err());
+        assert!(parse_cpu_list("99999").is_err());
+        assert_eq!(format_cpu_list(&[0, 1, 2, 3, 8]), "0-3,8");
+    }
+
+    #[test]
+    fn test_parse_sched_policy() {
+        assert_eq!("fifo:50".parse(), Ok(SchedPolicy::Fifo(50)));
+        assert_eq!("rr:1".parse(), Ok(SchedPolicy::RoundRobin(1)));
+        assert_eq!("batch".parse(), Ok(SchedPolicy::Batch));
+        assert!("fifo".parse::<SchedPolicy>().is_err());
+        assert!("fifo:0".parse::<SchedPolicy>().is_err());
+        assert!("deadline".parse::<SchedPolicy>().is_err());
+    }
+
+    #[test]
+    fn test_parse_io_priority() {
+        assert_eq!("best-effort:0".parse(), Ok(IoPriority::BestEffort(0)));
+        assert_eq!("realtime:7".parse(), Ok(IoPriority::RealTime(7)));
+        assert_eq!("idle".parse(), Ok(IoPriority::Idle));
+        assert!("best-effort:8".parse::<IoPriority>().is_err());
+        assert_eq!(
+            IoPriority::from_raw(IoPriority::BestEffort(3).raw()),
+            Some(IoPriority::BestEffort(3))
+        );
+    }
+
+    #[test]
+    fn test_state_of_self() {
+        let state = SchedulingState::of(std::process::id()).unwrap();
+        assert!(!state.cpus.is_empty());
+        assert_eq!(state.policy, "other");
+    }
+
+    #[test]
+    fn test_display() {
+        let scheduling = Scheduling {
+            cpus: Some(vec![2, 3]),
+            nice: Some(5),
+            policy: None,
+            io_priority: Some(IoPriority::Idle),
+        };
+        assert_eq!(scheduling.to_string(), "--cpus 2-3 --nice 5 --ionice idle");
+    }
+}
@@ -0,0 +1,327 @@
+use serde::{Deserialize, Serialize};
+
+/// Where a value lies relative to Tukey's fences: mild beyond 1.5 times the
+/// interquartile range from the quartiles, severe beyond 3 times.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Outlier {
+    LowSevere,
+    LowMild,
+    HighMild,
+    HighSevere,
+}
+
+impl Outlier {
+    pub fn is_severe(self) -> bool {
+        matches!(self, Outlier::LowSevere | Outlier::HighSevere)
+    }
+}
+
+impl std::fmt::Display for Outlier {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            Outlier::LowSevere => "low severe",
+            Outlier::LowMild => "low mild",
+            Outlier::HighMild => "high mild",
+            Outlier::HighSevere => "high severe",
+        })
+    }
+}
+
+pub fn mean(values: &[f64]) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+/// Value below which a fraction `p` of the sorted values fall, interpolating
+/// between neighbours.
+pub fn percentile(sorted: &[f64], p: f64) -> f64 {
+    match sorted {
+        [] => 0.0,
+        [only] => *only,
+        _ => {
+            let rank = p.clamp(0.0, 1.0) * (sorted.len() - 1) as f64;
+            let (low, high) = (rank.floor() as usize, rank.ceil() as usize);
+            sorted[low] + (sorted[high] - sorted[low]) * (rank - low as f64)
+        }
+    }
+}
+
+/// Classifies each value against Tukey's fences, in the order given. With
+/// fewer than four values the quartiles mean little and nothing is flagged.
+pub fn outliers(values: &[f64]) -> Vec<Option<Outlier>> {
+    if values.len() < 4 {
+        return vec![None; values.len()];
+    }
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let (q1, q3) = (percentile(&sorted, 0.25), percentile(&sorted, 0.75));
+    let iqr = q3 - q1;
+    values
+        .iter()
+        .map(|&value| {
+            if value < q1 - 3.0 * iqr {
+                Some(Outlier::LowSevere)
+            } else if value < q1 - 1.5 * iqr {
+                Some(Outlier::LowMild)
+            } else if value > q3 + 3.0 * iqr {
+                Some(Outlier::HighSevere)
+            } else if value > q3 + 1.5 * iqr {
+                Some(Outlier::HighMild)
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Unbiased sample variance.
+pub fn variance(values: &[f64]) -> f64 {
+    if values.len() < 2 {
+        return 0.0;
+    }
+    let mean = mean(values);
+    values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (values.len() - 1) as f64
+}
+
+/// Two-sided p-value of Welch's t-test for a difference in means. `None` when
+/// either side has fewer than two samples.
+pub fn welch_t_test(a: &[f64], b: &[f64]) -> Option<f64> {
+    if a.len() < 2 || b.len() < 2 {
+        return None;
+    }
+    let (na, nb) = (a.len() as f64, b.len() as f64);
+    let (va, vb) = (variance(a) / na, variance(b) / nb);
+    let se = (va + vb).sqrt();
+    let difference = mean(a) - mean(b);
+    if se == 0.0 {
+        // Both sides are constant, so any difference is exact
+        return Some(if difference == 0.0 { 1.0 } else { 0.0 });
+    }
+    let t = difference / se;
+    let df = (va + vb).powi(2) / (va.powi(2) / (na - 1.0) + vb.powi(2) / (nb - 1.0));
+    Some(student_t_two_tailed(t, df))
+}
+
+/// Half-width of the 95% confidence interval of the mean, relative to the
+/// mean. `None` with fewer than two samples or a mean of zero.
+pub fn relative_ci(values: &[f64]) -> Option<f64> {
+    let mean = mean(values);
+    if values.len() < 2 || mean == 0.0 {
+        return None;
+    }
+    let n = values.len() as f64;
+    let half_width = t_critical(0.05, n - 1.0) * (variance(values) / n).sqrt();
+    Some(half_width / mean.abs())
+}
+
+/// The t above which a two-sided test with significance `alpha` rejects, found
+/// by bisection since the distribution function is monotonic in t.
+pub fn t_critical(alpha: f64, df: f64) -> f64 {
+    let (mut low, mut high) = (0.0, 1e4);
+    for _ in 0..100 {
+        let middle = (low + high) / 2.0;
+        if student_t_two_tailed(middle, df) > alpha {
+            low = middle;
+        } else {
+            high = middle;
+        }
+    }
+    (low + high) / 2.0
+}
+
+/// Probability of seeing |T| >= |t| under Student's t distribution.
+pub fn student_t_two_tailed(t: f64, df: f64) -> f64 {
+    incomplete_beta(df / 2.0, 0.5, df / (df + t * t))
+}
+
+/// Regularized incomplete beta function I_x(a, b).
+fn incomplete_beta(a: f64, b: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    if x >= 1.0 {
+        return 1.0;
+    }
+    let front =
+        (ln_gamma(a + b) - ln_gamma(a) - ln_gamma(b) + a * x.ln() + b * (1.0 - x).ln()).exp();
+    // The continued fraction converges quickly only on this side of the mean
+    if x < (a + 1.0) / (a + b + 2.0) {
+        front * beta_continued_fraction(a, b, x) / a
+    } else {
+        1.0 - front * beta_continued_fraction(b, a, 1.0 - x) / b
+    }
+}
+
+/// Lentz's method for the continued fraction of the incomplete beta function.
+fn beta_continued_fraction(a: f64, b: f64, x: f64) -> f64 {
+    const MAX_ITERATIONS: usize = 200;
+    const EPSILON: f64 = 1e-14;
+    const TINY: f64 = 1e-300;
+
+    let mut c = 1.0;
+    let mut d = 1.0 - (a + b) * x / (a + 1.0);
+    if d.abs() < TINY {
+        d = TINY;
+    }
+    d = 1.0 / d;
+    let mut h = d;
+    for m in 1..=MAX_ITERATIONS {
+        let m = m as f64;
+        let m2 = 2.0 * m;
+
+        let aa = m * (b - m) * x / ((a + m2 - 1.0) * (a + m2));
+        d = 1.0 + aa * d;
+        if d.abs() < TINY {
+            d = TINY;
+        }
+        c = 1.0 + aa / c;
+        if c.abs() < TINY {
+            c = TINY;
+        }
+        d = 1.0 / d;
+        h *= d * c;
+
+        let aa = -(a + m) * (a + b + m) * x / ((a + m2) * (a + m2 + 1.0));
+        d = 1.0 + aa * d;
+        if d.abs() < TINY {
+            d = TINY;
+        }
+        c = 1.0 + aa / c;
+        if c.abs() < TINY {
+            c = TINY;
+        }
+        d = 1.0 / d;
+        let delta = d * c;
+        h *= delta;
+        if (delta - 1.0).abs() < EPSILON {
+            break;
+        }
+    }
+    h
+}
+
+/// Lanczos approximation of ln(Γ(x)) for x > 0.
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 6] = [
+        76.18009172947146,
+        -86.50532032941677,
+        24.01409824083091,
+        -1.231739572450155,
+        0.1208650973866179e-2,
+        -0.5395239384953e-5,
+    ];
+    let mut y = x;
+    let tmp = x + 5.5;
+    let tmp = tmp - (x + 0.5) * tmp.ln();
+    let mut series = 1.000000000190015;
+    for coefficient in COEFFICIENTS {
+        y += 1.0;
+        series += coefficient / y;
+    }
+    -tmp + (2.5066282746310005 * series / x).ln()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn approx(a: f64, b: f64, tolerance: f64) -> bool {
+        (a - b).abs() < tolerance
+    }
+
+    #[test]
+    fn test_percentile() {
+        let values = [1.0, 2.0, 3.0, 4.0, 5.0];
+        assert_eq!(percentile(&values, 0.5), 3.0);
+        assert_eq!(percentile(&values, 0.0), 1.0);
+        assert_eq!(percentile(&values, 1.0), 5.0);
+        assert!(approx(percentile(&values, 0.9), 4.6, 1e-9));
+        assert_eq!(percentile(&[7.0], 0.99), 7.0);
+        assert_eq!(percentile(&[], 0.5), 0.0);
+    }
+
+    #[test]
+    fn test_mean_and_variance() {
+        let values = [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0];
+        assert_eq!(mean(&values), 5.0);
+        assert!(approx(variance(&values), 32.0 / 7.0, 1e-12));
+        assert_eq!(variance(&[1.0]), 0.0);
+        assert_eq!(mean(&[]), 0.0);
+    }
+
+    #[test]
+    fn test_ln_gamma() {
+        // Γ(5) = 24, Γ(0.5) = √π
+        assert!(approx(ln_gamma(5.0), 24.0_f64.ln(), 1e-9));
+        assert!(approx(
+            ln_gamma(0.5),
+            std::f64::consts::PI.sqrt().ln(),
+            1e-9
+        ));
+    }
+
+    #[test]
+    fn test_student_t_two_tailed() {
+        assert!(approx(student_t_two_tailed(0.0, 10.0), 1.0, 1e-9));
+        // Critical values from standard t tables
+        assert!(approx(student_t_two_tailed(2.228, 10.0), 0.05, 1e-3));
+        assert!(approx(student_t_two_tailed(12.706, 1.0), 0.05, 1e-3));
+        assert!(approx(student_t_two_tailed(2.576, 1e6), 0.01, 1e-3));
+    }
+
+    #[test]
+    fn test_welch_t_test() {
+        let a = [1.00, 1.02, 0.98, 1.01, 0.99];
+        let b = [1.20, 1.22, 1.18, 1.21, 1.19];
+        assert!(welch_t_test(&a, &b).unwrap() < 0.001);
+
+        let c = [1.00, 1.10, 0.90, 1.05, 0.95];
+        let d = [1.01, 1.09, 0.91, 1.04, 0.96];
+        assert!(welch_t_test(&c, &d).unwrap() > 0.5);
+
+        assert_eq!(welch_t_test(&[1.0], &b), None);
+        assert_eq!(welch_t_test(&[1.0, 1.0], &[1.0, 1.0]), Some(1.0));
+        assert_eq!(welch_t_test(&[1.0, 1.0], &[2.0, 2.0]), Some(0.0));
+    }
+
+    #[test]
+    fn test_t_critical() {
+        assert!((t_critical(0.05, 1.0) - 12.706).abs() < 1e-3);
+        assert!((t_critical(0.05, 10.0) - 2.228).abs() < 1e-3);
+        assert!((t_critical(0.05, 1e6) - 1.960).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_relative_ci() {
+        assert_eq!(relative_ci(&[1.0]), None);
+        assert_eq!(relative_ci(&[0.0, 0.0]), None);
+        assert_eq!(relative_ci(&[2.0, 2.0, 2.0]), Some(0.0));
+        // Mean 2, standard error 1/sqrt(3), t(0.05, 2) = 4.303
+        let ci = relative_ci(&[1.0, 2.0, 3.0]).unwrap();
+        assert!((ci - 4.303 / 3f64.sqrt() / 2.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_outliers() {
+        assert_eq!(outliers(&[1.0, 9.0, 1.0]), [None, None, None]);
+        // Quartiles 1.0 and 1.2: mild fences at 0.7 and 1.5, severe at 0.4 and 1.8
+        let values = [1.0, 1.0, 1.0, 1.1, 1.1, 1.1, 1.2, 1.2, 1.2, 3.0, 1.6, 0.6];
+        let flagged: Vec<(usize, Outlier)> = outliers(&values)
+            .into_iter()
+            .enumerate()
+            .filter_map(|(i, outlier)| Some((i, outlier?)))
+            .collect();
+        assert_eq!(
+            flagged,
+            [
+                (9, Outlier::HighSevere),
+                (10, Outlier::HighMild),
+                (11, Outlier::LowMild)
+            ]
+        );
+        assert_eq!(outliers(&[2.0; 5]), [None; 5]);
+    }
+}
@@ -0,0 +1,356 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+/// An executable region of a process, from /proc/<pid>/maps or a perf mmap record.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Mapping {
+    pub(crate) start: u64,
+    pub(crate) end: u64,
+    /// Offset into the file the region starts at.
+    pub(crate) offset: u64,
+    pub(crate) path: String,
+}
+
+/// Executable mappings of a running process.
+pub(crate) fn read_maps(pid: u32) -> Vec<Mapping> {
+    std::fs::read_to_string(format!("/proc/{}/maps", pid))
+        .map(|maps| parse_maps(&maps))
+        .unwrap_or_default()
+}
+
+/// Lines such as `7f2c1000-7f2c3000 r-xp 00002000 08:01 1234 /usr/lib/libc.so.6`.
+fn parse_maps(maps: &str) -> Vec<Mapping> {
+    maps.lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let (start, end) = fields.next()?.split_once('-')?;
+            if !fields.next()?.contains('x') {
+                return None;
+            }
+            let offset = u64::from_str_radix(fields.next()?, 16).ok()?;
+            let path = fields.nth(2).unwrap_or_default().to_string();
+            Some(Mapping {
+                start: u64::from_str_radix(start, 16).ok()?,
+                end: u64::from_str_radix(end, 16).ok()?,
+                offset,
+                path,
+            })
+        })
+        .collect()
+}
+
+/// Function symbols sorted by address, as (start, end, name).
+type SymbolTable = Vec<(u64, u64, String)>;
+
+/// Turns sampled addresses into function names: user addresses through the
+/// symbol tables of the mapped files, or a `/tmp/perf-<pid>.map` written by a
+/// JIT, and kernel ones through /proc/kallsyms. Files are read once each.
+#[derive(Default)]
+pub(crate) struct Symbolizer {
+    files: HashMap<String, Option<ElfSymbols>>,
+    jit: HashMap<u32, SymbolTable>,
+    kernel: Option<SymbolTable>,
+}
+
+impl Symbolizer {
+    /// Name of the function at `address` in process `pid`, falling back to
+    /// the file it is in.
+    pub(crate) fn user(&mut self, pid: u32, maps: &[Mapping], address: u64) -> String {
+        let Some(mapping) = maps.iter().find(|m| (m.start..m.end).contains(&address)) else {
+            let jit = self.jit.entry(pid).or_insert_with(|| read_perf_map(pid));
+            return lookup(jit, address).unwrap_or("[unknown]").to_string();
+        };
+        if !mapping.path.starts_with('/') {
+            // [vdso], [anon:...] and the like
+            return match mapping.path.as_str() {
+                "" => String::from("[unknown]"),
+                path => path.to_string(),
+            };
+        }
+        let elf = self
+            .files
+            .entry(mapping.path.clone())
+            .or_insert_with(|| ElfSymbols::read(Path::new(&mapping.path)));
+        elf.as_ref()
+            .and_then(|elf| elf.lookup(address - mapping.start + mapping.offset))
+            .map(demangle)
+            .unwrap_or_else(|| {
+                let file = mapping.path.rsplit('/').next().unwrap_or_default();
+                format!("[{}]", file)
+            })
+    }
+
+    /// Name of the kernel function at `address`, marked like perf does.
+    pub(crate) fn kernel(&mut self, address: u64) -> String {
+        let kernel = self.kernel.get_or_insert_with(read_kallsyms);
+        format!("{}_[k]", lookup(kernel, address).unwrap_or("[unknown]"))
+    }
+}
+
+fn lookup(symbols: &SymbolTable, address: u64) -> Option<&str> {
+    let index = symbols.partition_point(|&(start, _, _)| start <= address);
+    let (_, end, name) = symbols.get(index.checked_sub(1)?)?;
+    (address < *end).then_some(name.as_str())
+}
+
+/// Kernel text symbols. The addresses read as zero without root, leaving nothing to look up.
+fn read_kallsyms() -> SymbolTable {
+    let text = std::fs::read_to_string("/proc/kallsyms").unwrap_or_default();
+    let mut starts: Vec<(u64, String)> = text
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let address = u64::from_str_radix(fields.next()?, 16).ok()?;
+            let kind = fields.next()?;
+            let name = fields.next()?;
+            (address != 0 && matches!(kind, "t" | "T")).then(|| (address, name.to_string()))
+        })
+        .collect();
+    starts.sort();
+    // Each function runs until the next one starts
+    let ends: Vec<u64> = starts.iter().skip(1).map(|&(start, _)| start).collect();
+    starts
+        .into_iter()
+        .zip(ends.into_iter().chain([u64::MAX]))
+        .map(|((start, name), end)| (start, end, name))
+        .collect()
+}
+
+/// Lines of `START SIZE name`, in hex, that JITs such as the JVM's perf agent
+/// or node's --perf-basic-prof write for the code they generate.
+fn read_perf_map(pid: u32) -> SymbolTable {
+    let text = std::fs::read_to_string(format!("/tmp/perf-{}.map", pid)).unwrap_or_default();
+    let mut symbols: SymbolTable = text
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(3, ' ');
+            let start = u64::from_str_radix(fields.next()?, 16).ok()?;
+            let size = u64::from_str_radix(fields.next()?, 16).ok()?;
+            Some((start, start + size, fields.next()?.to_string()))
+        })
+        .collect();
+    symbols.sort();
+    symbols
+}
+
+/// The function symbols of an ELF file, found by file offset.
+struct ElfSymbols {
+    /// Loadable segments as (file offset, virtual address, size in the file).
+    segments: Vec<(u64, u64, u64)>,
+    symbols: SymbolTable,
+}
+
+impl ElfSymbols {
+    /// Reads a 64-bit little-endian ELF file, using .symtab where it was not
+    /// stripped and .dynsym otherwise.
+    fn read(path: &Path) -> Option<Self> {
+        let data = std::fs::read(path).ok()?;
+        if data.get(..6)? != b"\x7fELF\x02\x01" {
+            return None;
+        }
+        let (phoff, shoff) = (read_u64(&data, 0x20)?, read_u64(&data, 0x28)?);
+        let (phsize, phnum) = (read_u16(&data, 0x36)?, read_u16(&data, 0x38)?);
+        let (shsize, shnum) = (read_u16(&data, 0x3a)?, read_u16(&data, 0x3c)?);
+
+        let segments = (0..phnum as u64)
+            .filter_map(|i| {
+                let header = phoff + i * phsize as u64;
+                // PT_LOAD
+                (read_u32(&data, header)? == 1).then_some((
+                    read_u64(&data, header + 0x08)?,
+                    read_u64(&data, header + 0x10)?,
+                    read_u64(&data, header + 0x20)?,
+                ))
+            })
+            .collect();
+
+        let section = |i: u64| -> Option<(u32, u64, u64, u32)> {
+            let header = shoff + i * shsize as u64;
+            Some((
+                read_u32(&data, header + 0x04)?,
+                read_u64(&data, header + 0x18)?,
+                read_u64(&data, header + 0x20)?,
+                read_u32(&data, header + 0x28)?,
+            ))
+        };
+        let sections: Vec<_> = (0..shnum as u64).filter_map(section).collect();
+        // SHT_SYMTAB, else SHT_DYNSYM
+        let table = [2, 11]
+            .iter()
+            .find_map(|&kind| sections.iter().find(|s| s.0 == kind))?;
+        let (_, offset, size, link) = *table;
+        let (_, strings, _, _) = *sections.get(link as usize)?;
+
+        let mut symbols: SymbolTable = (0..size / 24)
+            .filter_map(|i| {
+                let entry = offset + i * 24;
+                let info = *data.get(entry as usize + 4)?;
+                let value = read_u64(&data, entry + 8)?;
+                let size = read_u64(&data, entry + 16)?;
+                // STT_FUNC or STT_GNU_IFUNC, defined here
+                if !matches!(info & 0xf, 2 | 10) || value == 0 || size == 0 {
+                    return None;
+                }
+                let name = read_str(&data, strings + read_u32(&data, entry)? as u64)?;
+                Some((value, value + size, name.to_string()))
+            })
+            .collect();
+        symbols.sort();
+        Some(ElfSymbols { segments, symbols })
+    }
+
+    fn lookup(&self, offset: u64) -> Option<&str> {
+        let &(file_offset, address, _) = self
+            .segments
+            .iter()
+            .find(|&&(start, _, size)| (start..start + size).contains(&offset))?;
+        lookup(&self.symbols, offset - file_offset + address)
+    }
+}
+
+fn read_u16(data: &[u8], at: u64) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        data.get(at as usize..)?.get(..2)?.try_into().ok()?,
+    ))
+}
+
+fn read_u32(data: &[u8], at: u64) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(at as usize..)?.get(..4)?.try_into().ok()?,
+    ))
+}
+
+fn read_u64(data: &[u8], at: u64) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        data.get(at as usize..)?.get(..8)?.try_into().ok()?,
+    ))
+}
+
+fn read_str(data: &[u8], at: u64) -> Option<&str> {
+    let bytes = data.get(at as usize..)?;
+    let end = bytes.iter().position(|&b| b == 0)?;
+    std::str::from_utf8(&bytes[..end]).ok()
+}
+
+/// Readable names for symbols mangled as Rust's legacy scheme or simple C++
+/// nested names, e.g. `_ZN4lens5stats4mean17h0123456789abcdefE` as
+/// `lens::stats::mean`. Anything else is left as it is.
+pub(crate) fn demangle(symbol: &str) -> String {
+    let Some(mut rest) = symbol.strip_prefix("_ZN") else {
+        return symbol.to_string();
+    };
+    let mut parts = Vec::new();
+    while !rest.starts_with('E') {
+        let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+        let Some(len) = rest[..digits].parse::<usize>().ok() else {
+            return symbol.to_string();
+        };
+        let Some(part) = rest.get(digits..digits + len) else {
+            return symbol.to_string();
+        };
+        parts.push(part);
+        rest = &rest[digits + len..];
+    }
+    // Rust appends a hash of the crate to each path
+    if let Some(last) = parts.last()
+        && last.len() == 17
+        && last.starts_with('h')
+        && last[1..].bytes().all(|b| b.is_ascii_hexdigit())
+    {
+        parts.pop();
+    }
+    parts
+        .iter()
+        .map(|part| unescape(part.strip_prefix("_$").map_or(part, |_| &part[1..])))
+        .collect::<Vec<_>>()
+        .join("::")
+}
+
+/// Undoes the `$LT$`-style escapes of legacy Rust symbols.
+fn unescape(part: &str) -> String {
+    const ESCAPES: [(&str, &str); 15] = [
+        ("$LT$", "<"),
+        ("$GT$", ">"),
+        ("$RF$", "&"),
+        ("$BP$", "*"),
+        ("$C$", ","),
+        ("$SP$", "@"),
+        ("$LP$", "("),
+        ("$RP$", ")"),
+        ("$u20$", " "),
+        ("$u22$", "\""),
+        ("$u27$", "'"),
+        ("$u5b$", "["),
+        ("$u5d$", "]"),
+        ("$u7b$", "{"),
+        ("$u7d$", "}"),
+    ];
+    let mut text = part.replace("..", "::");
+    for (escape, replacement) in ESCAPES {
+        text = text.replace(escape, replacement);
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_maps() {
+        let maps = "\
+55d0c000-55d0e000 r--p 00000000 08:01 42 /usr/bin/cat
+55d0e000-55d12000 r-xp 00002000 08:01 42 /usr/bin/cat
+7ffd1000-7ffd3000 r-xp 00000000 00:00 0 [vdso]
+7ffd5000-7ffd6000 rw-p 00000000 00:00 0
+";
+        let maps = parse_maps(maps);
+        assert_eq!(maps.len(), 2);
+        assert_eq!(
+            maps[0],
+            Mapping {
+                start: 0x55d0e000,
+                end: 0x55d12000,
+                offset: 0x2000,
+                path: String::from("/usr/bin/cat")
+            }
+        );
+        assert_eq!(maps[1].path, "[vdso]");
+    }
+
+    #[test]
+    fn test_demangle() {
+        assert_eq!(
+            demangle("_ZN4lens5stats4mean17h0123456789abcdefE"),
+            "lens::stats::mean"
+        );
+        assert_eq!(
+            demangle("_ZN45_$LT$lens..units..Size$u20$as$u20$Display$GT$3fmt17h0123456789abcdefE"),
+            "<lens::units::Size as Display>::fmt"
+        );
+        assert_eq!(demangle("_ZN3foo3barEv"), "foo::bar");
+        assert_eq!(demangle("memcpy"), "memcpy");
+        assert_eq!(demangle("_ZN3fooX"), "_ZN3fooX");
+    }
+
+    #[test]
+    fn test_lookup() {
+        let symbols = vec![
+            (0x10, 0x20, String::from("a")),
+            (0x30, 0x40, String::from("b")),
+        ];
+        assert_eq!(lookup(&symbols, 0x18), Some("a"));
+        assert_eq!(lookup(&symbols, 0x20), None);
+        assert_eq!(lookup(&symbols, 0x30), Some("b"));
+        assert_eq!(lookup(&symbols, 0x08), None);
+    }
+
+    #[test]
+    fn test_symbolize_own_function() {
+        let address = test_symbolize_own_function as fn() as usize as u64;
+        let maps = read_maps(std::process::id());
+        let mut symbolizer = Symbolizer::default();
+        let name = symbolizer.user(std::process::id(), &maps, address);
+        assert!(name.ends_with("test_symbolize_own_function"), "{}", name);
+    }
+}
@@ -0,0 +1,709 @@
+use crate::files::{FileRecorder, FileReport};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::os::unix::process::CommandExt;
+use std::time::{Duration, Instant};
+
+/// Upper bounds of the latency histogram buckets, in microseconds. A last
+/// bucket counts everything slower.
+pub const BUCKET_BOUNDS_USEC: [u64; 6] = [1, 10, 100, 1_000, 10_000, 100_000];
+
+/// Calls of one syscall during a traced run.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SyscallStat {
+    pub name: String,
+    pub calls: u64,
+    pub errors: u64,
+    /// From entry to exit, so it includes the time spent blocked.
+    pub total_usec: u64,
+    /// Calls per bucket of [`BUCKET_BOUNDS_USEC`].
+    pub histogram: [u64; 7],
+}
+
+/// The syscalls made by a command and everything it started, like `strace -c`.
+/// Comes from a separate run under ptrace, which is slowed down by stopping
+/// twice per syscall.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SyscallProfile {
+    /// Wall time of the traced run, overhead included.
+    pub wall_time: f64,
+    /// How many times longer the traced run took than the timed runs did on
+    /// average, set once those are known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub overhead: Option<f64>,
+    /// Processes and threads traced.
+    pub tasks: usize,
+    /// Most time spent first.
+    pub syscalls: Vec<SyscallStat>,
+}
+
+impl SyscallProfile {
+    pub fn total_usec(&self) -> u64 {
+        self.syscalls.iter().map(|s| s.total_usec).sum()
+    }
+}
+
+/// What a traced run recorded.
+#[derive(Debug, Clone)]
+pub struct Trace {
+    pub syscalls: SyscallProfile,
+    /// Only when asked for, as it costs extra work on every read and write.
+    pub files: Option<FileReport>,
+}
+
+/// Tally of a syscall number while tracing.
+#[derive(Default)]
+struct Tally {
+    calls: u64,
+    errors: u64,
+    total: Duration,
+    histogram: [u64; 7],
+}
+
+/// Any task, thread or not, but only those this thread started or traces.
+const WAIT_FLAGS: libc::c_int = libc::__WALL | libc::__WNOTHREAD;
+
+/// Runs the command under ptrace until it and every task it started have
+/// exited, timing each syscall from its entry to its exit stop, and passing
+/// them on to `files` if given. `started` gets the pid once the command runs.
+///
+/// Blocks throughout, and has to: ptrace requests only work from the thread
+/// that spawned the tracee. Only that thread's children and tracees are
+/// waited for, so other children of lens are left to whoever started them.
+pub(crate) fn trace(
+    mut command: std::process::Command,
+    started: impl FnOnce(u32),
+    mut files: Option<FileRecorder>,
+) -> io::Result<Trace> {
+    // SAFETY: the closure makes a single ptrace syscall
+    unsafe {
+        command.pre_exec(|| {
+            if libc::ptrace(libc::PTRACE_TRACEME, 0, 0, 0) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let start = Instant::now();
+    let child = command.spawn()?;
+    let pid = child.id() as libc::pid_t;
+    started(pid as u32);
+
+    // The tracee stops with SIGTRAP once it has exec'd the command
+    let mut status = 0;
+    // SAFETY: waitpid only writes the status
+    if unsafe { libc::waitpid(pid, &mut status, WAIT_FLAGS) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    if !libc::WIFSTOPPED(status) {
+        return Err(io::Error::other(
+            "the command exited before it could be traced",
+        ));
+    }
+    if let Some(files) = &mut files {
+        files.exec(pid);
+    }
+    let options = libc::PTRACE_O_TRACESYSGOOD
+        | libc::PTRACE_O_TRACECLONE
+        | libc::PTRACE_O_TRACEFORK
+        | libc::PTRACE_O_TRACEVFORK
+        | libc::PTRACE_O_TRACEEXEC
+        | libc::PTRACE_O_EXITKILL;
+    // SAFETY: pid is a stopped tracee of this thread
+    unsafe {
+        libc::ptrace(libc::PTRACE_SETOPTIONS, pid, 0, options);
+        libc::ptrace(libc::PTRACE_SYSCALL, pid, 0, 0);
+    }
+
+    let mut tallies: HashMap<u64, Tally> = HashMap::new();
+    let mut in_syscall: HashMap<libc::pid_t, (u64, [u64; 6], Instant)> = HashMap::new();
+    let mut alive = HashSet::from([pid]);
+    let mut seen = HashSet::from([pid]);
+    while !alive.is_empty() {
+        // SAFETY: waitpid only writes the status
+        let tid = unsafe { libc::waitpid(-1, &mut status, WAIT_FLAGS) };
+        if tid == -1 {
+            match io::Error::last_os_error() {
+                e if e.raw_os_error() == Some(libc::EINTR) => continue,
+                e if e.raw_os_error() == Some(libc::ECHILD) => break,
+                e => return Err(e),
+            }
+        }
+        if !libc::WIFSTOPPED(status) {
+            alive.remove(&tid);
+            in_syscall.remove(&tid);
+            continue;
+        }
+        alive.insert(tid);
+        let first_stop = seen.insert(tid);
+        let signal = libc::WSTOPSIG(status);
+        let event = status >> 16;
+        let mut deliver = 0;
+        if signal == libc::SIGTRAP | 0x80 {
+            let Some(info) = syscall_info(tid) else {
+                resume(tid, 0);
+                continue;
+            };
+            match info.op {
+                libc::PTRACE_SYSCALL_INFO_ENTRY => {
+                    // SAFETY: op says the entry member is the one filled in
+                    let (nr, args) = unsafe { (info.u.entry.nr, info.u.entry.args) };
+                    tallies.entry(nr).or_default().calls += 1;
+                    in_syscall.insert(tid, (nr, args, Instant::now()));
+                }
+                libc::PTRACE_SYSCALL_INFO_EXIT => {
+                    if let Some((nr, args, entered)) = in_syscall.remove(&tid) {
+                        let elapsed = entered.elapsed();
+                        // SAFETY: op says the exit member is the one filled in
+                        let (ret, is_error) = unsafe { (info.u.exit.sval, info.u.exit.is_error) };
+                        let tally = tallies.entry(nr).or_default();
+                        tally.total += elapsed;
+                        tally.histogram[bucket(elapsed)] += 1;
+                        if is_error != 0 {
+                            tally.errors += 1;
+                        }
+                        if let Some(files) = &mut files {
+                            // Before resuming, so the descriptor is still open
+                            files.syscall(tid, nr, &args, ret);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        } else if signal == libc::SIGTRAP && event != 0 {
+            let mut message: libc::c_ulong = 0;
+            // SAFETY: the message is written to a c_ulong as ptrace expects
+            unsafe { libc::ptrace(libc::PTRACE_GETEVENTMSG, tid, 0, &mut message) };
+            match event {
+                libc::PTRACE_EVENT_FORK | libc::PTRACE_EVENT_VFORK | libc::PTRACE_EVENT_CLONE => {
+                    alive.insert(message as libc::pid_t);
+                }
+                libc::PTRACE_EVENT_EXEC => {
+                    // An exec from another thread takes over the thread group leader's id
+                    if message as libc::pid_t != tid {
+                        alive.remove(&(message as libc::pid_t));
+                        in_syscall.remove(&(message as libc::pid_t));
+                    }
+                    if let Some(files) = &mut files {
+                        files.exec(tid);
+                    }
+                }
+                _ => {}
+            }
+        } else if !(first_stop && signal == libc::SIGSTOP) {
+            // A signal for the tracee, passed on. New tasks start with a SIGSTOP of their own
+            deliver = signal;
+        }
+        resume(tid, deliver);
+    }
+    let wall_time = start.elapsed().as_secs_f64();
+
+    let mut syscalls: Vec<SyscallStat> = tallies
+        .into_iter()
+        .map(|(nr, tally)| SyscallStat {
+            name: name(nr),
+            calls: tally.calls,
+            errors: tally.errors,
+            total_usec: tally.total.as_micros() as u64,
+            histogram: tally.histogram,
+        })
+        .collect();
+    syscalls.sort_by(|a, b| b.total_usec.cmp(&a.total_usec).then(b.calls.cmp(&a.calls)));
+    Ok(Trace {
+        syscalls: SyscallProfile {
+            wall_time,
+            overhead: None,
+            tasks: seen.len(),
+            syscalls,
+        },
+        files: files.map(FileRecorder::finish),
+    })
+}
+
+fn syscall_info(tid: libc::pid_t) -> Option<libc::ptrace_syscall_info> {
+    let size = std::mem::size_of::<libc::ptrace_syscall_info>();
+    // SAFETY: the struct is plain data, and ptrace writes at most size bytes
+    unsafe {
+        let mut info: libc::ptrace_syscall_info = std::mem::zeroed();
+        let written = libc::ptrace(libc::PTRACE_GET_SYSCALL_INFO, tid, size, &mut info);
+        (written > 0).then_some(info)
+    }
+}
+
+/// Lets a stopped tracee run on to its next syscall stop.
+fn resume(tid: libc::pid_t, signal: libc::c_int) {
+    // SAFETY: tid is a stopped tracee; failing because it died meanwhile is fine
+    unsafe { libc::ptrace(libc::PTRACE_SYSCALL, tid, 0, signal) };
+}
+
+fn bucket(elapsed: Duration) -> usize {
+    let usec = elapsed.as_micros() as u64;
+    BUCKET_BOUNDS_USEC
+        .iter()
+        .position(|&bound| usec < bound)
+        .unwrap_or(BUCKET_BOUNDS_USEC.len())
+}
+
+/// Name of a syscall number on this architecture.
+fn name(nr: u64) -> String {
+    COMMON
+        .iter()
+        .chain(X86_64)
+        .find(|(number, _)| *number as u64 == nr)
+        .and_then(|(_, name)| name.strip_prefix("SYS_"))
+        .map(str::to_string)
+        .unwrap_or_else(|| format!("syscall_{}", nr))
+}
+
+macro_rules! syscalls {
+    ($($name:ident),* $(,)?) => {
+        &[$((libc::$name, stringify!($name))),*]
+    };
+}
+
+/// Syscalls of the generic table, which aarch64 uses as is.
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+const COMMON: &[(libc::c_long, &str)] = syscalls![
+    SYS_accept,
+    SYS_accept4,
+    SYS_acct,
+    SYS_add_key,
+    SYS_adjtimex,
+    SYS_bind,
+    SYS_bpf,
+    SYS_brk,
+    SYS_capget,
+    SYS_capset,
+    SYS_chdir,
+    SYS_chroot,
+    SYS_clock_adjtime,
+    SYS_clock_getres,
+    SYS_clock_gettime,
+    SYS_clock_nanosleep,
+    SYS_clock_settime,
+    SYS_clone,
+    SYS_clone3,
+    SYS_close,
+    SYS_close_range,
+    SYS_connect,
+    SYS_copy_file_range,
+    SYS_delete_module,
+    SYS_dup,
+    SYS_dup3,
+    SYS_epoll_create1,
+    SYS_epoll_ctl,
+    SYS_epoll_pwait,
+    SYS_epoll_pwait2,
+    SYS_eventfd2,
+    SYS_execve,
+    SYS_execveat,
+    SYS_exit,
+    SYS_exit_group,
+    SYS_faccessat,
+    SYS_faccessat2,
+    SYS_fallocate,
+    SYS_fanotify_init,
+    SYS_fanotify_mark,
+    SYS_fchdir,
+    SYS_fchmod,
+    SYS_fchmodat,
+    SYS_fchown,
+    SYS_fchownat,
+    SYS_fcntl,
+    SYS_fdatasync,
+    SYS_fgetxattr,
+    SYS_finit_module,
+    SYS_flistxattr,
+    SYS_flock,
+    SYS_fremovexattr,
+    SYS_fsconfig,
+    SYS_fsetxattr,
+    SYS_fsmount,
+    SYS_fsopen,
+    SYS_fspick,
+    SYS_fstat,
+    SYS_fstatfs,
+    SYS_fsync,
+    SYS_ftruncate,
+    SYS_futex,
+    SYS_futex_waitv,
+    SYS_get_mempolicy,
+    SYS_get_robust_list,
+    SYS_getcpu,
+    SYS_getcwd,
+    SYS_getdents64,
+    SYS_getegid,
+    SYS_geteuid,
+    SYS_getgid,
+    SYS_getgroups,
+    SYS_getitimer,
+    SYS_getpeername,
+    SYS_getpgid,
+    SYS_getpid,
+    SYS_getppid,
+    SYS_getpriority,
+    SYS_getrandom,
+    SYS_getresgid,
+    SYS_getresuid,
+    SYS_getrusage,
+    SYS_getsid,
+    SYS_getsockname,
+    SYS_getsockopt,
+    SYS_gettid,
+    SYS_gettimeofday,
+    SYS_getuid,
+    SYS_getxattr,
+    SYS_init_module,
+    SYS_inotify_add_watch,
+    SYS_inotify_init1,
+    SYS_inotify_rm_watch,
+    SYS_io_cancel,
+    SYS_io_destroy,
+    SYS_io_getevents,
+    SYS_io_setup,
+    SYS_io_submit,
+    SYS_io_uring_enter,
+    SYS_io_uring_register,
+    SYS_io_uring_setup,
+    SYS_ioctl,
+    SYS_ioprio_get,
+    SYS_ioprio_set,
+    SYS_kcmp,
+    SYS_kexec_file_load,
+    SYS_kexec_load,
+    SYS_keyctl,
+    SYS_kill,
+    SYS_landlock_add_rule,
+    SYS_landlock_create_ruleset,
+    SYS_landlock_restrict_self,
+    SYS_lgetxattr,
+    SYS_linkat,
+    SYS_listen,
+    SYS_listxattr,
+    SYS_llistxattr,
+    SYS_lookup_dcookie,
+    SYS_lremovexattr,
+    SYS_lseek,
+    SYS_lsetxattr,
+    SYS_madvise,
+    SYS_mbind,
+    SYS_membarrier,
+    SYS_memfd_create,
+    SYS_memfd_secret,
+    SYS_migrate_pages,
+    SYS_mincore,
+    SYS_mkdirat,
+    SYS_mknodat,
+    SYS_mlock,
+    SYS_mlock2,
+    SYS_mlockall,
+    SYS_mmap,
+    SYS_mount,
+    SYS_mount_setattr,
+    SYS_move_mount,
+    SYS_move_pages,
+    SYS_mprotect,
+    SYS_mq_getsetattr,
+    SYS_mq_notify,
+    SYS_mq_open,
+    SYS_mq_timedreceive,
+    SYS_mq_timedsend,
+    SYS_mq_unlink,
+    SYS_mremap,
+    SYS_mseal,
+    SYS_msgctl,
+    SYS_msgget,
+    SYS_msgrcv,
+    SYS_msgsnd,
+    SYS_msync,
+    SYS_munlock,
+    SYS_munlockall,
+    SYS_munmap,
+    SYS_name_to_handle_at,
+    SYS_nanosleep,
+    SYS_newfstatat,
+    SYS_nfsservctl,
+    SYS_open_by_handle_at,
+    SYS_open_tree,
+    SYS_openat,
+    SYS_openat2,
+    SYS_perf_event_open,
+    SYS_personality,
+    SYS_pidfd_getfd,
+    SYS_pidfd_open,
+    SYS_pidfd_send_signal,
+    SYS_pipe2,
+    SYS_pivot_root,
+    SYS_pkey_alloc,
+    SYS_pkey_free,
+    SYS_pkey_mprotect,
+    SYS_ppoll,
+    SYS_prctl,
+    SYS_pread64,
+    SYS_preadv,
+    SYS_preadv2,
+    SYS_prlimit64,
+    SYS_process_madvise,
+    SYS_process_mrelease,
+    SYS_process_vm_readv,
+    SYS_process_vm_writev,
+    SYS_pselect6,
+    SYS_ptrace,
+    SYS_pwrite64,
+    SYS_pwritev,
+    SYS_pwritev2,
+    SYS_quotactl,
+    SYS_quotactl_fd,
+    SYS_read,
+    SYS_readahead,
+    SYS_readlinkat,
+    SYS_readv,
+    SYS_reboot,
+    SYS_recvfrom,
+    SYS_recvmmsg,
+    SYS_recvmsg,
+    SYS_remap_file_pages,
+    SYS_removexattr,
+    SYS_renameat2,
+    SYS_request_key,
+    SYS_restart_syscall,
+    SYS_rseq,
+    SYS_rt_sigaction,
+    SYS_rt_sigpending,
+    SYS_rt_sigprocmask,
+    SYS_rt_sigqueueinfo,
+    SYS_rt_sigreturn,
+    SYS_rt_sigsuspend,
+    SYS_rt_sigtimedwait,
+    SYS_rt_tgsigqueueinfo,
+    SYS_sched_get_priority_max,
+    SYS_sched_get_priority_min,
+    SYS_sched_getaffinity,
+    SYS_sched_getattr,
+    SYS_sched_getparam,
+    SYS_sched_getscheduler,
+    SYS_sched_rr_get_interval,
+    SYS_sched_setaffinity,
+    SYS_sched_setattr,
+    SYS_sched_setparam,
+    SYS_sched_setscheduler,
+    SYS_sched_yield,
+    SYS_seccomp,
+    SYS_semctl,
+    SYS_semget,
+    SYS_semop,
+    SYS_semtimedop,
+    SYS_sendmmsg,
+    SYS_sendmsg,
+    SYS_sendto,
+    SYS_set_mempolicy,
+    SYS_set_mempolicy_home_node,
+    SYS_set_robust_list,
+    SYS_set_tid_address,
+    SYS_setdomainname,
+    SYS_setfsgid,
+    SYS_setfsuid,
+    SYS_setgid,
+    SYS_setgroups,
+    SYS_sethostname,
+    SYS_setitimer,
+    SYS_setns,
+    SYS_setpgid,
+    SYS_setpriority,
+    SYS_setregid,
+    SYS_setresgid,
+    SYS_setresuid,
+    SYS_setreuid,
+    SYS_setsid,
+    SYS_setsockopt,
+    SYS_settimeofday,
+    SYS_setuid,
+    SYS_setxattr,
+    SYS_shmat,
+    SYS_shmctl,
+    SYS_shmdt,
+    SYS_shmget,
+    SYS_shutdown,
+    SYS_sigaltstack,
+    SYS_signalfd4,
+    SYS_socket,
+    SYS_socketpair,
+    SYS_splice,
+    SYS_statfs,
+    SYS_statx,
+    SYS_swapoff,
+    SYS_swapon,
+    SYS_symlinkat,
+    SYS_sync,
+    SYS_syncfs,
+    SYS_sysinfo,
+    SYS_syslog,
+    SYS_tee,
+    SYS_tgkill,
+    SYS_timer_create,
+    SYS_timer_delete,
+    SYS_timer_getoverrun,
+    SYS_timer_gettime,
+    SYS_timer_settime,
+    SYS_timerfd_create,
+    SYS_timerfd_gettime,
+    SYS_timerfd_settime,
+    SYS_times,
+    SYS_tkill,
+    SYS_truncate,
+    SYS_umask,
+    SYS_umount2,
+    SYS_uname,
+    SYS_unlinkat,
+    SYS_unshare,
+    SYS_userfaultfd,
+    SYS_utimensat,
+    SYS_vhangup,
+    SYS_vmsplice,
+    SYS_wait4,
+    SYS_waitid,
+    SYS_write,
+    SYS_writev
+];
+
+/// The ones x86_64 kept from before the generic table.
+#[cfg(target_arch = "x86_64")]
+const X86_64: &[(libc::c_long, &str)] = syscalls![
+    SYS__sysctl,
+    SYS_access,
+    SYS_afs_syscall,
+    SYS_alarm,
+    SYS_arch_prctl,
+    SYS_chmod,
+    SYS_chown,
+    SYS_creat,
+    SYS_dup2,
+    SYS_epoll_create,
+    SYS_epoll_ctl_old,
+    SYS_epoll_wait,
+    SYS_epoll_wait_old,
+    SYS_eventfd,
+    SYS_fadvise64,
+    SYS_fchmodat2,
+    SYS_fork,
+    SYS_futimesat,
+    SYS_get_thread_area,
+    SYS_getdents,
+    SYS_getpgrp,
+    SYS_getpmsg,
+    SYS_getrlimit,
+    SYS_inotify_init,
+    SYS_ioperm,
+    SYS_iopl,
+    SYS_lchown,
+    SYS_link,
+    SYS_lstat,
+    SYS_mkdir,
+    SYS_mknod,
+    SYS_modify_ldt,
+    SYS_open,
+    SYS_pause,
+    SYS_pipe,
+    SYS_poll,
+    SYS_putpmsg,
+    SYS_readlink,
+    SYS_rename,
+    SYS_renameat,
+    SYS_rmdir,
+    SYS_security,
+    SYS_select,
+    SYS_sendfile,
+    SYS_set_thread_area,
+    SYS_setrlimit,
+    SYS_signalfd,
+    SYS_stat,
+    SYS_symlink,
+    SYS_sync_file_range,
+    SYS_sysfs,
+    SYS_time,
+    SYS_tuxcall,
+    SYS_unlink,
+    SYS_uselib,
+    SYS_ustat,
+    SYS_utime,
+    SYS_utimes,
+    SYS_vfork,
+    SYS_vserver
+];
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+const COMMON: &[(libc::c_long, &str)] = &[];
+
+#[cfg(not(target_arch = "x86_64"))]
+const X86_64: &[(libc::c_long, &str)] = &[];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket() {
+        assert_eq!(bucket(Duration::from_nanos(500)), 0);
+        assert_eq!(bucket(Duration::from_micros(50)), 2);
+        assert_eq!(bucket(Duration::from_millis(5)), 4);
+        assert_eq!(bucket(Duration::from_secs(2)), 6);
+    }
+
+    #[test]
+    fn test_name() {
+        assert_eq!(name(libc::SYS_read as u64), "read");
+        assert_eq!(name(libc::SYS_openat as u64), "openat");
+        assert_eq!(name(100_000), "syscall_100000");
+    }
+
+    #[test]
+    fn test_trace_counts_syscalls_of_children() {
+        let mut command = std::process::Command::new("sh");
+        command
+            .args(["-c", "cat /etc/hostname /etc/hostname >/dev/null; true"])
+            .stdin(std::process::Stdio::null());
+        let profile = match trace(command, |_| {}, None) {
+            Ok(trace) => trace.syscalls,
+            // Containers commonly forbid ptrace
+            Err(e) if e.raw_os_error() == Some(libc::EPERM) => {
+                eprintln!("skipping: ptrace is not permitted here");
+                return;
+            }
+            Err(e) => panic!("{}", e),
+        };
+        assert!(profile.tasks >= 2, "{} tasks", profile.tasks);
+        let calls = |name: &str| {
+            profile
+                .syscalls
+                .iter()
+                .find(|s| s.name == name)
+                .map_or(0, |s| s.calls)
+        };
+        assert!(calls("read") >= 2);
+        assert!(calls("execve") >= 1);
+        assert!(calls("exit_group") >= 2);
+        let stat = &profile.syscalls[0];
+        assert!(stat.histogram.iter().sum::<u64>() <= stat.calls);
+    }
+
+    #[test]
+    fn test_trace_leaves_other_children_alone() {
+        let mut other = std::process::Command::new("true").spawn().unwrap();
+        let tracer = std::thread::spawn(|| {
+            let mut command = std::process::Command::new("sleep");
+            command.arg("0.1");
+            trace(command, |_| {}, None)
+        });
+        let traced = tracer.join().unwrap();
+        // Still there to be reaped by the thread that started it
+        assert!(other.wait().unwrap().success());
+        match traced {
+            Ok(trace) => assert_eq!(trace.syscalls.tasks, 1),
+            Err(e) if e.raw_os_error() == Some(libc::EPERM) => {
+                eprintln!("skipping: ptrace is not permitted here");
+            }
+            Err(e) => panic!("{}", e),
+        }
+    }
+}
@@ -0,0 +1,423 @@
+use crate::linux;
+use crate::units;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::time::Duration;
+
+/// How long throttling of lens's own cgroup is watched for, a few of the
+/// usual 100ms quota periods.
+const THROTTLE_WINDOW: Duration = Duration::from_millis(300);
+
+/// Description of the machine the benchmark ran on, stored with the results.
+/// Fields added after the first release default when reading older results.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SystemInfo {
+    /// Logical CPUs.
+    pub cpus: u32,
+    #[serde(default)]
+    pub physical_cores: u32,
+    #[serde(default)]
+    pub cpu_model: String,
+    pub cpu_speed_mhz: u64,
+    /// Whether simultaneous multithreading (hyper-threading) is active.
+    #[serde(default)]
+    pub smt: Option<bool>,
+    /// The cpufreq scaling governor of the first CPU.
+    #[serde(default)]
+    pub governor: Option<String>,
+    #[serde(default)]
+    pub turbo: Option<bool>,
+    pub memory_total_kb: u64,
+    pub memory_free_kb: u64,
+    /// Memory that can be used without swapping, page cache included.
+    #[serde(default)]
+    pub memory_available_kb: u64,
+    #[serde(default)]
+    pub swap_used_kb: u64,
+    pub disk_total_kb: u64,
+    pub disk_free_kb: u64,
+    pub os: String,
+    pub os_release: String,
+    /// Build string of the kernel, e.g. `#1 SMP PREEMPT_DYNAMIC ...`.
+    #[serde(default)]
+    pub kernel_version: String,
+    #[serde(default)]
+    pub kernel_cmdline: String,
+    /// 1, 5 and 15 minute load averages when the benchmark started.
+    #[serde(default)]
+    pub load_average: [f64; 3],
+    pub processes: u64,
+    /// CPUs worth of time the cgroups lens runs in are allowed.
+    #[serde(default)]
+    pub cgroup_cpu_limit: Option<f64>,
+    #[serde(default)]
+    pub cgroup_memory_limit_kb: Option<u64>,
+    /// Periods the cgroup lens runs in was throttled for using up its CPU
+    /// quota, in a short window as lens started. Only watched with a quota.
+    #[serde(default)]
+    pub cgroup_throttled: Option<u64>,
+    /// Whether the machine runs on battery, `None` when it has none.
+    #[serde(default)]
+    pub on_battery: Option<bool>,
+    /// Container runtime lens appears to run under, e.g. `docker`.
+    #[serde(default)]
+    pub container: Option<String>,
+    /// Hypervisor vendor when running in a virtual machine, `unknown` when the
+    /// CPU reports one but not which.
+    #[serde(default)]
+    pub virtualization: Option<String>,
+}
+
+/// What /proc/cpuinfo says about the processor.
+#[derive(Debug, Default, PartialEq)]
+struct CpuInfo {
+    model: String,
+    physical_cores: Option<u32>,
+    smt: Option<bool>,
+    hypervisor: bool,
+}
+
+impl SystemInfo {
+    pub fn collect() -> Self {
+        let memory = sys_info::mem_info().ok();
+        let (memory_total_kb, memory_free_kb, memory_available_kb) = memory
+            .as_ref()
+            .map(|mem| (mem.total, mem.free, mem.avail))
+            .unwrap_or((0, 0, 0));
+        let (disk_total_kb, disk_free_kb) = sys_info::disk_info()
+            .map(|disk| (disk.total, disk.free))
+            .unwrap_or((0, 0));
+        let cpus = sys_info::cpu_num().unwrap_or(0);
+        let cpu = read("/proc/cpuinfo")
+            .map(|text| parse_cpuinfo(&text))
+            .unwrap_or_default();
+        let cgroup_cpu_limit = linux::cgroup_cpu_limit();
+        let smt = match read("/sys/devices/system/cpu/smt/active").as_deref() {
+            Some("1") => Some(true),
+            Some("0") => Some(false),
+            _ => cpu.smt,
+        };
+
+        SystemInfo {
+            cpus,
+            physical_cores: cpu.physical_cores.unwrap_or(cpus),
+            cpu_model: cpu.model,
+            cpu_speed_mhz: sys_info::cpu_speed().unwrap_or(0),
+            smt,
+            governor: read("/sys/devices/system/cpu/cpu0/cpufreq/scaling_governor"),
+            turbo: turbo(),
+            memory_total_kb,
+            memory_free_kb,
+            memory_available_kb,
+            swap_used_kb: memory
+                .map(|mem| mem.swap_total.saturating_sub(mem.swap_free))
+                .unwrap_or(0),
+            disk_total_kb,
+            disk_free_kb,
+            os: sys_info::os_type().unwrap_or_default(),
+            os_release: sys_info::os_release().unwrap_or_default(),
+            kernel_version: read("/proc/sys/kernel/version").unwrap_or_default(),
+            kernel_cmdline: read("/proc/cmdline").unwrap_or_default(),
+            load_average: read("/proc/loadavg")
+                .and_then(|text| parse_loadavg(&text))
+                .unwrap_or_default(),
+            processes: sys_info::proc_total().unwrap_or(0),
+            cgroup_cpu_limit,
+            cgroup_memory_limit_kb: linux::cgroup_memory_limit().map(|bytes| bytes / 1024),
+            // Without a quota there is nothing to be throttled by, nor to wait for
+            cgroup_throttled: cgroup_cpu_limit
+                .and_then(|_| linux::cgroup_throttled(THROTTLE_WINDOW)),
+            on_battery: on_battery(),
+            container: container(),
+            virtualization: cpu.hypervisor.then(|| {
+                ["/sys/hypervisor/type", "/sys/class/dmi/id/sys_vendor"]
+                    .into_iter()
+                    .find_map(read)
+                    .unwrap_or_else(|| String::from("unknown"))
+            }),
+        }
+    }
+
+    /// Labelled lines describing the machine, for the terminal and HTML reports.
+    pub fn summary(&self) -> Vec<(&'static str, String)> {
+        const GB_CONVERSION: f64 = 1024.0 * 1024.0;
+        let on_off = |on: bool| if on { "on" } else { "off" };
+        let mut rows = vec![("OS", format!("{} {}", self.os, self.os_release))];
+        if !self.kernel_version.is_empty() {
+            rows.push(("Kernel", self.kernel_version.clone()));
+        }
+        if !self.kernel_cmdline.is_empty() {
+            rows.push(("Cmdline", self.kernel_cmdline.clone()));
+        }
+
+        let mut cpu = Vec::new();
+        if !self.cpu_model.is_empty() {
+            cpu.push(self.cpu_model.clone());
+        }
+        cpu.push(format!(
+            "{} physical / {} logical cores at {} MHz",
+            self.physical_cores, self.cpus, self.cpu_speed_mhz
+        ));
+        if let Some(smt) = self.smt {
+            cpu.push(format!("SMT {}", on_off(smt)));
+        }
+        rows.push(("CPU", cpu.join(", ")));
+        let mut frequency = Vec::new();
+        if let Some(governor) = &self.governor {
+            frequency.push(format!("{} governor", governor));
+        }
+        if let Some(turbo) = self.turbo {
+            frequency.push(format!("turbo {}", on_off(turbo)));
+        }
+        if !frequency.is_empty() {
+            rows.push(("Frequency", frequency.join(", ")));
+        }
+
+        if self.memory_total_kb > 0 {
+            let mut memory = format!(
+                "{:.1} GB total, {:.1} GB free",
+                self.memory_total_kb as f64 / GB_CONVERSION,
+                self.memory_free_kb as f64 / GB_CONVERSION
+            );
+            if self.swap_used_kb > 0 {
+                memory.push_str(&format!(
+                    ", {:.1} GB swap used",
+                    self.swap_used_kb as f64 / GB_CONVERSION
+                ));
+            }
+            rows.push(("Memory", memory));
+        }
+        if self.disk_total_kb > 0 {
+            rows.push((
+                "Disk",
+                format!(
+                    "{:.1} GB total, {:.1} GB free",
+                    self.disk_total_kb as f64 / GB_CONVERSION,
+                    self.disk_free_kb as f64 / GB_CONVERSION
+                ),
+            ));
+        }
+        let [one, five, fifteen] = self.load_average;
+        rows.push(("Load", format!("{:.2} {:.2} {:.2}", one, five, fifteen)));
+
+        let mut limits = Vec::new();
+        if let Some(cpus) = self.cgroup_cpu_limit {
+            limits.push(format!("{:.2} CPUs", cpus));
+        }
+        if let Some(memory_kb) = self.cgroup_memory_limit_kb {
+            limits.push(format!(
+                "{} memory",
+                units::format_bytes(memory_kb as f64 * 1024.0)
+            ));
+        }
+        if !limits.is_empty() {
+            rows.push(("Limits", format!("cgroup, {}", limits.join(", "))));
+        }
+        let mut environment = Vec::new();
+        if let Some(container) = &self.container {
+            environment.push(format!("{} container", container));
+        }
+        if let Some(hypervisor) = &self.virtualization {
+            environment.push(format!("virtual machine ({})", hypervisor));
+        }
+        if self.on_battery == Some(true) {
+            environment.push(String::from("on battery"));
+        }
+        if !environment.is_empty() {
+            rows.push(("Environment", environment.join(", ")));
+        }
+        if self.processes > 0 {
+            rows.push(("Processes", self.processes.to_string()));
+        }
+        rows
+    }
+}
+
+fn read(path: impl AsRef<Path>) -> Option<String> {
+    let text = std::fs::read_to_string(path).ok()?;
+    Some(text.trim().to_string()).filter(|text| !text.is_empty())
+}
+
+fn parse_cpuinfo(text: &str) -> CpuInfo {
+    let mut info = CpuInfo::default();
+    let mut cores = Vec::new();
+    let mut physical_id = "";
+    let mut siblings_exceed_cores = None;
+    let mut siblings = None;
+    for line in text.lines() {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        match key.trim() {
+            "model name" | "Processor" if info.model.is_empty() => info.model = value.to_string(),
+            "physical id" => physical_id = value,
+            "core id" => {
+                let core = (physical_id, value);
+                if !cores.contains(&core) {
+                    cores.push(core);
+                }
+            }
+            "siblings" => siblings = value.parse::<u32>().ok(),
+            "cpu cores" => {
+                if let (Some(siblings), Ok(cores)) = (siblings, value.parse::<u32>()) {
+                    siblings_exceed_cores = Some(siblings > cores);
+                }
+            }
+            "flags" => info.hypervisor |= value.split(' ').any(|flag| flag == "hypervisor"),
+            _ => {}
+        }
+    }
+    info.physical_cores = (!cores.is_empty()).then_some(cores.len() as u32);
+    info.smt = siblings_exceed_cores;
+    info
+}
+
+fn parse_loadavg(text: &str) -> Option<[f64; 3]> {
+    let mut fields = text.split_whitespace().map(|field| field.parse().ok());
+    Some([fields.next()??, fields.next()??, fields.next()??])
+}
+
+/// Whether the CPU may boost above its base clock, from intel_pstate or the
+/// generic cpufreq switch.
+fn turbo() -> Option<bool> {
+    if let Some(no_turbo) = read("/sys/devices/system/cpu/intel_pstate/no_turbo") {
+        return Some(no_turbo == "0");
+    }
+    read("/sys/devices/system/cpu/cpufreq/boost").map(|boost| boost == "1")
+}
+
+/// Whether any battery is discharging, `None` on machines without one.
+fn on_battery() -> Option<bool> {
+    let supplies = std::fs::read_dir("/sys/class/power_supply").ok()?;
+    let mut discharging = None;
+    for supply in supplies.flatten() {
+        let path = supply.path();
+        if read(path.join("type")).as_deref() == Some("Battery") {
+            let status = read(path.join("status"));
+            *discharging.get_or_insert(false) |= status.as_deref() == Some("Discharging");
+        }
+    }
+    discharging
+}
+
+/// The container runtime lens runs under, from the marker files runtimes
+/// leave behind or the cgroup of the init process.
+fn container() -> Option<String> {
+    if Path::new("/.dockerenv").exists() {
+        return Some(String::from("docker"));
+    }
+    if Path::new("/run/.containerenv").exists() {
+        return Some(String::from("podman"));
+    }
+    // systemd-nspawn and LXC set it for init
+    if let Ok(environ) = std::fs::read("/proc/1/environ") {
+        let runtime = environ
+            .split(|&byte| byte == 0)
+            .find_map(|entry| entry.strip_prefix(b"container="));
+        if let Some(runtime) = runtime {
+            return Some(String::from_utf8_lossy(runtime).into_owned());
+        }
+    }
+    let cgroup = read("/proc/1/cgroup")?;
+    ["kubepods", "docker", "lxc"]
+        .into_iter()
+        .find(|runtime| cgroup.contains(runtime))
+        .map(|runtime| match runtime {
+            "kubepods" => String::from("kubernetes"),
+            runtime => runtime.to_string(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collect() {
+        let info = SystemInfo::collect();
+        assert!(info.cpus > 0);
+        assert!(info.physical_cores > 0 && info.physical_cores <= info.cpus);
+        assert!(info.memory_total_kb > 0);
+        assert_eq!(info.os, "Linux");
+        assert!(!info.kernel_version.is_empty());
+    }
+
+    #[test]
+    fn test_summary() {
+        let mut info = SystemInfo::collect();
+        info.cgroup_cpu_limit = Some(2.0);
+        info.cgroup_memory_limit_kb = Some(512 * 1024);
+        info.container = Some(String::from("docker"));
+        info.virtualization = None;
+        let rows = info.summary();
+        let row = |name| {
+            rows.iter()
+                .find(|(n, _)| *n == name)
+                .map(|(_, v)| v.as_str())
+        };
+        assert_eq!(row("Limits"), Some("cgroup, 2.00 CPUs, 512.0 MB memory"));
+        assert_eq!(row("Environment"), Some("docker container"));
+        assert!(row("CPU").unwrap().contains("logical cores"));
+    }
+
+    #[test]
+    fn test_parse_cpuinfo() {
+        let text = "\
+processor\t: 0
+model name\t: Example CPU @ 3.00GHz
+physical id\t: 0
+siblings\t: 4
+core id\t\t: 0
+cpu cores\t: 2
+flags\t\t: fpu vme hypervisor
+
+processor\t: 1
+model name\t: Example CPU @ 3.00GHz
+physical id\t: 0
+siblings\t: 4
+core id\t\t: 0
+cpu cores\t: 2
+
+processor\t: 2
+physical id\t: 0
+core id\t\t: 1
+
+processor\t: 3
+physical id\t: 0
+core id\t\t: 1
+";
+        assert_eq!(
+            parse_cpuinfo(text),
+            CpuInfo {
+                model: String::from("Example CPU @ 3.00GHz"),
+                physical_cores: Some(2),
+                smt: Some(true),
+                hypervisor: true,
+            }
+        );
+        assert_eq!(parse_cpuinfo(""), CpuInfo::default());
+    }
+
+    #[test]
+    fn test_parse_loadavg() {
+        assert_eq!(
+            parse_loadavg("0.58 0.41 0.32 2/72 32484\n"),
+            Some([0.58, 0.41, 0.32])
+        );
+        assert_eq!(parse_loadavg("garbage"), None);
+    }
+
+    #[test]
+    fn test_deserialize_older_results() {
+        let info: SystemInfo = serde_json::from_str(
+            r#"{"cpus": 4, "cpu_speed_mhz": 2000, "memory_total_kb": 1, "memory_free_kb": 1,
+                "disk_total_kb": 1, "disk_free_kb": 1, "os": "Linux", "os_release": "6.1",
+                "processes": 10}"#,
+        )
+        .unwrap();
+        assert_eq!(info.physical_cores, 0);
+        assert_eq!(info.smt, None);
+        assert_eq!(info.load_average, [0.0; 3]);
+    }
+}
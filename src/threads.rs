@@ -0,0 +1,313 @@
+use crate::process::ProcessInfo;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// CPU time of the threads sharing a name, such as a runtime's GC or worker
+/// threads, averaged over iterations.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ThreadStat {
+    pub name: String,
+    /// Most threads with this name seen in one iteration.
+    pub threads: usize,
+    pub user_ticks: f64,
+    pub system_ticks: f64,
+}
+
+impl ThreadStat {
+    /// One entry per thread name across the iterations, most CPU time first.
+    /// Only the command's own process is looked at, not what it starts.
+    pub fn aggregate(processes: &[ProcessInfo]) -> Vec<ThreadStat> {
+        let mut by_name: BTreeMap<&str, ThreadStat> = BTreeMap::new();
+        for process in processes {
+            let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+            for thread in process.stat.threads.values() {
+                let stat = by_name.entry(&thread.name).or_insert_with(|| ThreadStat {
+                    name: thread.name.clone(),
+                    threads: 0,
+                    user_ticks: 0.0,
+                    system_ticks: 0.0,
+                });
+                stat.user_ticks += thread.utime as f64;
+                stat.system_ticks += thread.stime as f64;
+                *counts.entry(&thread.name).or_default() += 1;
+            }
+            for (name, count) in counts {
+                let stat = by_name.get_mut(name).unwrap();
+                stat.threads = stat.threads.max(count);
+            }
+        }
+        let iterations = processes.len().max(1) as f64;
+        let mut stats: Vec<ThreadStat> = by_name
+            .into_values()
+            .map(|stat| ThreadStat {
+                user_ticks: stat.user_ticks / iterations,
+                system_ticks: stat.system_ticks / iterations,
+                ..stat
+            })
+            .collect();
+        stats.sort_by(|a, b| b.ticks().total_cmp(&a.ticks()));
+        stats
+    }
+
+    pub fn ticks(&self) -> f64 {
+        self.user_ticks + self.system_ticks
+    }
+}
+
+/// What a sampled thread was doing, from its state and wait channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WaitState {
+    /// On a CPU or ready to run.
+    Running,
+    /// In uninterruptible sleep, which is mostly waiting on the disk.
+    Disk,
+    /// Waiting for a socket, pipe or terminal in a read, poll or select.
+    Io,
+    /// Waiting on a futex, which is how userspace locks and condition variables block.
+    Lock,
+    /// Any other sleep, such as a timer or waiting for a child to exit.
+    Sleeping,
+}
+
+impl WaitState {
+    /// Classifies a thread by its state letter in /proc and the kernel
+    /// function it is blocked in.
+    pub(crate) fn classify(state: char, wchan: &str) -> Self {
+        const IO_WAITS: [&str; 9] = [
+            "poll",
+            "select",
+            "sk_wait",
+            "tcp_",
+            "unix_",
+            "inet_",
+            "pipe_",
+            "wait_woken",
+            "tty",
+        ];
+        match state {
+            'R' => WaitState::Running,
+            'D' => WaitState::Disk,
+            _ if wchan.contains("futex") => WaitState::Lock,
+            _ if IO_WAITS.iter().any(|io| wchan.contains(io)) => WaitState::Io,
+            _ => WaitState::Sleeping,
+        }
+    }
+}
+
+impl std::fmt::Display for WaitState {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            WaitState::Running => write!(f, "running"),
+            WaitState::Disk => write!(f, "disk"),
+            WaitState::Io => write!(f, "I/O wait"),
+            WaitState::Lock => write!(f, "lock wait"),
+            WaitState::Sleeping => write!(f, "sleeping"),
+        }
+    }
+}
+
+/// Wait channels kept per thread name in a [`WaitStat`].
+const WAIT_CHANNELS_KEPT: usize = 10;
+
+/// A kernel function threads were found blocked in.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WaitChannel {
+    pub name: String,
+    pub state: WaitState,
+    /// Fraction of the samples of the threads with this name.
+    pub share: f64,
+}
+
+/// How the threads sharing a name spent the wall time. Shares are of their own
+/// samples, so a pool of parked workers does not dilute a busy main thread.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ThreadWaits {
+    pub name: String,
+    /// Samples of threads with this name over all iterations.
+    pub samples: u64,
+    /// Fractions of the samples, adding up to 1. For a single thread the
+    /// shares are of wall time.
+    pub running: f64,
+    pub disk: f64,
+    pub io: f64,
+    pub lock: f64,
+    pub sleeping: f64,
+    /// Where the threads were blocked most often, most first.
+    pub wait_channels: Vec<WaitChannel>,
+}
+
+/// How the command's threads spent the wall time, from sampling the state of
+/// each one over the runs. Only the command's own process is sampled, not
+/// the processes it starts.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WaitStat {
+    /// Thread samples over all iterations.
+    pub samples: u64,
+    /// One entry per thread name, most samples first.
+    pub threads: Vec<ThreadWaits>,
+}
+
+impl WaitStat {
+    /// Pools the samples of all iterations by thread name, or None if none
+    /// were taken.
+    pub fn aggregate(processes: &[ProcessInfo]) -> Option<WaitStat> {
+        let mut by_name: BTreeMap<&str, BTreeMap<(WaitState, &str), u64>> = BTreeMap::new();
+        for process in processes {
+            for ((name, state, wchan), count) in &process.stat.wait_samples {
+                *by_name
+                    .entry(name)
+                    .or_default()
+                    .entry((*state, wchan))
+                    .or_default() += count;
+            }
+        }
+        let mut threads: Vec<ThreadWaits> = by_name
+            .into_iter()
+            .map(|(name, samples)| ThreadWaits::new(name, &samples))
+            .collect();
+        let total: u64 = threads.iter().map(|thread| thread.samples).sum();
+        if total == 0 {
+            return None;
+        }
+        threads.sort_by_key(|thread| std::cmp::Reverse(thread.samples));
+        Some(WaitStat {
+            samples: total,
+            threads,
+        })
+    }
+}
+
+impl ThreadWaits {
+    fn new(name: &str, samples: &BTreeMap<(WaitState, &str), u64>) -> Self {
+        let total: u64 = samples.values().sum();
+        let share = |state: WaitState| {
+            let count: u64 = samples
+                .iter()
+                .filter(|((s, _), _)| *s == state)
+                .map(|(_, count)| count)
+                .sum();
+            count as f64 / total as f64
+        };
+        let mut wait_channels: Vec<WaitChannel> = samples
+            .iter()
+            .filter(|((_, wchan), _)| !wchan.is_empty())
+            .map(|(&(state, wchan), &count)| WaitChannel {
+                name: wchan.to_string(),
+                state,
+                share: count as f64 / total as f64,
+            })
+            .collect();
+        wait_channels.sort_by(|a, b| b.share.total_cmp(&a.share));
+        wait_channels.truncate(WAIT_CHANNELS_KEPT);
+        ThreadWaits {
+            name: name.to_string(),
+            samples: total,
+            running: share(WaitState::Running),
+            disk: share(WaitState::Disk),
+            io: share(WaitState::Io),
+            lock: share(WaitState::Lock),
+            sleeping: share(WaitState::Sleeping),
+            wait_channels,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::ThreadTicks;
+
+    fn process(threads: &[(u32, &str, u64)]) -> ProcessInfo {
+        let mut process = ProcessInfo::new(String::from("java"), vec![]);
+        for &(tid, name, utime) in threads {
+            let ticks = ThreadTicks {
+                name: name.to_string(),
+                utime,
+                stime: 1,
+            };
+            process.stat.threads.insert(tid, ticks);
+        }
+        process
+    }
+
+    #[test]
+    fn test_aggregate_by_name() {
+        let processes = [
+            process(&[(1, "java", 10), (2, "GC Thread", 30), (3, "GC Thread", 20)]),
+            process(&[(7, "java", 20), (8, "GC Thread", 40)]),
+        ];
+        let stats = ThreadStat::aggregate(&processes);
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[0].name, "GC Thread");
+        assert_eq!(stats[0].threads, 2);
+        assert_eq!(stats[0].user_ticks, 45.0);
+        assert_eq!(stats[0].system_ticks, 1.5);
+        assert_eq!(stats[1].name, "java");
+        assert_eq!(stats[1].threads, 1);
+        assert_eq!(stats[1].ticks(), 16.0);
+    }
+
+    #[test]
+    fn test_aggregate_nothing() {
+        assert!(ThreadStat::aggregate(&[]).is_empty());
+        assert_eq!(WaitStat::aggregate(&[]), None);
+    }
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(WaitState::classify('R', ""), WaitState::Running);
+        assert_eq!(WaitState::classify('D', "io_schedule"), WaitState::Disk);
+        assert_eq!(WaitState::classify('S', "futex_do_wait"), WaitState::Lock);
+        assert_eq!(WaitState::classify('S', "do_epoll_wait"), WaitState::Io);
+        assert_eq!(WaitState::classify('S', "pipe_read"), WaitState::Io);
+        assert_eq!(
+            WaitState::classify('S', "hrtimer_nanosleep"),
+            WaitState::Sleeping
+        );
+        assert_eq!(WaitState::classify('S', ""), WaitState::Sleeping);
+    }
+
+    #[test]
+    fn test_aggregate_waits() {
+        let mut first = process(&[]);
+        let mut second = process(&[]);
+        let sample = |process: &mut ProcessInfo, name: &str, state, wchan: &str, count| {
+            let key = (name.to_string(), state, wchan.to_string());
+            process.stat.wait_samples.insert(key, count);
+        };
+        sample(&mut first, "db", WaitState::Running, "", 2);
+        sample(&mut first, "db", WaitState::Disk, "io_schedule", 3);
+        sample(&mut second, "db", WaitState::Disk, "io_schedule", 1);
+        sample(&mut second, "db", WaitState::Lock, "futex_do_wait", 2);
+        let waits = WaitStat::aggregate(&[first, second]).unwrap();
+        assert_eq!(waits.samples, 8);
+        let db = &waits.threads[0];
+        assert_eq!(db.running, 0.25);
+        assert_eq!(db.disk, 0.5);
+        assert_eq!(db.lock, 0.25);
+        assert_eq!(db.io + db.sleeping, 0.0);
+        assert_eq!(db.wait_channels.len(), 2);
+        assert_eq!(db.wait_channels[0].name, "io_schedule");
+        assert_eq!(db.wait_channels[0].state, WaitState::Disk);
+        assert_eq!(db.wait_channels[0].share, 0.5);
+    }
+
+    #[test]
+    fn test_parked_workers_do_not_dilute_busy_thread() {
+        let mut pool = process(&[]);
+        let mut sample = |name: &str, state, wchan: &str, count| {
+            let key = (name.to_string(), state, wchan.to_string());
+            pool.stat.wait_samples.insert(key, count);
+        };
+        sample("main", WaitState::Running, "", 10);
+        sample("worker", WaitState::Lock, "futex_do_wait", 80);
+        let waits = WaitStat::aggregate(&[pool]).unwrap();
+        assert_eq!(waits.samples, 90);
+        assert_eq!(waits.threads[0].name, "worker");
+        assert_eq!(waits.threads[0].lock, 1.0);
+        assert_eq!(waits.threads[1].name, "main");
+        assert_eq!(waits.threads[1].running, 1.0);
+        assert_eq!(waits.threads[1].lock, 0.0);
+    }
+}
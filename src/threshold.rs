@@ -0,0 +1,271 @@
+use crate::baseline;
+use crate::linux::clock_ticks_per_sec;
+use crate::report::CommandReport;
+use crate::units::format_bytes;
+use std::time::Duration;
+
+/// Limits a benchmark must stay within, checked after the runs complete.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Thresholds {
+    /// Upper bound on the average wall time.
+    pub max_time: Option<Duration>,
+    /// Upper bound on the peak RSS of the command or a child it waited for, in bytes.
+    pub max_rss: Option<u64>,
+    /// Largest allowed wall time increase relative to the baseline, as a fraction.
+    pub max_regression: Option<f64>,
+    /// Upper bound on the average user + system CPU time.
+    pub max_cpu_time: Option<Duration>,
+}
+
+/// A threshold a command failed to meet.
+#[derive(Debug, PartialEq)]
+pub struct Violation {
+    pub command: String,
+    pub option: &'static str,
+    pub metric: &'static str,
+    pub actual: String,
+    pub limit: String,
+    /// How far past the limit the measurement went, relative to the limit.
+    pub excess: f64,
+}
+
+impl std::fmt::Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {} {} exceeds {} {} by {:.1}%",
+            self.command,
+            self.metric,
+            self.actual,
+            self.option,
+            self.limit,
+            self.excess * 100.0
+        )
+    }
+}
+
+impl Thresholds {
+    /// Each limit from `self`, falling back to `other` where `self` has none.
+    pub fn or(self, other: Thresholds) -> Thresholds {
+        Thresholds {
+            max_time: self.max_time.or(other.max_time),
+            max_rss: self.max_rss.or(other.max_rss),
+            max_regression: self.max_regression.or(other.max_regression),
+            max_cpu_time: self.max_cpu_time.or(other.max_cpu_time),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Thresholds::default()
+    }
+
+    pub fn check(
+        &self,
+        current: &CommandReport,
+        baseline: Option<&CommandReport>,
+    ) -> Vec<Violation> {
+        let summary = &current.summary;
+        let mut violations = Vec::new();
+        let mut violation = |option, metric, actual: f64, limit: f64, format: fn(f64) -> String| {
+            if actual > limit {
+                violations.push(Violation {
+                    command: current.name.clone(),
+                    option,
+                    metric,
+                    actual: format(actual),
+                    limit: format(limit),
+                    excess: if limit > 0.0 {
+                        (actual - limit) / limit
+                    } else {
+                        f64::INFINITY
+                    },
+                });
+            }
+        };
+
+        if let Some(max_time) = self.max_time {
+            violation(
+                "--max-time",
+                "average wall time",
+                summary.average_duration,
+                max_time.as_secs_f64(),
+                |v| format!("{:.3} s", v),
+            );
+        }
+        if let Some(max_rss) = self.max_rss {
+            violation(
+                "--max-rss",
+                "peak RSS",
+                summary.max_memory * 1024.0,
+                max_rss as f64,
+                format_bytes,
+            );
+        }
+        if let Some(max_cpu_time) = self.max_cpu_time {
+            let cpu_time =
+                (summary.average_utime + summary.average_stime) / clock_ticks_per_sec() as f64;
+            violation(
+                "--max-cpu-time",
+                "average CPU time",
+                cpu_time,
+                max_cpu_time.as_secs_f64(),
+                |v| format!("{:.3} s", v),
+            );
+        }
+        if let (Some(max_regression), Some(baseline)) = (self.max_regression, baseline) {
+            let wall_time = baseline::compare(baseline, current)
+                .into_iter()
+                .find(|c| c.metric == "Wall time");
+            // Gated on the change itself, so noise can't hide a large regression,
+            // but one the t-test can't tell apart from noise says so
+            if let Some(wall_time) = wall_time {
+                let metric = if wall_time.p_value.is_none() || wall_time.significant() {
+                    "wall time regression"
+                } else {
+                    "wall time regression (not statistically significant)"
+                };
+                violation(
+                    "--max-regression",
+                    metric,
+                    wall_time.change(),
+                    max_regression,
+                    |v| format!("{:.1}%", v * 100.0),
+                );
+            }
+        }
+        violations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::benchmark::Benchmark;
+    use crate::process::{ProcessInfo, Sample};
+    use crate::report::Report;
+    use crate::system::SystemInfo;
+
+    fn create_command(wall_times_ms: &[u64], rss_kb: u64, cpu_ticks: u64) -> CommandReport {
+        let processes: Vec<ProcessInfo> = wall_times_ms
+            .iter()
+            .map(|&ms| {
+                let mut process = ProcessInfo::new(String::from("ls"), vec![]);
+                process.duration = Some(Duration::from_millis(ms));
+                process.stat.utime = cpu_ticks;
+                process.stat.total_time = cpu_ticks;
+                process.stat.memory_kb = vec![Sample::new(Duration::ZERO, rss_kb)];
+                process
+            })
+            .collect();
+        let stats: Vec<_> = processes.iter().map(Benchmark::calculate).collect();
+        let mut report = Report::new(SystemInfo::collect());
+        report.push(
+            String::from("ls"),
+            String::from("ls"),
+            &processes,
+            Benchmark::average_stat(&stats),
+        );
+        report.commands.remove(0)
+    }
+
+    #[test]
+    fn test_no_thresholds_no_violations() {
+        let command = create_command(&[1000], 1024, 100);
+        assert!(Thresholds::default().is_empty());
+        assert!(Thresholds::default().check(&command, None).is_empty());
+    }
+
+    #[test]
+    fn test_or_prefers_self() {
+        let cli = Thresholds {
+            max_time: Some(Duration::from_secs(1)),
+            ..Default::default()
+        };
+        let suite = Thresholds {
+            max_time: Some(Duration::from_secs(5)),
+            max_rss: Some(1024),
+            ..Default::default()
+        };
+        let merged = cli.or(suite);
+        assert_eq!(merged.max_time, Some(Duration::from_secs(1)));
+        assert_eq!(merged.max_rss, Some(1024));
+    }
+
+    #[test]
+    fn test_max_time() {
+        let command = create_command(&[2000, 3000], 1024, 0);
+        let thresholds = Thresholds {
+            max_time: Some(Duration::from_secs(2)),
+            ..Default::default()
+        };
+        let violations = thresholds.check(&command, None);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].option, "--max-time");
+        assert!((violations[0].excess - 0.25).abs() < 1e-9);
+        assert_eq!(
+            violations[0].to_string(),
+            "ls: average wall time 2.500 s exceeds --max-time 2.000 s by 25.0%"
+        );
+    }
+
+    #[test]
+    fn test_max_rss_and_cpu_time() {
+        let command = create_command(&[100], 600 * 1024, 250);
+        let thresholds = Thresholds {
+            max_rss: Some(512 << 20),
+            max_cpu_time: Some(Duration::from_secs(3)),
+            ..Default::default()
+        };
+        let violations = thresholds.check(&command, None);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].option, "--max-rss");
+        assert_eq!(violations[0].actual, "600.0 MB");
+
+        let thresholds = Thresholds {
+            max_cpu_time: Some(Duration::from_secs(2)),
+            ..Default::default()
+        };
+        let violations = thresholds.check(&command, None);
+        assert_eq!(violations[0].option, "--max-cpu-time");
+    }
+
+    #[test]
+    fn test_max_regression() {
+        let baseline = create_command(&[100, 101, 99, 100], 1024, 0);
+        let slower = create_command(&[110, 111, 109, 110], 1024, 0);
+        let thresholds = Thresholds {
+            max_regression: Some(0.05),
+            ..Default::default()
+        };
+        let violations = thresholds.check(&slower, Some(&baseline));
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].metric, "wall time regression");
+        assert_eq!(violations[0].actual, "10.0%");
+
+        // Without a baseline there is nothing to regress against
+        assert!(thresholds.check(&slower, None).is_empty());
+
+        // Within the allowed margin
+        let thresholds = Thresholds {
+            max_regression: Some(0.15),
+            ..Default::default()
+        };
+        assert!(thresholds.check(&slower, Some(&baseline)).is_empty());
+    }
+
+    #[test]
+    fn test_noisy_regression_still_fails() {
+        let baseline = create_command(&[100, 140, 60, 120, 80], 1024, 0);
+        let noisy = create_command(&[110, 150, 70, 130, 90], 1024, 0);
+        let thresholds = Thresholds {
+            max_regression: Some(0.05),
+            ..Default::default()
+        };
+        let violations = thresholds.check(&noisy, Some(&baseline));
+        assert_eq!(violations.len(), 1);
+        assert_eq!(
+            violations[0].metric,
+            "wall time regression (not statistically significant)"
+        );
+    }
+}
@@ -0,0 +1,124 @@
+use std::time::Duration;
+
+/// Parses a duration such as `2.5s`, `300ms` or `1m`. A bare number is seconds.
+pub fn parse_duration(text: &str) -> Result<Duration, String> {
+    let (number, unit) = split_number(text)?;
+    let scale = match unit.to_ascii_lowercase().as_str() {
+        "ns" => 1e-9,
+        "us" | "µs" => 1e-6,
+        "ms" => 1e-3,
+        "" | "s" | "sec" | "secs" => 1.0,
+        "m" | "min" | "mins" => 60.0,
+        "h" => 3600.0,
+        _ => return Err(format!("unknown time unit '{}' in '{}'", unit, text)),
+    };
+    Duration::try_from_secs_f64(number * scale)
+        .map_err(|e| format!("invalid duration '{}': {}", text, e))
+}
+
+/// Parses a size such as `512MB`, `1.5G` or `64KiB` into bytes. Suffixes are
+/// binary multiples, matching how container memory limits are usually written.
+pub fn parse_bytes(text: &str) -> Result<u64, String> {
+    let (number, unit) = split_number(text)?;
+    let scale: u64 = match unit.to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        "T" | "TB" | "TIB" => 1 << 40,
+        _ => return Err(format!("unknown size unit in '{}'", text)),
+    };
+    Ok((number * scale as f64).round() as u64)
+}
+
+/// Parses a percentage such as `5%` or `5` into a fraction (0.05).
+pub fn parse_percent(text: &str) -> Result<f64, String> {
+    let (number, unit) = split_number(text)?;
+    if !unit.is_empty() && unit != "%" {
+        return Err(format!("expected a percentage like '5%', got '{}'", text));
+    }
+    Ok(number / 100.0)
+}
+
+/// Short label for a latency in microseconds, e.g. `10us`, `1ms` or `1s`.
+pub fn format_usec(usec: u64) -> String {
+    match usec {
+        usec if usec >= 1_000_000 && usec % 1_000_000 == 0 => format!("{}s", usec / 1_000_000),
+        usec if usec >= 1_000 && usec % 1_000 == 0 => format!("{}ms", usec / 1_000),
+        usec => format!("{}us", usec),
+    }
+}
+
+/// Human readable size for messages, e.g. `512.0 MB`.
+pub fn format_bytes(bytes: f64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut value = bytes;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+fn split_number(text: &str) -> Result<(f64, &str), String> {
+    let text = text.trim();
+    let end = text
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(end);
+    let number = number
+        .parse::<f64>()
+        .map_err(|_| format!("expected a number in '{}'", text))?;
+    Ok((number, unit.trim()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("2.5s"), Ok(Duration::from_millis(2500)));
+        assert_eq!(parse_duration("300ms"), Ok(Duration::from_millis(300)));
+        assert_eq!(parse_duration("1m"), Ok(Duration::from_secs(60)));
+        assert_eq!(parse_duration("3"), Ok(Duration::from_secs(3)));
+        assert!(parse_duration("3 fortnights").is_err());
+        assert!(parse_duration("fast").is_err());
+    }
+
+    #[test]
+    fn test_parse_bytes() {
+        assert_eq!(parse_bytes("512MB"), Ok(512 << 20));
+        assert_eq!(parse_bytes("512M"), Ok(512 << 20));
+        assert_eq!(parse_bytes("64KiB"), Ok(64 << 10));
+        assert_eq!(parse_bytes("1.5G"), Ok(3 << 29));
+        assert_eq!(parse_bytes("100"), Ok(100));
+        assert_eq!(parse_bytes("2b"), Ok(2));
+        assert!(parse_bytes("12 parsecs").is_err());
+        assert!(parse_bytes("10IB").is_err());
+        assert!(parse_bytes("10MBB").is_err());
+        assert!(parse_bytes("10Mi").is_err());
+    }
+
+    #[test]
+    fn test_parse_percent() {
+        assert_eq!(parse_percent("5%"), Ok(0.05));
+        assert_eq!(parse_percent("12.5"), Ok(0.125));
+        assert!(parse_percent("5x").is_err());
+    }
+
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!(format_bytes(512.0), "512.0 B");
+        assert_eq!(format_bytes((512u64 << 20) as f64), "512.0 MB");
+    }
+
+    #[test]
+    fn test_format_usec() {
+        assert_eq!(format_usec(10), "10us");
+        assert_eq!(format_usec(100_000), "100ms");
+        assert_eq!(format_usec(2_000_000), "2s");
+        assert_eq!(format_usec(1_500), "1500us");
+    }
+}
@@ -0,0 +1,114 @@
+use lens::{Benchmark, MonitorKind, OutputMode};
+use std::path::Path;
+use std::time::Duration;
+
+#[tokio::test]
+async fn test_benchmark_command() {
+    let report = Benchmark::command("sleep 0.05")
+        .name("nap")
+        .warmup(1)
+        .iterations(3)
+        .run()
+        .await
+        .unwrap();
+
+    assert_eq!(report.commands.len(), 1);
+    let command = &report.commands[0];
+    assert_eq!(command.name, "nap");
+    assert_eq!(command.command, "sleep 0.05");
+    assert_eq!(command.iterations.len(), 3);
+    assert!(command.summary.min_duration >= 0.05);
+    assert!(command.iterations.iter().all(|i| i.exit_status == Some(0)));
+}
+
+#[tokio::test]
+async fn test_benchmark_env_and_output() {
+    let report = Benchmark::command("sh -c 'test \"$LENS_TEST\" = 42'")
+        .env("LENS_TEST", "42")
+        .output(OutputMode::Pipe)
+        .timeout(Duration::from_secs(5))
+        .iterations(1)
+        .run()
+        .await
+        .unwrap();
+    assert_eq!(report.commands[0].iterations[0].exit_status, Some(0));
+}
+
+#[tokio::test]
+async fn test_benchmark_unknown_command_fails() {
+    let result = Benchmark::command("nonexistent_command_12345")
+        .iterations(2)
+        .run()
+        .await;
+    assert!(result.is_err());
+}
+
+/// Whether this process may create cgroups below its own cgroup v2 group. The
+/// v2 hierarchy is at /sys/fs/cgroup, or below it on hybrid v1/v2 hosts.
+fn cgroups_writable() -> bool {
+    let Ok(cgroups) = std::fs::read_to_string("/proc/self/cgroup") else {
+        return false;
+    };
+    let Some(own) = cgroups.lines().find_map(|line| line.strip_prefix("0::")) else {
+        return false;
+    };
+    let Some(root) = ["/sys/fs/cgroup", "/sys/fs/cgroup/unified"]
+        .into_iter()
+        .find(|root| Path::new(root).join("cgroup.controllers").exists())
+    else {
+        return false;
+    };
+    let probe = format!("{}{}/lens-probe-{}", root, own, std::process::id());
+    std::fs::create_dir(&probe).is_ok() && std::fs::remove_dir(&probe).is_ok()
+}
+
+#[tokio::test]
+async fn test_benchmark_cgroup_monitor() {
+    if !cgroups_writable() {
+        eprintln!("skipping: no writable cgroup v2 hierarchy");
+        return;
+    }
+    let report = Benchmark::command("sh -c 'for i in 1 2 3; do sh -c true; done'")
+        .monitor(MonitorKind::Cgroup)
+        .iterations(2)
+        .run()
+        .await
+        .unwrap();
+    assert_eq!(report.commands[0].iterations.len(), 2);
+}
+
+#[tokio::test]
+async fn test_benchmark_runs_on_spawned_task() {
+    let task = tokio::spawn(Benchmark::command("true").iterations(2).run());
+    let report = task.await.unwrap().unwrap();
+    assert_eq!(report.commands[0].iterations.len(), 2);
+}
+
+#[tokio::test]
+async fn test_benchmark_concurrency() {
+    let report = Benchmark::command("sleep 0.05")
+        .iterations(2)
+        .concurrency(3)
+        .run()
+        .await
+        .unwrap();
+    let command = &report.commands[0];
+    assert_eq!(command.iterations.len(), 6);
+    let concurrency = command.concurrency.as_ref().unwrap();
+    assert_eq!(concurrency.concurrency, 3);
+    assert_eq!(concurrency.batches, 2);
+    assert!(concurrency.throughput > 0.0);
+}
+
+#[tokio::test]
+async fn test_benchmark_time_budget() {
+    let report = Benchmark::command("sleep 0.02")
+        .iterations(2)
+        .time_budget(Duration::from_millis(150))
+        .max_iterations(20)
+        .run()
+        .await
+        .unwrap();
+    let runs = report.commands[0].iterations.len();
+    assert!((5..20).contains(&runs), "{} runs", runs);
+}